@@ -1,6 +1,73 @@
 use std::net::IpAddr;
+use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
+use regex::Regex;
+
+use crate::net::error::ErrorPage;
+use crate::net::rewrite::RewriteRules;
+use crate::net::route::HeaderRoute;
+
+/// The body format of responses generated by the proxy itself
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+/// What to record instead of a requester IP with `--anonymize-ips`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnonymizeIps {
+    Hash,
+    Truncate,
+}
+
+/// The syslog facility access logs are sent to with `--syslog`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFacility {
+    User = 1,
+    Daemon = 3,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// How to canonicalize a trailing slash on request paths
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    Strip,
+    Add,
+}
+
+/// How to order the endpoints of the interval table
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// The busiest first
+    Requests,
+    /// The slowest first, by estimated p95 latency
+    P95,
+}
+
+/// What to do with the options, which may go before or after the subcommand
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Run the proxy, the default without a subcommand
+    Run,
+
+    /// Check the options for problems such as malformed values, missing files or a taken port,
+    /// report all of them and exit without starting the proxy
+    Validate,
+
+    /// Print the histograms a running proxy recorded so far this interval, read from its admin
+    /// API at `--admin-addr`, and exit
+    Stats,
+}
 
 #[derive(Parser, Debug, Clone)]
 #[clap(
@@ -10,37 +77,688 @@ use clap::Parser;
     long_about = "An observation tool to better monitor and secure your web traffic."
 )]
 pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// The port number to run the proxy server on
-    #[clap(short, long, default_value = "8000")]
+    #[clap(short, long, default_value = "8000", global = true)]
     pub proxy: u16,
 
     /// The interval in seconds to print the histograms
-    #[clap(short, long, default_value = "60")]
+    #[clap(short, long, default_value = "60", global = true)]
     pub interval: u64,
 
     /// The host of the target server
-    #[clap(short = 'H', long, default_value = "localhost")]
+    #[clap(short = 'H', long, default_value = "localhost", global = true)]
     pub host: String,
 
     /// The port of the target server
-    #[clap(short = 'P', long, default_value = "3000")]
+    #[clap(short = 'P', long, default_value = "3000", global = true)]
     pub port: u16,
 
     /// Blacklisted IP addresses (comma-separated)
-    #[clap(short, long, use_value_delimiter = true, value_delimiter = ',')]
+    #[clap(short, long, use_value_delimiter = true, value_delimiter = ',', global = true)]
     pub blacklist: Vec<IpAddr>,
 
     /// Whether to send the histograms to a monitoring server
-    #[clap(short, long, default_value = "false")]
+    #[clap(short, long, default_value = "false", global = true)]
     pub monitoring: bool,
 
     /// The host of the monitoring server
-    #[clap(short, long, default_value = "https://monitoring.narrow.so")]
+    #[clap(short, long, default_value = "https://monitoring.narrow.so", global = true)]
     pub server: String,
 
     /// The key to authenticate with the monitoring server
-    #[clap(short, long, default_value = "")]
+    #[clap(short, long, default_value = "", global = true)]
     pub key: String,
+
+    /// Directory to serve static files from instead of forwarding matching paths
+    #[clap(long, global = true)]
+    pub static_dir: Option<PathBuf>,
+
+    /// Path prefixes served from the static directory (comma-separated)
+    #[clap(
+        long,
+        default_value = "/static",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        global = true
+    )]
+    pub static_prefix: Vec<String>,
+
+    /// Request header whose value is added to the histogram key (e.g. X-Tenant-Id)
+    #[clap(long, global = true)]
+    pub group_by_header: Option<String>,
+
+    /// The maximum number of distinct histogram endpoints, the rest are counted as "Other" (0 for
+    /// no limit)
+    #[clap(long, default_value = "1000", global = true)]
+    pub max_endpoints: usize,
+
+    /// Path prefix prepended to every request forwarded to the target server
+    #[clap(long, global = true)]
+    pub upstream_path_prefix: Option<String>,
+
+    /// Client-facing path prefix removed before forwarding to the target server
+    #[clap(long, global = true)]
+    pub strip_prefix: Option<String>,
+
+    /// The body format of errors generated by the proxy (upstream responses are untouched)
+    #[clap(long, value_enum, default_value = "text", global = true)]
+    pub error_format: ErrorFormat,
+
+    /// URL of a newline-delimited IP/CIDR list to add to the blacklist (http only)
+    #[clap(long, global = true)]
+    pub blacklist_url: Option<String>,
+
+    /// The interval in seconds to refresh the blacklist from the blacklist URL
+    #[clap(long, default_value = "300", value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    pub blacklist_refresh: u64,
+
+    /// Disable highlighting of slow or failing endpoints in the histogram table
+    #[clap(long, default_value = "false", global = true)]
+    pub no_color: bool,
+
+    /// Highlight endpoints whose estimated p95 latency exceeds this many milliseconds
+    #[clap(long, default_value = "500", global = true)]
+    pub highlight_p95_ms: f64,
+
+    /// Highlight endpoints whose share of 5xx responses exceeds this ratio
+    #[clap(long, default_value = "0.05", global = true)]
+    pub highlight_error_ratio: f64,
+
+    /// The timeout in milliseconds for requests to the target server (0 for no timeout)
+    #[clap(long, default_value = "0", global = true)]
+    pub timeout_ms: u64,
+
+    /// An inherited, already bound TCP socket to accept connections on instead of binding the
+    /// proxy port (e.g. from socket activation)
+    #[clap(long, global = true)]
+    pub listen_fd: Option<i32>,
+
+    /// Send a copy of every request to this host:port, discarding its responses
+    #[clap(long, value_parser = parse_authority, global = true)]
+    pub mirror_to: Option<String>,
+
+    /// Request headers removed before forwarding unless the client is a trusted proxy
+    /// (comma-separated)
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',', global = true)]
+    pub sensitive_headers: Vec<String>,
+
+    /// Client addresses or CIDR ranges allowed to send sensitive headers (comma-separated)
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',', global = true)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// OTLP/HTTP endpoint to push the histograms to every interval (e.g.
+    /// http://localhost:4318/v1/metrics)
+    #[cfg(feature = "otlp")]
+    #[clap(long, global = true)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Render every interval's latency buckets as a bar chart to this file, which has to end in
+    /// .svg
+    #[cfg(feature = "chart")]
+    #[clap(long, value_parser = parse_chart_output, global = true)]
+    pub chart_output: Option<PathBuf>,
+
+    /// Strip or add a trailing slash on histogram endpoints so /users and /users/ share a row
+    #[clap(long, value_enum, global = true)]
+    pub normalize_trailing_slash: Option<TrailingSlash>,
+
+    /// Also apply --normalize-trailing-slash to the path forwarded to the target server
+    #[clap(long, default_value = "false", global = true)]
+    pub normalize_forwarded_path: bool,
+
+    /// Reject requests whose header fields add up to more than this many bytes with a 431.
+    /// Defaults to hyper's own read buffer limit of about 400KB
+    #[clap(long, global = true)]
+    pub max_request_header_bytes: Option<usize>,
+
+    /// Add a column with the number of requests per method to the histogram table, e.g.
+    /// `G:120 P:5` for GET and POST (U is PUT, D is DELETE, O any other method)
+    #[clap(long, default_value = "false", global = true)]
+    pub show_methods: bool,
+
+    /// Top-level fields removed from JSON responses of the target server (comma-separated)
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',', global = true)]
+    pub json_strip_fields: Vec<String>,
+
+    /// JSON responses larger than this, or without a Content-Length, are passed through without
+    /// removing --json-strip-fields
+    #[clap(long, default_value = "1048576", global = true)]
+    pub json_strip_max_bytes: u64,
+
+    /// On Ctrl-C or SIGTERM, how long in milliseconds to wait for open requests and for flushing
+    /// the statistics of the partial interval before exiting
+    #[clap(long, default_value = "10000", global = true)]
+    pub shutdown_grace_ms: u64,
+
+    /// MaxMind GeoLite2 Country and/or ASN database used by --block-countries and --block-asn
+    #[clap(long, global = true)]
+    pub geoip_db: Option<PathBuf>,
+
+    /// Reject clients from these ISO country codes, e.g. `NL,BE` (comma-separated)
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        requires = "geoip_db",
+        global = true
+    )]
+    pub block_countries: Vec<String>,
+
+    /// Reject clients from these autonomous system numbers (comma-separated)
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        requires = "geoip_db",
+        global = true
+    )]
+    pub block_asn: Vec<u32>,
+
+    /// Forward at most this many requests to the target server at once, queueing the rest (0
+    /// disables the limit)
+    #[clap(long, default_value = "0", global = true)]
+    pub max_upstream_requests: usize,
+
+    /// Answer with a 503 instead of queueing once this many requests wait for
+    /// --max-upstream-requests
+    #[clap(long, requires = "max_upstream_requests", global = true)]
+    pub max_queued: Option<usize>,
+
+    /// Give up connecting to the target server after this many milliseconds with a 502 (0 leaves
+    /// it to the OS)
+    #[clap(long, default_value = "0", global = true)]
+    pub connect_timeout_ms: u64,
+
+    /// Warn when an endpoint's estimated p95 latency worsens by more than this many percent from
+    /// one interval to the next
+    #[clap(long, global = true)]
+    pub regression_pct: Option<f64>,
+
+    /// Answer `/favicon.ico` with an empty 204 instead of forwarding it
+    #[clap(long, default_value = "false", global = true)]
+    pub serve_favicon: bool,
+
+    /// Answer `/robots.txt` with this content instead of forwarding it
+    #[clap(long, global = true)]
+    pub robots: Option<String>,
+
+    /// Follow up to this many redirects from the target server before answering, as long as they
+    /// stay on its host
+    #[clap(long, default_value = "0", global = true)]
+    pub follow_redirects: usize,
+
+    /// The pseudonym this proxy adds to `Via` headers
+    #[clap(long, default_value = "narrow", value_parser = parse_via_name, global = true)]
+    pub via_name: String,
+
+    /// Don't add a `Via` header to forwarded requests and responses
+    #[clap(long, default_value = "false", global = true)]
+    pub no_via: bool,
+
+    /// Override --timeout-ms for paths under a prefix, as PREFIX=MILLISECONDS (repeatable, the
+    /// longest matching prefix wins)
+    #[clap(long = "endpoint-timeout", value_parser = parse_endpoint_timeout, global = true)]
+    pub endpoint_timeouts: Vec<(String, u64)>,
+
+    /// Append the requests of every interval to this file as JSON lines
+    #[clap(long, global = true)]
+    pub loglist_file: Option<PathBuf>,
+
+    /// Latency percentiles shown in the histogram table (comma-separated, fractions allowed)
+    #[clap(long, default_value = "50,95,99", use_value_delimiter = true, value_delimiter = ',', value_parser = parse_percentile, global = true)]
+    pub percentiles: Vec<f64>,
+
+    /// Answer requests whose path matches this regex with --deny-status instead of forwarding them
+    /// (repeatable)
+    #[clap(long, global = true)]
+    pub deny_path: Vec<Regex>,
+
+    /// Only forward requests whose path matches one of these regexes (repeatable), --deny-path
+    /// still applies on top
+    #[clap(long, global = true)]
+    pub allow_path: Vec<Regex>,
+
+    /// The status answered to requests refused by --deny-path or --allow-path
+    #[clap(long, default_value = "404", value_parser = clap::value_parser!(u16).range(400..600), global = true)]
+    pub deny_status: u16,
+
+    /// Answer with a 502 when the target server announces a larger response body than this, or cut
+    /// off a streamed body once it exceeds it
+    #[clap(long, global = true)]
+    pub max_response_bytes: Option<u64>,
+
+    /// Check that the target server answers --health-path before accepting connections, and exit
+    /// if it doesn't
+    #[clap(long, default_value = "false", global = true)]
+    pub startup_probe: bool,
+
+    /// The path of the target server checked by --startup-probe
+    #[clap(long, default_value = "/", global = true)]
+    pub health_path: String,
+
+    /// How many more times --startup-probe tries, a second apart, before giving up
+    #[clap(long, default_value = "0", global = true)]
+    pub startup_probe_retries: u32,
+
+    /// How long every --startup-probe attempt may take in milliseconds
+    #[clap(long, default_value = "5000", global = true)]
+    pub startup_probe_timeout_ms: u64,
+
+    /// Which tracing events to log, as a level such as `debug` or directives such as
+    /// `narrow=debug,hyper=info`
+    #[clap(long, default_value = "info", value_parser = parse_log_filter, global = true)]
+    pub log_level: String,
+
+    /// Threads the runtime handles connections on. Defaults to the CPUs available to the process,
+    /// which already honors container CPU quotas, so set it when quotas are fractional or absent
+    #[clap(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), global = true)]
+    pub worker_threads: Option<usize>,
+
+    /// Forward the request path and query exactly as received, bypassing --strip-prefix,
+    /// --upstream-path-prefix, --normalize-forwarded-path and --rewrite-rules
+    #[clap(long, default_value = "false", conflicts_with_all = ["strip_prefix", "upstream_path_prefix", "normalize_forwarded_path", "rewrite_rules"], global = true)]
+    pub preserve_raw_uri: bool,
+
+    /// Write every interval's histograms as one NDJSON line to this file, named pipe or /dev/fd/N,
+    /// or to stdout when given `-` or no value
+    #[clap(long, num_args = 0..=1, default_missing_value = "-", global = true)]
+    pub stats_stream: Option<PathBuf>,
+
+    /// Count requests per local hour of the day for every endpoint and print them in an extra
+    /// table
+    #[clap(long, default_value = "false", global = true)]
+    pub track_hourly: bool,
+
+    /// Send TCP keepalive probes on connections to the target server once they are idle for this
+    /// many milliseconds, so connections silently dropped by a firewall or NAT leave the pool (0
+    /// disables)
+    #[clap(long, default_value = "0", global = true)]
+    pub upstream_tcp_keepalive_ms: u64,
+
+    /// Instead of proxying, send the requests of a `--loglist-file` to the target server at their
+    /// original pace and print the response time histogram
+    #[clap(long, global = true)]
+    pub replay: Option<PathBuf>,
+
+    /// Replay requests this many times as fast as they were logged
+    #[clap(long, default_value = "1", value_parser = parse_replay_speed, global = true)]
+    pub replay_speed: f64,
+
+    /// Count requests per requester IP and report this many of the busiest addresses every
+    /// interval
+    #[clap(long, global = true)]
+    pub top_talkers: Option<usize>,
+
+    /// The most requester IPs `--top-talkers` counts per interval, requests from further addresses
+    /// are only counted as untracked
+    #[clap(long, default_value = "10000", global = true)]
+    pub max_tracked_ips: usize,
+
+    /// Forward request headers in the order the client sent them, for target servers that sign or
+    /// verify requests over their raw headers. Repeated headers are grouped under their first
+    /// occurrence. Over HTTP/2 the client's order carries less meaning because pseudo-headers
+    /// always come first and header compression may have reordered fields on the way.
+    #[clap(long, default_value = "false", global = true)]
+    pub preserve_header_order: bool,
+
+    /// Hand free --max-upstream-requests slots to the waiting requests of each endpoint in turn,
+    /// so a flood of requests for one endpoint doesn't starve the others
+    #[clap(long, default_value = "false", requires = "max_upstream_requests", global = true)]
+    pub fair_queueing: bool,
+
+    /// Keep requester IPs out of logs and statistics, recording a salted hash or the /24 (IPv4) or
+    /// /48 (IPv6) network instead. Blacklist, geo and trusted proxy checks still see the real
+    /// address.
+    #[clap(long, value_enum, global = true)]
+    pub anonymize_ips: Option<AnonymizeIps>,
+
+    /// The salt for `--anonymize-ips hash`, a random one is picked on every start by default
+    #[clap(long, requires = "anonymize_ips", global = true)]
+    pub anonymize_salt: Option<String>,
+
+    /// Reject requests whose URI is longer than this many bytes with a 414 (0 disables the limit)
+    #[clap(long, default_value = "16384", global = true)]
+    pub max_uri_bytes: usize,
+
+    /// How many connections the kernel queues for the proxy port before refusing more, raise it
+    /// for bursty traffic (ignored with --listen-fd, whose socket is already listening)
+    #[clap(long, default_value = "1024", value_parser = clap::value_parser!(i32).range(1..), global = true)]
+    pub listen_backlog: i32,
+
+    /// Write a sample of full requests and responses to this directory, one file per request ID
+    #[clap(long, global = true)]
+    pub capture_dir: Option<PathBuf>,
+
+    /// The share of requests written to --capture-dir, by default 0.01 for one in a hundred
+    #[clap(long, default_value = "0.01", value_parser = parse_sample_rate, requires = "capture_dir", global = true)]
+    pub capture_sample_rate: f64,
+
+    /// Bytes of each body kept in a capture, the rest is cut off
+    #[clap(long, default_value = "65536", global = true)]
+    pub capture_max_bytes: usize,
+
+    /// Headers whose values are left out of captures, as are those of --sensitive-headers
+    /// (comma-separated)
+    #[clap(
+        long,
+        default_value = "authorization,proxy-authorization,cookie,set-cookie",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        global = true
+    )]
+    pub capture_redact_headers: Vec<String>,
+
+    /// Buffer request bodies that have to be sent more than once (for --mirror-to or
+    /// --follow-redirects) in temp files in this directory once they exceed
+    /// --spill-threshold-bytes
+    #[clap(long, global = true)]
+    pub spill_to_disk: Option<PathBuf>,
+
+    /// Bytes of a buffered request body kept in memory before it is spilled to --spill-to-disk
+    #[clap(long, default_value = "1048576", requires = "spill_to_disk", global = true)]
+    pub spill_threshold_bytes: usize,
+
+    /// Send the access logs of every interval to syslog as RFC 5424 messages, to the local
+    /// daemon when given `local` or no value, or over UDP to a remote host:port
+    #[clap(long, num_args = 0..=1, default_missing_value = "local", value_parser = parse_syslog_target, global = true)]
+    pub syslog: Option<String>,
+
+    /// The facility of the --syslog messages
+    #[clap(long, value_enum, default_value = "user", global = true)]
+    pub syslog_facility: SyslogFacility,
+
+    /// Forward requests with a matching header to another upstream, as NAME=VALUE:HOST:PORT with a
+    /// trailing `*` on VALUE to match by prefix (repeatable, the first matching route wins).
+    /// Requests matching no route go to --host and --port, there are no path routes.
+    #[clap(long = "header-route", value_parser = parse_header_route, global = true)]
+    pub header_routes: Vec<HeaderRoute>,
+
+    /// Keep the last N forwarded requests across intervals and serve them at GET
+    /// /__narrow/recent, to the clients allowed to use the admin API (see
+    /// --admin-allow)
+    #[clap(long, global = true)]
+    pub recent_buffer: Option<usize>,
+
+    /// Client CIDR ranges allowed to pick the upstream of a request with a `__upstream=host:port`
+    /// query parameter, for testing (comma-separated). The parameter is always removed before
+    /// forwarding, and ignored for everyone else.
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',', global = true)]
+    pub allow_upstream_override: Vec<IpNet>,
+
+    /// Warn when the share of an endpoint's requests answered without a 5xx drops below a ratio
+    /// over an interval, as ENDPOINT=RATIO (repeatable)
+    #[clap(long = "endpoint-min-success", value_parser = parse_endpoint_min_success, global = true)]
+    pub endpoint_min_success: Vec<(String, f64)>,
+
+    /// Cache the addresses of the target server and resolve it again every this many milliseconds,
+    /// closing connections to addresses it no longer resolves to
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    pub dns_refresh_ms: Option<u64>,
+
+    /// Forget endpoints this many seconds after their last request instead of after an interval
+    /// without requests ("Overall" is always kept)
+    #[clap(long, global = true)]
+    pub endpoint_ttl: Option<u64>,
+
+    /// Send the request durations and counts of every interval to this StatsD server (host:port)
+    /// over UDP
+    #[clap(long, value_parser = parse_authority, global = true)]
+    pub statsd: Option<String>,
+
+    /// Let identical GETs without credentials that arrive while one of them is being forwarded
+    /// share its response instead of reaching the target server each (only 200s without cookies,
+    /// Cache-Control: private or no-store, not varying on other request headers and of up to 1 MiB
+    /// are shared)
+    #[clap(long, default_value = "false", global = true)]
+    pub coalesce: bool,
+
+    /// Replace the body of 5xx responses from the target server with this file, keeping their
+    /// status and headers
+    #[clap(long = "error-page-5xx", value_parser = parse_error_page, global = true)]
+    pub error_page_5xx: Option<ErrorPage>,
+
+    /// Client CIDR ranges allowed to use the admin API under `/__narrow/` (comma-separated).
+    /// Without it only loopback clients that aren't --trusted-proxies may, everyone else gets a
+    /// 403. The admin paths share the proxy port, so this list is the only network boundary
+    /// around them.
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',', global = true)]
+    pub admin_allow: Vec<IpNet>,
+
+    /// Bearer token the admin API additionally requires in `Authorization`, requests without it
+    /// get a 401
+    #[clap(long, global = true)]
+    pub admin_token: Option<String>,
+
+    /// Time the phases of every request (preparing it, connecting, waiting for the response and
+    /// streaming the body) and report their averages, for tuning. Off by default, as it costs
+    /// some time itself.
+    #[clap(long, default_value = "false", global = true)]
+    pub profile: bool,
+
+    /// Delay requests from a client address or CIDR range by some milliseconds before forwarding
+    /// them, rather than rejecting them (e.g. 203.0.113.0/24=2000, repeatable). The first
+    /// matching range applies, the delay is not counted in the response times.
+    #[clap(long, value_parser = parse_greylist, global = true)]
+    pub greylist: Vec<(IpNet, u64)>,
+
+    /// Expect a PROXY protocol v1 or v2 header from the load balancer in front of the proxy on
+    /// every connection, and use the client address it carries. Connections without a valid
+    /// one are closed.
+    #[clap(long, default_value = "false", global = true)]
+    pub proxy_protocol: bool,
+
+    /// The most streams an HTTP/2 client may have open on one connection at once
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    pub http2_max_concurrent_streams: Option<u32>,
+
+    /// The initial HTTP/2 flow control window of every stream in bytes, hyper defaults to 1 MiB
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=2147483647), global = true)]
+    pub http2_initial_stream_window_size: Option<u32>,
+
+    /// The initial HTTP/2 flow control window of every connection in bytes, hyper defaults to
+    /// 1 MiB
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=2147483647), global = true)]
+    pub http2_initial_connection_window_size: Option<u32>,
+
+    /// URL to POST a JSON alert to when --regression-pct or --endpoint-min-success is crossed
+    /// (http only). An alert is sent when its threshold is first crossed, not again every interval
+    /// until it clears.
+    #[clap(long, global = true)]
+    pub alert_webhook: Option<String>,
+
+    /// Address of the running proxy whose admin API `stats` queries, as host:port. Defaults to
+    /// the loopback address on `--proxy`, which has to run with `--serve-stats`.
+    #[clap(long, global = true)]
+    pub admin_addr: Option<String>,
+
+    /// Add an "EWMA p95" column with each endpoint's p95 latency smoothed across intervals, the
+    /// weight between 0 and 1 of the latest interval. A p95 in the open-ended top bucket counts as
+    /// its lower bound.
+    #[clap(long, value_parser = parse_ewma_alpha, global = true)]
+    pub ewma_alpha: Option<f64>,
+
+    /// Rewrite request paths on the way to the target server with the rules in this file, one
+    /// regex and replacement per line where the first matching rule wins. Capture groups are
+    /// available as `$1` or `${name}`. The query is kept, a rewritten path with a query or
+    /// fragment of its own is answered with a 500.
+    #[clap(long, value_parser = parse_rewrite_rules, global = true)]
+    pub rewrite_rules: Option<RewriteRules>,
+
+    /// Record histogram endpoints under the path as rewritten by --rewrite-rules, instead of the
+    /// path the client requested
+    #[clap(long, default_value = "false", global = true)]
+    pub record_rewritten_path: bool,
+
+    /// Order the endpoints of the interval table, in no particular order by default
+    #[clap(long, value_enum, global = true)]
+    pub sort_by: Option<SortBy>,
+
+    /// Show only this many endpoints in the interval table, the busiest or those first by
+    /// --sort-by, and sum up the rest in an "Other" row. Exports still get every endpoint.
+    #[clap(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), global = true)]
+    pub top_n: Option<usize>,
+
+    /// Give up on a mirrored request after this many milliseconds, counting it as a mirror error
+    /// (0 for no timeout)
+    #[clap(long, default_value = "5000", global = true)]
+    pub mirror_timeout_ms: u64,
+
+    /// Serve the histograms recorded so far this interval at GET /__narrow/stats for `narrow
+    /// stats`, to the clients allowed to use the admin API. Without it the path is forwarded like
+    /// any other.
+    #[clap(long, default_value = "false", global = true)]
+    pub serve_stats: bool,
+}
+
+/// Accept a `host:port` authority for a target server
+fn parse_authority(value: &str) -> Result<String, String> {
+    match value.parse::<hyper::http::uri::Authority>() {
+        Ok(authority) if authority.port().is_some() => Ok(value.to_string()),
+        Ok(_) => Err("expected host:port".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Accept a `--syslog` target, `local` or the `host:port` of a remote syslog server
+fn parse_syslog_target(value: &str) -> Result<String, String> {
+    match value {
+        "local" => Ok(value.to_string()),
+        _ => parse_authority(value).map_err(|e| format!("expected local or host:port, {}", e)),
+    }
+}
+
+/// Accept a `--header-route` as `NAME=VALUE:HOST:PORT`, where a trailing `*` on the value
+/// matches any value starting with it
+fn parse_header_route(value: &str) -> Result<HeaderRoute, String> {
+    let (header, rest) = value.split_once('=').ok_or("expected NAME=VALUE:HOST:PORT")?;
+    hyper::header::HeaderName::from_bytes(header.as_bytes())
+        .map_err(|_| format!("invalid header name {}", header))?;
+    let mut parts = rest.rsplitn(3, ':');
+    let (port, host, matched) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(port), Some(host), Some(matched)) => (port, host, matched),
+        _ => return Err("expected NAME=VALUE:HOST:PORT".to_string()),
+    };
+    let upstream = parse_authority(&format!("{}:{}", host, port))?;
+    let (matched, prefix) = match matched.strip_suffix('*') {
+        Some(matched) => (matched, true),
+        None => (matched, false),
+    };
+
+    Ok(HeaderRoute {
+        header: header.to_ascii_lowercase(),
+        value: matched.to_string(),
+        prefix,
+        upstream,
+    })
+}
+
+/// Accept a `PREFIX=MILLISECONDS` timeout override
+fn parse_endpoint_timeout(value: &str) -> Result<(String, u64), String> {
+    let (prefix, millis) = value.split_once('=').ok_or("expected PREFIX=MILLISECONDS")?;
+    if !prefix.starts_with('/') {
+        return Err("the prefix must start with /".to_string());
+    }
+    let millis = millis.parse::<u64>().map_err(|e| format!("invalid milliseconds: {}", e))?;
+    Ok((prefix.to_string(), millis))
+}
+
+/// Accept an `--endpoint-min-success` threshold such as `/checkout=0.99`
+fn parse_endpoint_min_success(value: &str) -> Result<(String, f64), String> {
+    let (endpoint, ratio) = value.rsplit_once('=').ok_or("expected ENDPOINT=RATIO")?;
+    if endpoint.is_empty() {
+        return Err("the endpoint must not be empty".to_string());
+    }
+    let ratio = ratio.parse::<f64>().map_err(|e| format!("invalid ratio: {}", e))?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err("the ratio must be between 0 and 1".to_string());
+    }
+    Ok((endpoint.to_string(), ratio))
+}
+
+/// Accept a `--greylist` entry such as `203.0.113.0/24=2000`, a single address is a range of one
+fn parse_greylist(value: &str) -> Result<(IpNet, u64), String> {
+    let (range, ms) = value.split_once('=').ok_or("expected IP_OR_CIDR=DELAY_MS")?;
+    let range = match range.parse::<IpAddr>() {
+        Ok(ip) => IpNet::from(ip),
+        Err(_) => range.parse::<IpNet>().map_err(|e| format!("invalid range: {}", e))?,
+    };
+    let ms = ms.parse::<u64>().map_err(|e| format!("invalid delay: {}", e))?;
+    Ok((range, ms))
+}
+
+/// Accept an `--ewma-alpha`, 0 would never move from the first interval
+fn parse_ewma_alpha(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(alpha),
+        Ok(_) => Err("expected a weight above 0 and at most 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Read the `--error-page-5xx` file, so a missing page stops the proxy from starting
+fn parse_error_page(value: &str) -> Result<ErrorPage, String> {
+    ErrorPage::load(std::path::Path::new(value)).map_err(|e| e.to_string())
+}
+
+/// Read and compile the `--rewrite-rules` file, so a broken rule stops the proxy from starting
+fn parse_rewrite_rules(value: &str) -> Result<RewriteRules, String> {
+    RewriteRules::load(std::path::Path::new(value))
+}
+
+/// Accept a `--chart-output` path in a format the chart can be rendered in, only SVG for now
+#[cfg(feature = "chart")]
+fn parse_chart_output(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("svg") => Ok(path),
+        Some(ext) => Err(format!("can't render .{} charts, only .svg", ext)),
+        None => Err("expected a path ending in .svg".to_string()),
+    }
+}
+
+/// Accept a percentile between 0 and 100
+fn parse_percentile(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        Ok(_) => Err("expected a percentile between 0 and 100".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Accept a `--capture-sample-rate`, a share of the requests between 0 and 1
+fn parse_sample_rate(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        Ok(_) => Err("expected a rate between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Accept a `--replay-speed` multiplier, which has to be positive
+fn parse_replay_speed(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        Ok(_) => Err("expected a speed above 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Accept a `tracing` filter such as `info` or `narrow=debug,hyper=warn`
+fn parse_log_filter(value: &str) -> Result<String, String> {
+    tracing_subscriber::EnvFilter::try_new(value).map_err(|e| e.to_string())?;
+    Ok(value.to_string())
+}
+
+/// Accept a pseudonym for `Via` headers, which has to be a single token
+fn parse_via_name(value: &str) -> Result<String, String> {
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if value.is_empty() || !value.chars().all(is_token) {
+        return Err("expected a name without spaces or separators".to_string());
+    }
+    Ok(value.to_string())
 }
 
 // unit test
@@ -51,7 +769,7 @@ mod tests {
 
     #[test]
     fn test_args() {
-        let args = Args::parse_from(&[
+        let args = Args::parse_from([
             "test",
             "--proxy",
             "8001",
@@ -68,8 +786,371 @@ mod tests {
         assert_eq!(args.host, "example.com");
         assert_eq!(args.port, 3001);
         assert_eq!(args.blacklist, vec![] as Vec<IpAddr>);
-        assert_eq!(args.monitoring, false);
+        assert!(!args.monitoring);
         assert_eq!(args.server, "https://monitoring.narrow.so");
         assert_eq!(args.key, "");
+        assert_eq!(args.static_dir, None);
+        assert_eq!(args.static_prefix, vec!["/static"]);
+        assert_eq!(args.group_by_header, None);
+        assert_eq!(args.max_endpoints, 1000);
+        assert_eq!(args.upstream_path_prefix, None);
+        assert_eq!(args.strip_prefix, None);
+        assert_eq!(args.error_format, ErrorFormat::Text);
+        assert_eq!(args.blacklist_url, None);
+        assert_eq!(args.blacklist_refresh, 300);
+        assert!(!args.no_color);
+        assert_eq!(args.highlight_p95_ms, 500.0);
+        assert_eq!(args.highlight_error_ratio, 0.05);
+        assert_eq!(args.timeout_ms, 0);
+        assert_eq!(args.listen_fd, None);
+        assert_eq!(args.mirror_to, None);
+        assert_eq!(args.sensitive_headers, vec![] as Vec<String>);
+        assert_eq!(args.trusted_proxies, vec![] as Vec<IpNet>);
+        #[cfg(feature = "otlp")]
+        assert_eq!(args.otlp_endpoint, None);
+        #[cfg(feature = "chart")]
+        assert_eq!(args.chart_output, None);
+        assert_eq!(args.normalize_trailing_slash, None);
+        assert!(!args.normalize_forwarded_path);
+        assert_eq!(args.max_request_header_bytes, None);
+        assert!(!args.show_methods);
+        assert_eq!(args.json_strip_fields, vec![] as Vec<String>);
+        assert_eq!(args.json_strip_max_bytes, 1048576);
+        assert_eq!(args.shutdown_grace_ms, 10000);
+        assert_eq!(args.geoip_db, None);
+        assert_eq!(args.block_countries, vec![] as Vec<String>);
+        assert_eq!(args.block_asn, vec![] as Vec<u32>);
+        assert_eq!(args.max_upstream_requests, 0);
+        assert_eq!(args.max_queued, None);
+        assert_eq!(args.connect_timeout_ms, 0);
+        assert_eq!(args.regression_pct, None);
+        assert!(!args.serve_favicon);
+        assert_eq!(args.robots, None);
+        assert_eq!(args.follow_redirects, 0);
+        assert_eq!(args.via_name, "narrow");
+        assert!(!args.no_via);
+        assert_eq!(args.endpoint_timeouts, vec![]);
+        assert_eq!(args.loglist_file, None);
+        assert_eq!(args.percentiles, vec![50.0, 95.0, 99.0]);
+        assert!(args.deny_path.is_empty());
+        assert!(args.allow_path.is_empty());
+        assert_eq!(args.deny_status, 404);
+        assert_eq!(args.max_response_bytes, None);
+        assert!(!args.startup_probe);
+        assert_eq!(args.health_path, "/");
+        assert_eq!(args.startup_probe_retries, 0);
+        assert_eq!(args.startup_probe_timeout_ms, 5000);
+        assert_eq!(args.log_level, "info");
+        assert_eq!(args.worker_threads, None);
+        assert!(!args.preserve_raw_uri);
+        assert_eq!(args.stats_stream, None);
+        assert!(!args.track_hourly);
+        assert_eq!(args.upstream_tcp_keepalive_ms, 0);
+        assert_eq!(args.replay, None);
+        assert_eq!(args.replay_speed, 1.0);
+        assert_eq!(args.top_talkers, None);
+        assert_eq!(args.max_tracked_ips, 10000);
+        assert!(!args.preserve_header_order);
+        assert!(!args.fair_queueing);
+        assert_eq!(args.anonymize_ips, None);
+        assert_eq!(args.anonymize_salt, None);
+        assert_eq!(args.max_uri_bytes, 16384);
+        assert_eq!(args.listen_backlog, 1024);
+        assert_eq!(args.capture_dir, None);
+        assert_eq!(args.capture_sample_rate, 0.01);
+        assert_eq!(args.capture_max_bytes, 65536);
+        assert_eq!(
+            args.capture_redact_headers,
+            ["authorization", "proxy-authorization", "cookie", "set-cookie"]
+        );
+        assert_eq!(args.spill_to_disk, None);
+        assert_eq!(args.spill_threshold_bytes, 1048576);
+        assert_eq!(args.syslog, None);
+        assert_eq!(args.syslog_facility, SyslogFacility::User);
+        assert_eq!(args.header_routes, vec![]);
+        assert_eq!(args.recent_buffer, None);
+        assert_eq!(args.allow_upstream_override, vec![] as Vec<IpNet>);
+        assert_eq!(args.endpoint_min_success, vec![]);
+        assert_eq!(args.dns_refresh_ms, None);
+        assert_eq!(args.endpoint_ttl, None);
+        assert_eq!(args.statsd, None);
+        assert!(!args.coalesce);
+        assert_eq!(args.error_page_5xx, None);
+        assert_eq!(args.admin_allow, vec![] as Vec<IpNet>);
+        assert_eq!(args.admin_token, None);
+        assert!(!args.profile);
+        assert_eq!(args.greylist, vec![]);
+        assert!(!args.proxy_protocol);
+        assert_eq!(args.http2_max_concurrent_streams, None);
+        assert_eq!(args.http2_initial_stream_window_size, None);
+        assert_eq!(args.http2_initial_connection_window_size, None);
+        assert_eq!(args.alert_webhook, None);
+        assert_eq!(args.admin_addr, None);
+        assert_eq!(args.ewma_alpha, None);
+        assert!(args.rewrite_rules.is_none());
+        assert!(!args.record_rewritten_path);
+        assert_eq!(args.sort_by, None);
+        assert_eq!(args.top_n, None);
+        assert_eq!(args.mirror_timeout_ms, 5000);
+        assert!(!args.serve_stats);
+    }
+
+    #[test]
+    fn test_endpoint_timeouts() {
+        let args = Args::parse_from([
+            "test",
+            "--endpoint-timeout",
+            "/report=120000",
+            "--endpoint-timeout",
+            "/api/slow=0",
+        ]);
+        assert_eq!(
+            args.endpoint_timeouts,
+            vec![("/report".to_string(), 120000), ("/api/slow".to_string(), 0)]
+        );
+
+        assert!(Args::try_parse_from(["test", "--endpoint-timeout", "/report"]).is_err());
+        assert!(Args::try_parse_from(["test", "--endpoint-timeout", "report=1"]).is_err());
+        assert!(Args::try_parse_from(["test", "--endpoint-timeout", "/report=soon"]).is_err());
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let args = Args::parse_from(["test", "--percentiles", "50,99,99.9"]);
+        assert_eq!(args.percentiles, vec![50.0, 99.0, 99.9]);
+
+        assert!(Args::try_parse_from(["test", "--percentiles", "50,101"]).is_err());
+        assert!(Args::try_parse_from(["test", "--percentiles", "p99"]).is_err());
+    }
+
+    #[test]
+    fn test_path_filters() {
+        let args = Args::parse_from(["test", "--deny-path", "^/admin", "--deny-status", "403"]);
+        assert_eq!(args.deny_path[0].as_str(), "^/admin");
+        assert_eq!(args.deny_status, 403);
+
+        assert!(Args::try_parse_from(["test", "--deny-path", "(unclosed"]).is_err());
+        assert!(Args::try_parse_from(["test", "--deny-status", "200"]).is_err());
+    }
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!(
+            Args::parse_from(["test", "--log-level", "narrow=debug"]).log_level,
+            "narrow=debug"
+        );
+        assert!(Args::try_parse_from(["test", "--log-level", "narrow=loud"]).is_err());
+    }
+
+    #[test]
+    fn test_worker_threads() {
+        assert_eq!(Args::parse_from(["test", "--worker-threads", "2"]).worker_threads, Some(2));
+        assert!(Args::try_parse_from(["test", "--worker-threads", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_preserve_raw_uri_excludes_rewrites() {
+        assert!(Args::parse_from(["test", "--preserve-raw-uri"]).preserve_raw_uri);
+        assert!(
+            Args::try_parse_from(["test", "--preserve-raw-uri", "--strip-prefix", "/api"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_stats_stream_defaults_to_stdout() {
+        let args = Args::parse_from(["test", "--stats-stream"]);
+        assert_eq!(args.stats_stream, Some(PathBuf::from("-")));
+
+        let args = Args::parse_from(["test", "--stats-stream", "/dev/fd/3"]);
+        assert_eq!(args.stats_stream, Some(PathBuf::from("/dev/fd/3")));
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        let args =
+            Args::parse_from(["test", "--replay", "requests.jsonl", "--replay-speed", "2.5"]);
+        assert_eq!(args.replay, Some(PathBuf::from("requests.jsonl")));
+        assert_eq!(args.replay_speed, 2.5);
+
+        for speed in ["0", "-1", "inf", "fast"] {
+            assert!(Args::try_parse_from(["test", "--replay-speed", speed]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_capture_sample_rate() {
+        let args =
+            Args::parse_from(["test", "--capture-dir", "/tmp", "--capture-sample-rate", "0.25"]);
+        assert_eq!(args.capture_sample_rate, 0.25);
+        assert!(Args::try_parse_from(["test", "--capture-sample-rate", "0.25"]).is_err());
+        assert!(Args::try_parse_from([
+            "test",
+            "--capture-dir",
+            "/tmp",
+            "--capture-sample-rate",
+            "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(Args::parse_from(["test"]).command, None);
+        assert_eq!(Args::parse_from(["test", "run"]).command, Some(Command::Run));
+
+        let args = Args::parse_from(["test", "--proxy", "9000", "validate"]);
+        assert_eq!(args.command, Some(Command::Validate));
+        assert_eq!(args.proxy, 9000);
+        let args = Args::parse_from(["test", "validate", "--proxy", "9000", "-H", "backend"]);
+        assert_eq!(args.command, Some(Command::Validate));
+        assert_eq!((args.proxy, args.host.as_str()), (9000, "backend"));
+        assert_eq!(args.port, 3000);
+
+        let args = Args::parse_from(["test", "--admin-addr", "10.0.0.5:8000", "stats"]);
+        assert_eq!(args.command, Some(Command::Stats));
+        assert_eq!(args.admin_addr.as_deref(), Some("10.0.0.5:8000"));
+    }
+
+    #[cfg(feature = "chart")]
+    #[test]
+    fn test_chart_output() {
+        let args = Args::parse_from(["test", "--chart-output", "latency.SVG"]);
+        assert_eq!(args.chart_output, Some(PathBuf::from("latency.SVG")));
+        assert!(Args::try_parse_from(["test", "--chart-output", "latency.png"]).is_err());
+        assert!(Args::try_parse_from(["test", "--chart-output", "latency"]).is_err());
+    }
+
+    #[test]
+    fn test_syslog() {
+        let args = Args::parse_from(["test", "--syslog"]);
+        assert_eq!(args.syslog.as_deref(), Some("local"));
+        let args =
+            Args::parse_from(["test", "--syslog", "logs:514", "--syslog-facility", "local3"]);
+        assert_eq!(args.syslog.as_deref(), Some("logs:514"));
+        assert_eq!(args.syslog_facility, SyslogFacility::Local3);
+        assert!(Args::try_parse_from(["test", "--syslog", "logs"]).is_err());
+    }
+
+    #[test]
+    fn test_header_route() {
+        let args = Args::parse_from([
+            "test",
+            "--header-route",
+            "X-API-Version=2:backend-v2:3002",
+            "--header-route",
+            "X-Tenant=beta-*:127.0.0.1:3004",
+        ]);
+        assert_eq!(
+            args.header_routes,
+            vec![
+                HeaderRoute {
+                    header: "x-api-version".to_string(),
+                    value: "2".to_string(),
+                    prefix: false,
+                    upstream: "backend-v2:3002".to_string(),
+                },
+                HeaderRoute {
+                    header: "x-tenant".to_string(),
+                    value: "beta-".to_string(),
+                    prefix: true,
+                    upstream: "127.0.0.1:3004".to_string(),
+                },
+            ]
+        );
+        assert!(Args::try_parse_from(["test", "--header-route", "X-API-Version=2"]).is_err());
+        assert!(
+            Args::try_parse_from(["test", "--header-route", "X-API-Version=2:host:port"]).is_err()
+        );
+        assert!(Args::try_parse_from(["test", "--header-route", "bad name=2:host:3000"]).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_min_success() {
+        let args = Args::parse_from([
+            "test",
+            "--endpoint-min-success",
+            "/checkout=0.99",
+            "--endpoint-min-success",
+            "Overall=0.9",
+        ]);
+        assert_eq!(
+            args.endpoint_min_success,
+            vec![("/checkout".to_string(), 0.99), ("Overall".to_string(), 0.9)]
+        );
+
+        assert!(Args::try_parse_from(["test", "--endpoint-min-success", "/checkout"]).is_err());
+        assert!(Args::try_parse_from(["test", "--endpoint-min-success", "=0.99"]).is_err());
+        assert!(Args::try_parse_from(["test", "--endpoint-min-success", "/checkout=1.5"]).is_err());
+    }
+
+    #[test]
+    fn test_greylist() {
+        let args =
+            Args::parse_from(["test", "--greylist", "203.0.113.0/24=2000", "--greylist", "::1=50"]);
+        assert_eq!(
+            args.greylist,
+            vec![("203.0.113.0/24".parse().unwrap(), 2000), ("::1/128".parse().unwrap(), 50)]
+        );
+
+        assert!(Args::try_parse_from(["test", "--greylist", "203.0.113.0/24"]).is_err());
+        assert!(Args::try_parse_from(["test", "--greylist", "not-an-ip=100"]).is_err());
+        assert!(Args::try_parse_from(["test", "--greylist", "10.0.0.1=soon"]).is_err());
+    }
+
+    #[test]
+    fn test_ewma_alpha() {
+        assert_eq!(parse_ewma_alpha("0.3"), Ok(0.3));
+        assert_eq!(parse_ewma_alpha("1"), Ok(1.0));
+        assert!(parse_ewma_alpha("0").is_err());
+        assert!(parse_ewma_alpha("1.5").is_err());
+        assert!(Args::try_parse_from(["test", "--ewma-alpha", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_top_n() {
+        let args = Args::parse_from(["test", "--top-n", "20", "--sort-by", "p95"]);
+        assert_eq!((args.top_n, args.sort_by), (Some(20), Some(SortBy::P95)));
+        assert!(Args::try_parse_from(["test", "--top-n", "0"]).is_err());
+        assert!(Args::try_parse_from(["test", "--sort-by", "name"]).is_err());
+    }
+
+    #[test]
+    fn test_via_name() {
+        assert_eq!(Args::parse_from(["test", "--via-name", "edge-1"]).via_name, "edge-1");
+        assert!(Args::try_parse_from(["test", "--via-name", "edge 1"]).is_err());
+        assert!(Args::try_parse_from(["test", "--via-name", "edge,1"]).is_err());
+        assert!(Args::try_parse_from(["test", "--via-name", ""]).is_err());
+    }
+
+    #[test]
+    fn test_geoip_args_require_database() {
+        assert!(Args::try_parse_from(["test", "--block-countries", "NL"]).is_err());
+        assert!(Args::try_parse_from(["test", "--block-asn", "64500"]).is_err());
+
+        let args =
+            Args::parse_from(["test", "--geoip-db", "geo.mmdb", "--block-countries", "NL,BE"]);
+        assert_eq!(args.block_countries, vec!["NL", "BE"]);
+    }
+
+    #[test]
+    fn test_max_queued_requires_limit() {
+        assert!(Args::try_parse_from(["test", "--max-queued", "10"]).is_err());
+        let argv = ["test", "--max-upstream-requests", "4", "--max-queued", "10"];
+        assert_eq!(Args::parse_from(argv).max_queued, Some(10));
+    }
+
+    #[test]
+    fn test_fair_queueing_requires_limit() {
+        assert!(Args::try_parse_from(["test", "--fair-queueing"]).is_err());
+        let argv = ["test", "--max-upstream-requests", "4", "--fair-queueing"];
+        assert!(Args::parse_from(argv).fair_queueing);
+    }
+
+    #[test]
+    fn test_parse_authority() {
+        assert_eq!(parse_authority("localhost:3001"), Ok("localhost:3001".to_string()));
+        assert_eq!(parse_authority("10.0.0.1:80"), Ok("10.0.0.1:80".to_string()));
+        assert!(parse_authority("localhost").is_err());
+        assert!(parse_authority("http://localhost:3001/").is_err());
     }
 }
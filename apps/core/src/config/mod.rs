@@ -1,3 +1,5 @@
 mod args;
+mod validate;
 
-pub use args::Args;
+pub use args::{AnonymizeIps, Args, Command, ErrorFormat, SortBy, SyslogFacility, TrailingSlash};
+pub use validate::{validate, validate_args};
@@ -0,0 +1,203 @@
+use std::io::ErrorKind;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+use clap::error::ErrorKind as ClapErrorKind;
+use clap::Parser;
+
+use crate::config::Args;
+use crate::net::listener::bind_listener;
+use crate::state::{Config, GeoBlocker};
+
+/// Every problem with `config` that would keep the proxy from starting or break it once running,
+/// for the `validate` subcommand
+pub fn validate(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    match bind_listener(config) {
+        // Validating the options of a proxy that is already running is likely the reason
+        Err(e) if e.kind() == ErrorKind::AddrInUse => problems.push(format!(
+            "could not listen on port {}: it is already in use, possibly by a running proxy",
+            config.proxy
+        )),
+        Err(e) => problems.push(format!("could not listen on port {}: {}", config.proxy, e)),
+        Ok(_) => {}
+    }
+    if let Err(e) = (config.host.as_str(), config.port).to_socket_addrs() {
+        problems.push(format!("could not resolve target host {}: {}", config.host, e));
+    }
+
+    if let Some(dir) = &config.static_dir {
+        require_dir(&mut problems, "--static-dir", dir);
+    }
+    if let Some(dir) = &config.spill_to_disk {
+        require_dir(&mut problems, "--spill-to-disk", dir);
+    }
+    // Created on the first capture, but it can't be a file
+    if let Some(dir) = config.capture_dir.as_ref().filter(|dir| dir.exists()) {
+        require_dir(&mut problems, "--capture-dir", dir);
+    }
+
+    if let Some(path) = &config.geoip_db {
+        if let Err(e) = GeoBlocker::open(path, &config.block_countries, &config.block_asn) {
+            problems.push(e);
+        }
+    }
+    if let Some(path) = config.replay.as_ref().filter(|path| !path.is_file()) {
+        problems.push(format!("--replay {} is not a file", path.display()));
+    }
+
+    if let Some(path) = &config.loglist_file {
+        require_parent(&mut problems, "--loglist-file", path);
+    }
+    if let Some(path) = config.stats_stream.as_ref().filter(|path| path.as_os_str() != "-") {
+        require_parent(&mut problems, "--stats-stream", path);
+    }
+
+    problems
+}
+
+/// Every problem with the command line `argv` of the `validate` subcommand, for when it doesn't
+/// parse. The parser stops at the first bad value, so each option is parsed on its own to find
+/// all of them, and the options left over are parsed together and checked with [`validate`]
+pub fn validate_args(argv: &[String]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut valid = vec![argv[0].as_str(), "validate"];
+
+    for option in options(&argv[1..]) {
+        let mut alone = vec![argv[0].as_str(), "validate"];
+        alone.extend_from_slice(&option);
+        match Args::try_parse_from(alone) {
+            // The options it requires may well be among the others
+            Err(e) if e.kind() != ClapErrorKind::MissingRequiredArgument => {
+                problems.push(clap_message(&e))
+            }
+            _ => valid.extend_from_slice(&option),
+        }
+    }
+
+    match Args::try_parse_from(valid) {
+        Ok(args) => problems.extend(validate(&Config::from(&args))),
+        Err(e) => problems.push(clap_message(&e)),
+    }
+    problems
+}
+
+/// Splits the arguments into options, each with the values following it. A lone `-` is a value,
+/// standing for stdout, and the subcommand isn't part of any
+fn options(args: &[String]) -> Vec<Vec<&str>> {
+    let mut options: Vec<Vec<&str>> = Vec::new();
+    for arg in args.iter().map(String::as_str).filter(|&arg| arg != "validate") {
+        match options.last_mut() {
+            Some(option) if !arg.starts_with('-') || arg == "-" => option.push(arg),
+            _ => options.push(vec![arg]),
+        }
+    }
+    options
+}
+
+/// A clap error on one line, without the usage hint after it and without its `error: ` prefix,
+/// which the caller adds back
+fn clap_message(e: &clap::Error) -> String {
+    let message = e.to_string();
+    let lines: Vec<&str> = message.lines().take_while(|line| !line.is_empty()).collect();
+    let message = lines.iter().map(|line| line.trim()).collect::<Vec<_>>().join(" ");
+    message.strip_prefix("error: ").unwrap_or(&message).to_string()
+}
+
+fn require_dir(problems: &mut Vec<String>, option: &str, dir: &Path) {
+    if !dir.is_dir() {
+        problems.push(format!("{} {} is not a directory", option, dir.display()));
+    }
+}
+
+/// The file itself is created when first written to, its directory has to exist already
+fn require_parent(problems: &mut Vec<String>, option: &str, path: &Path) {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+    if let Some(parent) = parent.filter(|parent| !parent.is_dir()) {
+        problems.push(format!("{} {}: no directory {}", option, path.display(), parent.display()));
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn config(extra_args: &[&str]) -> Config {
+        let mut argv = vec!["test", "--proxy", "0", "--host", "127.0.0.1"];
+        argv.extend_from_slice(extra_args);
+        Config::from(&Args::parse_from(argv))
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("narrow-validate-{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let (dir, file) = (dir.to_str().unwrap(), file.to_str().unwrap());
+
+        let valid = ["--static-dir", dir, "--replay", file, "--loglist-file", "requests.jsonl"];
+        assert_eq!(validate(&config(&valid)), Vec::<String>::new());
+
+        let invalid = config(&[
+            "--static-dir",
+            file,
+            "--spill-to-disk",
+            "/nonexistent/spill",
+            "--replay",
+            dir,
+            "--stats-stream",
+            "/nonexistent/stats.ndjson",
+        ]);
+        assert_eq!(
+            validate(&invalid),
+            vec![
+                format!("--static-dir {} is not a directory", file),
+                "--spill-to-disk /nonexistent/spill is not a directory".to_string(),
+                format!("--replay {} is not a file", dir),
+                "--stats-stream /nonexistent/stats.ndjson: no directory /nonexistent".to_string(),
+            ]
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let taken =
+            Config::from(&Args::parse_from(["test", "--proxy", &port, "--host", "127.0.0.1"]));
+        let problems = validate(&taken);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with(&format!("could not listen on port {}", port)));
+        assert!(problems[0].ends_with("already in use, possibly by a running proxy"));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let argv = [
+            "test",
+            "--proxy",
+            "0",
+            "--trusted-proxies",
+            "nope",
+            "validate",
+            "--host",
+            "127.0.0.1",
+            "--deny-path",
+            "(",
+            "--replay",
+            "/nonexistent/replay",
+            "--proxy-port",
+            "1",
+        ];
+        let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+        assert!(Args::try_parse_from(&argv).is_err());
+
+        let problems = validate_args(&argv);
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].starts_with("invalid value 'nope' for '--trusted-proxies"));
+        assert!(problems[1].starts_with("invalid value '(' for '--deny-path"));
+        assert!(problems[2].starts_with("unexpected argument '--proxy-port'"));
+        assert_eq!(problems[3], "--replay /nonexistent/replay is not a file");
+    }
+}
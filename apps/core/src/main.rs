@@ -3,76 +3,219 @@ mod net;
 mod state;
 mod statistics;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::convert::Infallible;
-use std::net::{IpAddr, SocketAddr};
-use std::sync::{Arc, Mutex};
+use std::io::IsTerminal;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
 use clap::Parser;
-use hyper::server::conn::AddrStream;
+use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Client, Server};
+use hyper::Server;
 use tokio::time;
+use tracing::info;
 
-use crate::config::Args;
+use crate::config::{validate, validate_args, Args, Command};
+use crate::net::admin::fetch_stats;
+use crate::net::blacklist::{refresh_blacklist, spawn_blacklist_refresh};
+use crate::net::client::build_client;
+use crate::net::coalesce::Coalescer;
+use crate::net::listener::{bind_listener, configure_server};
+use crate::net::probe::startup_probe;
 use crate::net::proxy::proxy;
-use crate::state::{Config, HistogramMap, LogList};
-use crate::statistics::print_histograms;
-
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
-    let config = Config {
-        blacklist: args.blacklist.clone(),
-        host: args.host.clone(),
-        interval: args.interval,
-        key: args.key.clone(),
-        monitoring: args.monitoring,
-        port: args.port,
-        proxy: args.proxy,
-        server: args.server.clone(),
+use crate::net::proxy_protocol::{ClientAcceptor, ClientStream};
+use crate::net::queue::UpstreamLimiter;
+use crate::net::replay::replay;
+use crate::net::shutdown::shutdown_signal;
+use crate::state::{
+    Blacklist, Config, GeoBlocker, HistogramMap, LogList, RecentRequests, SharedBlacklist, SharedCoalescer, SharedConnections, SharedCounters, SharedGeoBlocker, SharedLimiter, SharedRecent, SharedTalkers, SharedUpstreams
+};
+use crate::statistics::{
+    print_histograms, Counters, Reporter, RequesterCounts, TableOptions, UpstreamConnections
+};
+
+fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    let args = match Args::try_parse_from(&argv) {
+        Ok(args) => args,
+        // Rather than only the first bad option, `validate` reports all of them
+        Err(e) if e.use_stderr() && argv.iter().skip(1).any(|arg| arg == "validate") => {
+            report_problems(&validate_args(&argv))
+        }
+        Err(e) => e.exit(),
     };
+    let config = Arc::new(Config::from(&args));
+
+    if args.command == Some(Command::Validate) {
+        let problems = validate(&config);
+        if !problems.is_empty() {
+            report_problems(&problems);
+        }
+        println!("No problems found");
+        return;
+    }
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], config.proxy));
-    let client = Client::new();
+    if args.command == Some(Command::Stats) {
+        let addr =
+            config.admin_addr.clone().unwrap_or_else(|| format!("127.0.0.1:{}", config.proxy));
+        let stats = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("could not start the runtime: {}", e))
+            .and_then(|runtime| {
+                runtime.block_on(async {
+                    let client = build_client(&config, Arc::default());
+                    fetch_stats(&client, &addr, config.admin_token.as_deref()).await
+                })
+            });
+        match stats {
+            Ok(snapshot) => {
+                print_histograms(&snapshot.histograms, &TableOptions::from(&*config));
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = config.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    let runtime = match runtime.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("could not start the runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    runtime.block_on(run(config));
+}
+
+/// Prints the problems `validate` found and exits with a failure
+fn report_problems(problems: &[String]) -> ! {
+    for problem in problems {
+        eprintln!("error: {}", problem);
+    }
+    std::process::exit(1);
+}
+
+async fn run(config: Arc<Config>) {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(&config.log_level))
+        .with_ansi(!config.no_color && std::io::stdout().is_terminal())
+        .init();
+
+    let connections: SharedConnections = Arc::new(UpstreamConnections::default());
+    let upstreams = SharedUpstreams::default();
+    let client = build_client(&config, Arc::clone(&connections));
 
     // Create shared state for the histograms and log list
     let histograms: HistogramMap = Arc::new(Mutex::new(HashMap::new()));
     let loglist: LogList = Arc::new(Mutex::new(Vec::new()));
-    let blacklist: Arc<HashSet<IpAddr>> = Arc::new(config.blacklist.clone().into_iter().collect());
+    let blacklist: SharedBlacklist =
+        Arc::new(RwLock::new(Blacklist::new(&config.blacklist, Vec::new())));
+    let counters: SharedCounters = Arc::new(Counters::default());
+    let limiter: SharedLimiter = UpstreamLimiter::from_config(&config).map(Arc::new);
+    let talkers: SharedTalkers = RequesterCounts::from_config(&config).map(Arc::new);
+    let recent: SharedRecent = RecentRequests::from_config(&config).map(Arc::new);
+    let coalescer: SharedCoalescer = Coalescer::from_config(&config).map(Arc::new);
+    let geo_blocker: SharedGeoBlocker = match &config.geoip_db {
+        Some(path) => match GeoBlocker::open(path, &config.block_countries, &config.block_asn) {
+            Ok(geo_blocker) => Some(Arc::new(geo_blocker)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Warn early about a misconfigured target, requests will fail with a 502 until it resolves
+    if let Err(e) = tokio::net::lookup_host((config.host.as_str(), config.port)).await {
+        eprintln!("warning: could not resolve target host {}: {}", config.host, e);
+    }
 
-    let histograms_for_timer = Arc::clone(&histograms);
-    let loglist_for_timer = Arc::clone(&loglist);
+    if let Some(url) = &config.blacklist_url {
+        refresh_blacklist(&client, url, &config.blacklist, &blacklist).await;
+        spawn_blacklist_refresh(
+            client.clone(),
+            url.clone(),
+            config.blacklist.clone(),
+            Arc::clone(&blacklist),
+            Duration::from_secs(config.blacklist_refresh),
+        );
+    }
+
+    if config.startup_probe {
+        match startup_probe(&client, &config).await {
+            Ok(status) => println!("Startup probe of {} answered {}", config.health_path, status),
+            Err(e) => {
+                eprintln!("startup probe failed, {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &config.replay {
+        match replay(&client, &config, path).await {
+            Ok(histograms) => {
+                print_histograms(&histograms, &TableOptions::from(config.as_ref()));
+            }
+            Err(e) => {
+                eprintln!("replay failed, {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let reporter = Arc::new(Reporter::new(
+        Arc::clone(&histograms),
+        Arc::clone(&loglist),
+        Arc::clone(&counters),
+        Arc::clone(&connections),
+        Arc::clone(&upstreams),
+        talkers.clone(),
+        Arc::clone(&config),
+        client.clone(),
+    ));
+    let reporter_for_timer = Arc::clone(&reporter);
+    let interval_secs = config.interval;
 
     tokio::spawn(async move {
         // Wait for the first period before starting the timer
-        time::sleep(Duration::from_secs(config.interval)).await;
+        time::sleep(Duration::from_secs(interval_secs)).await;
 
-        let mut interval = time::interval(Duration::from_secs(config.interval));
+        let mut interval = time::interval(Duration::from_secs(interval_secs));
         loop {
             interval.tick().await;
-            let histograms = histograms_for_timer.lock().unwrap().clone();
-            print_histograms(&histograms);
-
-            // TODO: send the histograms and loglist to a monitoring service
-
-            histograms_for_timer.lock().unwrap().clear();
-            loglist_for_timer.lock().unwrap().clear();
+            reporter_for_timer.report();
         }
     });
 
-    let target_host = config.host.clone();
-    let target_port = config.port;
+    let config_for_svc = Arc::clone(&config);
+    let counters_for_shutdown = Arc::clone(&counters);
 
-    let make_svc = make_service_fn(move |conn: &AddrStream| {
+    let make_svc = make_service_fn(move |conn: &ClientStream| {
         let client = client.clone();
         let requester_ip = conn.remote_addr();
         let histograms = Arc::clone(&histograms);
         let loglist = Arc::clone(&loglist);
-        let target_host = target_host.clone();
-        let target_port = target_port;
+        let config = Arc::clone(&config_for_svc);
         let blacklist = Arc::clone(&blacklist);
+        let geo_blocker = geo_blocker.clone();
+        let limiter = limiter.clone();
+        let counters = Arc::clone(&counters);
+        let talkers = talkers.clone();
+        let recent = recent.clone();
+        let coalescer = coalescer.clone();
+        let upstreams = Arc::clone(&upstreams);
 
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
@@ -82,20 +225,89 @@ async fn main() {
                     requester_ip,
                     Arc::clone(&histograms),
                     Arc::clone(&loglist),
-                    target_host.clone(),
-                    target_port,
+                    Arc::clone(&config),
                     Arc::clone(&blacklist),
+                    geo_blocker.clone(),
+                    limiter.clone(),
+                    Arc::clone(&counters),
+                    talkers.clone(),
+                    recent.clone(),
+                    coalescer.clone(),
+                    Arc::clone(&upstreams),
                 )
             }))
         }
     });
 
-    let server = Server::bind(&addr).serve(make_svc);
+    let listener = bind_listener(&config).and_then(tokio::net::TcpListener::from_std);
+    let incoming = match listener.map(AddrIncoming::from_listener) {
+        Ok(Ok(incoming)) => incoming,
+        Ok(Err(e)) => {
+            eprintln!("could not listen for connections: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("could not listen for connections: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let addr = incoming.local_addr();
+    let builder = Server::builder(ClientAcceptor::new(incoming, config.proxy_protocol));
+    let server = configure_server(builder, &config).serve(make_svc);
 
     println!("Proxy server running on http://{}", addr);
     println!("Forwarding traffic to http://{}:{}", config.host, config.port);
 
-    if let Err(e) = server.await {
+    // Stop accepting connections on shutdown, then give open requests until the grace deadline
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = server.with_graceful_shutdown(async {
+        stop_rx.await.ok();
+    });
+    tokio::pin!(server);
+
+    let result = tokio::select! {
+        result = &mut server => result,
+        _ = shutdown_signal() => {
+            let grace = Duration::from_millis(config.shutdown_grace_ms);
+            let draining = time::Instant::now();
+            let deadline = draining + grace;
+            let in_flight = counters_for_shutdown.in_flight.load(Ordering::Relaxed);
+            println!(
+                "Shutting down with {} requests in flight, waiting up to {:?} for them",
+                in_flight, grace
+            );
+            stop_tx.send(()).ok();
+
+            let result = time::timeout_at(deadline, &mut server).await;
+            let drained = draining.elapsed();
+            let left_open = counters_for_shutdown.in_flight.load(Ordering::Relaxed);
+            if result.is_err() {
+                eprintln!(
+                    "warning: {} requests still open after the shutdown grace period",
+                    left_open
+                );
+            } else {
+                println!("Drained {} requests in flight in {:?}", in_flight, drained);
+            }
+            info!(
+                target: "narrow::shutdown",
+                in_flight,
+                left_open,
+                drain_ms = drained.as_secs_f64() * 1000.0,
+                "drained"
+            );
+
+            // Flush the statistics of the partial interval so they aren't lost
+            let (_, exports) = reporter.report();
+            if time::timeout_at(deadline, exports).await.is_err() {
+                eprintln!("warning: could not finish exporting statistics before shutting down");
+            }
+
+            result.unwrap_or(Ok(()))
+        }
+    };
+
+    if let Err(e) = result {
         eprintln!("server error: {}", e);
     }
 }
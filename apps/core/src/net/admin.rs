@@ -0,0 +1,223 @@
+use std::net::IpAddr;
+use std::sync::PoisonError;
+
+use chrono::Utc;
+use hyper::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::Value;
+
+use crate::state::{Config, HistogramMap, HttpClient, RecentRequests};
+use crate::statistics::{upstreams_json, StatsSnapshot, UpstreamCounts};
+
+/// Where `--recent-buffer` serves the last forwarded requests
+pub const RECENT_PATH: &str = "/__narrow/recent";
+
+/// Where the histograms recorded so far this interval are served with `--serve-stats`, for
+/// `narrow stats`
+pub const STATS_PATH: &str = "/__narrow/stats";
+
+/// The admin API shows other clients' requests, so it only answers to `--admin-allow`, or on
+/// loopback when not given. A trusted proxy relays requests from anyone, so without the allow list
+/// it is refused even on loopback. With `--admin-token` the request must carry it as a bearer
+/// token as well. `Err` has the status to refuse the request with.
+pub fn admin_access(config: &Config, ip: &IpAddr, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let allowed = match config.admin_allow.as_slice() {
+        [] => ip.is_loopback() && !config.is_trusted_proxy(ip),
+        allow => allow.iter().any(|net| net.contains(ip)),
+    };
+    if !allowed {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if let Some(token) = &config.admin_token {
+        let given = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !given.is_some_and(|given| same_token(given.as_bytes(), token.as_bytes())) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    Ok(())
+}
+
+/// Compare without returning early, so the time taken doesn't tell how much of a guess matched
+fn same_token(given: &[u8], token: &[u8]) -> bool {
+    given.len() == token.len()
+        && given.iter().zip(token).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// `GET /__narrow/recent`, the kept requests as JSON
+pub fn recent_response(recent: &RecentRequests, method: &Method) -> Response<Body> {
+    json_response(method, || recent.to_json())
+}
+
+/// `GET /__narrow/stats`, the current interval's histograms in the `--stats-stream` format. Every
+/// endpoint also gets the time of its `last_request` and its `generation`, which changes whenever
+/// the counts were cleared between two reads, and the requests per upstream are under `upstreams`.
+/// Nothing is reset, so this doesn't take anything away from the printed interval.
+pub fn stats_response(
+    histograms: &HistogramMap,
+    upstreams: &UpstreamCounts,
+    config: &Config,
+    method: &Method,
+) -> Response<Body> {
+    json_response(method, || {
+        let snapshot = StatsSnapshot {
+            histograms: histograms.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+        };
+        let mut record = snapshot.to_json(Utc::now(), &config.percentiles);
+        for (endpoint, hist) in &snapshot.histograms {
+            if let Some(at) = hist.last_request_time {
+                record["endpoints"][endpoint]["last_request"] = at.to_rfc3339().into();
+            }
+            record["endpoints"][endpoint]["generation"] = hist.generation.into();
+        }
+        record["upstreams"] = upstreams_json(&upstreams.current());
+        record
+    })
+}
+
+fn json_response(method: &Method, json: impl FnOnce() -> Value) -> Response<Body> {
+    if method != Method::GET && method != Method::HEAD {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("Method not allowed"))
+            .unwrap();
+    }
+
+    let body = match method {
+        &Method::HEAD => Body::empty(),
+        _ => Body::from(json().to_string()),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .unwrap()
+}
+
+/// Read the current histograms from the admin API of the proxy at `addr`, with `--admin-token`
+/// as the bearer token when given
+pub async fn fetch_stats(
+    client: &HttpClient,
+    addr: &str,
+    token: Option<&str>,
+) -> Result<StatsSnapshot, String> {
+    let url = format!("http://{}{}", addr, STATS_PATH);
+    let mut req = Request::get(&url);
+    if let Some(token) = token {
+        req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let req =
+        req.body(Body::empty()).map_err(|e| format!("invalid admin address {}: {}", addr, e))?;
+
+    let resp = client.request(req).await.map_err(|e| format!("could not reach {}: {}", addr, e))?;
+    if !resp.status().is_success() {
+        return Err(format!("{} returned {}", url, resp.status()));
+    }
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| format!("could not read the stats from {}: {}", addr, e))?;
+    let record = serde_json::from_slice::<Value>(&body)
+        .map_err(|e| format!("invalid stats from {}: {}", addr, e))?;
+
+    StatsSnapshot::from_json(&record)
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::client::build_client;
+    use crate::net::mock::MockUpstream;
+    use crate::state::Log;
+    use crate::statistics::Histogram;
+
+    fn access(config: &Config, ip: &str, authorization: Option<&str>) -> Result<(), StatusCode> {
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        }
+        admin_access(config, &ip.parse().unwrap(), &headers)
+    }
+
+    #[test]
+    fn test_admin_access() {
+        let config =
+            Config::from(&Args::parse_from(["test", "--trusted-proxies", "10.0.0.0/8,::1/128"]));
+        assert_eq!(access(&config, "127.0.0.1", None), Ok(()));
+        assert_eq!(access(&config, "::1", None), Err(StatusCode::FORBIDDEN));
+        assert_eq!(access(&config, "10.1.2.3", None), Err(StatusCode::FORBIDDEN));
+        assert_eq!(access(&config, "192.168.1.1", None), Err(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn test_admin_access_with_allow_list_and_token() {
+        let args = ["test", "--admin-allow", "192.168.0.0/16", "--admin-token", "s3cret"];
+        let config = Config::from(&Args::parse_from(args));
+        assert_eq!(access(&config, "192.168.1.1", Some("Bearer s3cret")), Ok(()));
+        assert_eq!(access(&config, "127.0.0.1", Some("Bearer s3cret")), Err(StatusCode::FORBIDDEN));
+        assert_eq!(access(&config, "192.168.1.1", None), Err(StatusCode::UNAUTHORIZED));
+        let wrong = Some("Bearer s3cre");
+        assert_eq!(access(&config, "192.168.1.1", wrong), Err(StatusCode::UNAUTHORIZED));
+        let basic = Some("Basic s3cret");
+        assert_eq!(access(&config, "192.168.1.1", basic), Err(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_recent_response() {
+        let recent = RecentRequests::new(5);
+        recent.push(Log { req_uri: "/items".to_string(), ..Default::default() });
+
+        let resp = recent_response(&recent, &Method::GET);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(json["requests"][0]["uri"], "/items");
+
+        let head = recent_response(&recent, &Method::HEAD);
+        assert!(hyper::body::to_bytes(head.into_body()).await.unwrap().is_empty());
+        assert_eq!(
+            recent_response(&recent, &Method::DELETE).status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stats() {
+        let mut hist = Histogram::default();
+        hist.add(Duration::from_millis(120), Utc::now());
+        let histograms = Arc::new(Mutex::new(HashMap::from([("/api".to_string(), hist)])));
+        let config = Config::from(&Args::parse_from(["test"]));
+        let upstreams = UpstreamCounts::default();
+        upstreams.add("127.0.0.1:3000", 200, false);
+        let resp = stats_response(&histograms, &upstreams, &config, &Method::GET);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        // The mock only serves static bodies
+        let body = String::from_utf8(body.to_vec()).unwrap().leak();
+
+        let admin = MockUpstream::start(StatusCode::OK, body, Duration::ZERO).await;
+        let client = build_client(&config, Arc::default());
+        let addr = admin.addr.to_string();
+        let snapshot = fetch_stats(&client, &addr, Some("s3cret")).await.unwrap();
+        let api = &snapshot.histograms["/api"];
+        assert_eq!((api.count_101_250, api.total_requests), (1, 1));
+        assert!(api.last_request_time.is_some());
+        let requests = admin.requests();
+        assert_eq!(requests[0].uri, STATS_PATH);
+        assert_eq!(requests[0].headers[AUTHORIZATION], "Bearer s3cret");
+
+        let refusing = MockUpstream::start(StatusCode::FORBIDDEN, "", Duration::ZERO).await;
+        assert!(fetch_stats(&client, &refusing.addr.to_string(), None).await.is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use hyper::Uri;
+use ipnet::IpNet;
+use tokio::time;
+
+use crate::state::{parse_blacklist, Blacklist, HttpClient, SharedBlacklist};
+
+/// How long fetching the blacklist may take, so a server that never answers can't hold up
+/// startup or the refreshes
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fetch a newline-delimited IP/CIDR list from `url`, giving up after `timeout`
+pub async fn fetch_blacklist(
+    client: &HttpClient,
+    url: &str,
+    timeout: Duration,
+) -> Result<Vec<IpNet>, String> {
+    let uri = url.parse::<Uri>().map_err(|e| format!("invalid blacklist URL {}: {}", url, e))?;
+    let fetch = async {
+        let resp = client.get(uri).await.map_err(|e| format!("could not fetch {}: {}", url, e))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("could not fetch {}: upstream returned {}", url, resp.status()));
+        }
+
+        hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(|e| format!("could not read {}: {}", url, e))
+    };
+    let body = time::timeout(timeout, fetch)
+        .await
+        .map_err(|_| format!("could not fetch {}: timed out after {:?}", url, timeout))??;
+    let (nets, invalid) = parse_blacklist(&String::from_utf8_lossy(&body));
+    for entry in invalid {
+        eprintln!("warning: ignoring invalid blacklist entry {:?} from {}", entry, url);
+    }
+
+    Ok(nets)
+}
+
+/// Fetch the list at `url` and swap it into `blacklist` alongside the static `ips`. On failure,
+/// including a fetch that takes too long, the current list is kept.
+pub async fn refresh_blacklist(
+    client: &HttpClient,
+    url: &str,
+    ips: &[IpAddr],
+    blacklist: &SharedBlacklist,
+) {
+    match fetch_blacklist(client, url, FETCH_TIMEOUT).await {
+        Ok(nets) => *blacklist.write().unwrap() = Blacklist::new(ips, nets),
+        Err(e) => eprintln!("warning: keeping the last good blacklist, {}", e),
+    }
+}
+
+/// Refresh the blacklist from `url` every `every`
+pub fn spawn_blacklist_refresh(
+    client: HttpClient,
+    url: String,
+    ips: Vec<IpAddr>,
+    blacklist: SharedBlacklist,
+    every: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(every);
+        // The first tick completes immediately and the list was fetched at startup
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            refresh_blacklist(&client, &url, &ips, &blacklist).await;
+        }
+    });
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, RwLock};
+
+    use hyper::{Client, StatusCode};
+
+    use super::*;
+    use crate::net::client::CountingConnector;
+    use crate::net::mock::MockUpstream;
+
+    #[tokio::test]
+    async fn test_refresh_blacklist() {
+        let client = Client::builder().build(CountingConnector::default());
+        let ips: Vec<IpAddr> = vec!["1.1.1.1".parse().unwrap()];
+        let blacklist: SharedBlacklist = Arc::new(RwLock::new(Blacklist::new(&ips, vec![])));
+
+        let list = MockUpstream::start(StatusCode::OK, "10.0.0.0/8\n", Duration::ZERO).await;
+        let url = format!("http://{}/blacklist.txt", list.addr);
+        refresh_blacklist(&client, &url, &ips, &blacklist).await;
+
+        assert!(blacklist.read().unwrap().contains(&"1.1.1.1".parse().unwrap()));
+        assert!(blacklist.read().unwrap().contains(&"10.1.2.3".parse().unwrap()));
+
+        // A failing fetch keeps the previous list
+        let broken = MockUpstream::start(StatusCode::NOT_FOUND, "", Duration::ZERO).await;
+        let url = format!("http://{}/blacklist.txt", broken.addr);
+        refresh_blacklist(&client, &url, &ips, &blacklist).await;
+
+        assert!(blacklist.read().unwrap().contains(&"10.1.2.3".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_blacklist_times_out() {
+        let client = Client::builder().build(CountingConnector::default());
+        let hung =
+            MockUpstream::start(StatusCode::OK, "10.0.0.0/8\n", Duration::from_secs(5)).await;
+        let url = format!("http://{}/blacklist.txt", hung.addr);
+
+        let fetched = fetch_blacklist(&client, &url, Duration::from_millis(50)).await;
+        assert!(fetched.unwrap_err().contains("timed out"));
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use hyper::header::HeaderMap;
+use hyper::{Body, Request, Response};
+use uuid::Uuid;
+
+/// Whether to capture this request, true for about `rate` of all requests
+pub fn sampled(rate: f64) -> bool {
+    // The first 32 bits of a v4 UUID are all random
+    rate > 0.0 && (Uuid::new_v4().as_u128() >> 96) as f64 / 2f64.powi(32) < rate
+}
+
+/// A request sampled for `--capture-dir` and the response to it, kept up to `max_bytes` of each
+/// body. They are copied while streaming through the proxy and written to `<request ID>.http`
+/// once neither body is used any longer.
+pub struct Capture {
+    path: PathBuf,
+    max_bytes: usize,
+    redacted: Vec<String>,
+    record: Mutex<CaptureRecord>,
+}
+
+#[derive(Default)]
+struct CaptureRecord {
+    request: CapturedMessage,
+    response: Option<CapturedMessage>,
+}
+
+#[derive(Default)]
+struct CapturedMessage {
+    head: String,
+    body: Vec<u8>,
+    truncated: bool,
+}
+
+impl Capture {
+    /// Start capturing the request `req`, with the values of the `redacted` headers hidden
+    pub fn start<B>(
+        dir: &Path,
+        request_id: &str,
+        max_bytes: usize,
+        redacted: Vec<String>,
+        req: &Request<B>,
+    ) -> Arc<Capture> {
+        let capture = Capture {
+            path: dir.join(format!("{}.http", file_stem(request_id))),
+            max_bytes,
+            redacted,
+            record: Mutex::new(CaptureRecord::default()),
+        };
+        let head = format!("{} {} {:?}\n", req.method(), req.uri(), req.version());
+        capture.record.lock().unwrap().request.head = head + &capture.headers(req.headers());
+
+        Arc::new(capture)
+    }
+
+    /// Copy the request body as it is forwarded
+    pub fn request_body(self: &Arc<Self>, body: Body) -> Body {
+        self.tee(body, |record| &mut record.request)
+    }
+
+    /// Record the response to the client and copy its body as it is sent
+    pub fn response(self: &Arc<Self>, resp: Response<Body>) -> Response<Body> {
+        let (parts, body) = resp.into_parts();
+        let head =
+            format!("{:?} {}\n", parts.version, parts.status) + &self.headers(&parts.headers);
+        self.record.lock().unwrap().response = Some(CapturedMessage { head, ..Default::default() });
+
+        let body = self.tee(body, |record| record.response.get_or_insert_with(Default::default));
+        Response::from_parts(parts, body)
+    }
+
+    fn tee(
+        self: &Arc<Self>,
+        body: Body,
+        message: fn(&mut CaptureRecord) -> &mut CapturedMessage,
+    ) -> Body {
+        let capture = Arc::clone(self);
+        Body::wrap_stream(body.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                let mut record = capture.record.lock().unwrap();
+                let message = message(&mut record);
+                let room = capture.max_bytes.saturating_sub(message.body.len());
+                message.truncated |= chunk.len() > room;
+                message.body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            }
+        }))
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> String {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let redacted = self.redacted.iter().any(|r| name.as_str().eq_ignore_ascii_case(r));
+                match redacted {
+                    true => format!("{}: [redacted]\n", name),
+                    false => format!("{}: {}\n", name, String::from_utf8_lossy(value.as_bytes())),
+                }
+            })
+            .collect()
+    }
+
+    /// The capture as written to its file
+    fn contents(&self) -> Vec<u8> {
+        let record = self.record.lock().unwrap();
+        let mut contents = record.request.to_bytes();
+        contents.extend_from_slice(b"\n");
+        match &record.response {
+            Some(response) => contents.extend(response.to_bytes()),
+            None => contents.extend_from_slice(b"(no response from the target server)\n"),
+        }
+
+        contents
+    }
+}
+
+impl CapturedMessage {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{}\n", self.head).into_bytes();
+        bytes.extend_from_slice(&self.body);
+        if self.truncated {
+            bytes.extend(format!("\n(truncated after {} bytes)", self.body.len()).into_bytes());
+        }
+        if !self.body.is_empty() {
+            bytes.push(b'\n');
+        }
+
+        bytes
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        let contents = self.contents();
+        tokio::spawn(async move {
+            if let Some(dir) = path.parent() {
+                let _ = tokio::fs::create_dir_all(dir).await;
+            }
+            if let Err(e) = tokio::fs::write(&path, contents).await {
+                eprintln!("Could not write capture {}: {}", path.display(), e);
+            }
+        });
+    }
+}
+
+/// The request ID as a file name, client-supplied IDs may contain anything
+fn file_stem(request_id: &str) -> String {
+    request_id
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sampled() {
+        assert!(!sampled(0.0));
+        assert!(sampled(1.0));
+        assert!((0..1000).filter(|_| sampled(0.5)).count().abs_diff(500) < 100);
+    }
+
+    #[tokio::test]
+    async fn test_capture() {
+        let dir = std::env::temp_dir().join(format!("narrow-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let req = Request::post("/orders")
+            .header("authorization", "Bearer secret")
+            .header("content-type", "text/plain")
+            .body(())
+            .unwrap();
+        let capture = Capture::start(&dir, "../id 1", 8, vec!["Authorization".to_string()], &req);
+
+        let body = capture.request_body(Body::from("0123456789"));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "0123456789");
+        let resp = capture.response(Response::new(Body::from("ok")));
+        drop(capture);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "ok");
+
+        let path = dir.join("___id_1.http");
+        // Written in the background once the last body is dropped
+        for _ in 0..100 {
+            if std::fs::read_to_string(&path).is_ok_and(|contents| contents.ends_with("ok\n")) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "POST /orders HTTP/1.1\nauthorization: [redacted]\ncontent-type: text/plain\n\n\
+             01234567\n(truncated after 8 bytes)\n\nHTTP/1.1 200 OK\n\nok\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
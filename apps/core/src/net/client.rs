@@ -0,0 +1,279 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper::client::connect::{capture_connection, Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Body, Client, Request, Response, Uri};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::net::dns::{spawn_dns_refresh, CachingResolver, DnsCache, StaleAddress};
+use crate::state::{Config, HttpClient, SharedConnections};
+
+/// The client used to forward requests, connecting with `--connect-timeout-ms`, probing idle
+/// connections after `--upstream-tcp-keepalive-ms` and counting its connections in `connections`.
+/// With `--dns-refresh-ms` it caches the addresses of the target server and refreshes them in the
+/// background, which needs a running runtime.
+pub fn build_client(config: &Config, connections: SharedConnections) -> HttpClient {
+    let dns = config.dns_refresh_ms.map(|ms| {
+        let cache = Arc::new(DnsCache::default());
+        spawn_dns_refresh(&cache, Duration::from_millis(ms));
+        cache
+    });
+    let mut connector = HttpConnector::new_with_resolver(CachingResolver { cache: dns.clone() });
+    let mut builder = Client::builder();
+    // Connections in use are dropped once their address goes stale, idle ones have to expire
+    if let Some(ms) = config.dns_refresh_ms {
+        builder.pool_idle_timeout(Duration::from_millis(ms));
+    }
+    connector.set_connect_timeout(
+        Some(Duration::from_millis(config.connect_timeout_ms)).filter(|t| !t.is_zero()),
+    );
+    connector.set_keepalive(
+        Some(Duration::from_millis(config.upstream_tcp_keepalive_ms)).filter(|t| !t.is_zero()),
+    );
+
+    builder.build(CountingConnector { inner: connector, connections, dns })
+}
+
+/// Send `req` like `client.request`, but with `--dns-refresh-ms` keep the connection it went over
+/// from being reused once its address no longer resolves
+pub async fn send_request(
+    client: &HttpClient,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let captured = capture_connection(&mut req);
+    let resp = client.request(req).await?;
+    if resp.extensions().get::<StaleAddress>().is_some_and(StaleAddress::is_stale) {
+        if let Some(connected) = captured.connection_metadata().as_ref() {
+            connected.poison();
+        }
+    }
+
+    Ok(resp)
+}
+
+/// How long a connection took to establish. Responses carry it in their extensions, only the
+/// first one to `take` it gets the time, the others reused the connection.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectTime(Arc<Mutex<Option<Duration>>>);
+
+impl ConnectTime {
+    pub fn take(&self) -> Duration {
+        self.0.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+/// Connects like `HttpConnector`, keeping track of how many connections are open to each host
+/// since hyper's pool doesn't tell
+#[derive(Clone)]
+pub struct CountingConnector {
+    inner: HttpConnector<CachingResolver>,
+    connections: SharedConnections,
+    dns: Option<Arc<DnsCache>>,
+}
+
+impl Default for CountingConnector {
+    fn default() -> Self {
+        CountingConnector {
+            inner: HttpConnector::new_with_resolver(CachingResolver::default()),
+            connections: Arc::default(),
+            dns: None,
+        }
+    }
+}
+
+impl Service<Uri> for CountingConnector {
+    type Response = CountedStream;
+    type Error = <HttpConnector<CachingResolver> as Service<Uri>>::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<CountedStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let default_port = if uri.scheme_str() == Some("https") { 443 } else { 80 };
+        let name = uri.host().unwrap_or_default().to_string();
+        let host = format!("{}:{}", name, uri.port_u16().unwrap_or(default_port));
+        let connecting = self.inner.call(uri);
+        let connections = Arc::clone(&self.connections);
+        let dns = self.dns.clone();
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let stream = connecting.await?;
+            let connect_time = ConnectTime(Arc::new(Mutex::new(Some(start.elapsed()))));
+            connections.opened(&host);
+            let dns = match (dns, stream.peer_addr()) {
+                (Some(cache), Ok(peer)) => {
+                    let id = cache.connection_id();
+                    let stale = cache.track(id, &name, peer.ip());
+                    Some((cache, id, stale))
+                }
+                _ => None,
+            };
+            Ok(CountedStream { stream, host, connections, dns, connect_time })
+        })
+    }
+}
+
+/// A connection that is counted as closed once hyper drops it
+pub struct CountedStream {
+    stream: TcpStream,
+    host: String,
+    connections: SharedConnections,
+    dns: Option<(Arc<DnsCache>, u64, StaleAddress)>,
+    connect_time: ConnectTime,
+}
+
+impl Drop for CountedStream {
+    fn drop(&mut self) {
+        self.connections.closed(&self.host);
+        if let Some((cache, id, _)) = &self.dns {
+            cache.untrack(*id);
+        }
+    }
+}
+
+impl Connection for CountedStream {
+    fn connected(&self) -> Connected {
+        let connected = self.stream.connected().extra(self.connect_time.clone());
+        match &self.dns {
+            Some((_, _, stale)) => connected.extra(stale.clone()),
+            None => connected,
+        }
+    }
+}
+
+impl AsyncRead for CountedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CountedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use clap::Parser;
+    use hyper::body::to_bytes;
+    use hyper::StatusCode;
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::mock::MockUpstream;
+    use crate::statistics::HostConnections;
+
+    #[tokio::test]
+    async fn test_client_counts_connections() {
+        let upstream = MockUpstream::start(StatusCode::OK, "ok", Duration::ZERO).await;
+        let host = upstream.addr.to_string();
+        let connections = SharedConnections::default();
+        let client =
+            build_client(&Config::from(&Args::parse_from(["test"])), Arc::clone(&connections));
+
+        // Two requests at once need two connections, which are then kept idle in the pool
+        let uri = format!("http://{}/", host).parse::<Uri>().unwrap();
+        let (a, b) = tokio::join!(client.get(uri.clone()), client.get(uri.clone()));
+        to_bytes(a.unwrap().into_body()).await.unwrap();
+        to_bytes(b.unwrap().into_body()).await.unwrap();
+        to_bytes(client.get(uri).await.unwrap().into_body()).await.unwrap();
+
+        assert_eq!(
+            connections.take(),
+            vec![(host.clone(), HostConnections { open: 2, peak: 2, opened: 2 })]
+        );
+
+        drop(client);
+        for _ in 0..100 {
+            if connections.take().is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("connections were not closed");
+    }
+
+    #[tokio::test]
+    async fn test_client_drops_connections_to_stale_addresses() {
+        let upstream = MockUpstream::start(StatusCode::OK, "ok", Duration::ZERO).await;
+        let host = format!("localhost:{}", upstream.addr.port());
+        let connections = SharedConnections::default();
+        let cache = Arc::new(DnsCache::default());
+        cache.update("localhost", vec!["127.0.0.1".parse().unwrap()]);
+        let client = Client::builder().build(CountingConnector {
+            inner: HttpConnector::new_with_resolver(CachingResolver { cache: Some(cache.clone()) }),
+            connections: Arc::clone(&connections),
+            dns: Some(Arc::clone(&cache)),
+        });
+        let get = || async {
+            let req = Request::get(format!("http://{}/", host)).body(Body::empty()).unwrap();
+            to_bytes(send_request(&client, req).await.unwrap().into_body()).await.unwrap();
+        };
+
+        get().await;
+        get().await;
+        assert_eq!(connections.take()[0].1.opened, 1);
+
+        // The address disappears and comes back, the connection stays stale and is used once more
+        cache.update("localhost", vec!["10.0.0.1".parse().unwrap()]);
+        cache.update("localhost", vec!["127.0.0.1".parse().unwrap()]);
+        get().await;
+        get().await;
+        assert_eq!(connections.take()[0].1.opened, 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_reports_connect_time_once() {
+        let upstream = MockUpstream::start(StatusCode::OK, "ok", Duration::ZERO).await;
+        let client = build_client(&Config::from(&Args::parse_from(["test"])), Arc::default());
+        let uri = format!("http://{}/", upstream.addr).parse::<Uri>().unwrap();
+
+        let first = client.get(uri.clone()).await.unwrap();
+        let connect_time = first.extensions().get::<ConnectTime>().unwrap().clone();
+        to_bytes(first.into_body()).await.unwrap();
+        let second = client.get(uri).await.unwrap();
+        assert!(connect_time.take() > Duration::ZERO);
+        assert_eq!(second.extensions().get::<ConnectTime>().unwrap().take(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures_util::{stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::header::{
+    HeaderMap, HeaderName, ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, AUTHORIZATION, CACHE_CONTROL, COOKIE, SET_COOKIE, VARY
+};
+use hyper::{Body, Method, Response, StatusCode, Uri, Version};
+use tokio::sync::watch;
+
+use crate::state::Config;
+
+/// Responses larger than this are passed on to the request that fetched them only
+const MAX_SHARED_BYTES: usize = 1024 * 1024;
+
+/// The request headers that have to match for requests to count as identical
+const KEY_HEADERS: [HeaderName; 3] = [ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE];
+
+/// Single-flight for `--coalesce`: identical GETs arriving while one of them is still being
+/// fetched wait for that one and get a copy of its response.
+///
+/// Only requests that can't depend on who sent them are coalesced: GETs without credentials
+/// (`Authorization` or `Cookie`). They count as identical when the forwarded URI and the `Accept`,
+/// `Accept-Encoding` and `Accept-Language` headers match. Only `200 OK` responses without
+/// `Set-Cookie`, `Cache-Control: private` or `no-store`, that `Vary` on none but those three
+/// headers and with bodies up to 1 MiB are shared, otherwise every waiting request is forwarded
+/// on its own after all.
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    flights: Mutex<HashMap<String, watch::Receiver<Option<Arc<SharedResponse>>>>>,
+}
+
+/// The part a request plays in its flight
+pub enum Flight {
+    /// The first request, which fetches the response for everyone
+    Leader(Leader),
+
+    /// A later one, waiting for the leader's response
+    Follower(Follower),
+}
+
+pub struct Follower {
+    receiver: watch::Receiver<Option<Arc<SharedResponse>>>,
+}
+
+/// Ends the flight once dropped, so the next identical request starts a new one. Followers that
+/// got no response by then forward their requests themselves.
+pub struct Leader {
+    key: String,
+    coalescer: Arc<Coalescer>,
+    sender: watch::Sender<Option<Arc<SharedResponse>>>,
+}
+
+/// A response read to the end, to be handed out to every request of a flight
+#[derive(Debug)]
+pub struct SharedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Coalescer {
+    /// The coalescer for `--coalesce`, if it is enabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.coalesce.then(Coalescer::default)
+    }
+
+    /// Join the flight of an identical request, or start one. `None` for requests that must not
+    /// be coalesced.
+    pub fn join(
+        self: &Arc<Self>,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+    ) -> Option<Flight> {
+        if method != Method::GET
+            || headers.contains_key(AUTHORIZATION)
+            || headers.contains_key(COOKIE)
+        {
+            return None;
+        }
+
+        let mut key = uri.to_string();
+        for name in KEY_HEADERS {
+            for value in headers.get_all(&name) {
+                key.push('\n');
+                key.push_str(name.as_str());
+                key.push(':');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+
+        let mut flights = self.flights.lock().unwrap();
+        if let Some(receiver) = flights.get(&key) {
+            return Some(Flight::Follower(Follower { receiver: receiver.clone() }));
+        }
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(key.clone(), receiver);
+
+        Some(Flight::Leader(Leader { key, coalescer: Arc::clone(self), sender }))
+    }
+}
+
+impl Follower {
+    /// Wait for the leader's response, `None` if it isn't shared
+    pub async fn shared(mut self) -> Option<Arc<SharedResponse>> {
+        let shared = self.receiver.wait_for(Option::is_some).await.ok()?;
+        shared.clone()
+    }
+}
+
+impl Leader {
+    /// Share `resp` with the followers if it may be, returning the response for the leader itself
+    pub async fn share(self, resp: Response<Body>) -> Result<Response<Body>, hyper::Error> {
+        if !shareable(&resp) {
+            return Ok(resp);
+        }
+
+        let (parts, mut body) = resp.into_parts();
+        let mut buffered = Vec::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            if buffered.len() + chunk.len() > MAX_SHARED_BYTES {
+                // Too large to keep, stream the rest through to the leader only
+                let read = stream::iter([Ok(Bytes::from(buffered)), Ok(chunk)]);
+                return Ok(Response::from_parts(parts, Body::wrap_stream(read.chain(body))));
+            }
+            buffered.extend_from_slice(&chunk);
+        }
+
+        let shared = SharedResponse {
+            status: parts.status,
+            version: parts.version,
+            headers: parts.headers,
+            body: buffered.into(),
+        };
+        let resp = shared.to_response();
+        self.sender.send_replace(Some(Arc::new(shared)));
+
+        Ok(resp)
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.coalescer.flights.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl SharedResponse {
+    pub fn to_response(&self) -> Response<Body> {
+        let mut resp = Response::new(Body::from(self.body.clone()));
+        *resp.status_mut() = self.status;
+        *resp.version_mut() = self.version;
+        *resp.headers_mut() = self.headers.clone();
+        resp
+    }
+}
+
+/// Whether the response is the same for anyone asking
+fn shareable(resp: &Response<Body>) -> bool {
+    let private = resp.headers().get_all(CACHE_CONTROL).iter().any(|value| {
+        let value = String::from_utf8_lossy(value.as_bytes()).to_ascii_lowercase();
+        value.split(',').map(str::trim).any(|directive| {
+            directive == "no-store" || directive == "private" || directive.starts_with("private=")
+        })
+    });
+
+    // The followers only sent the same headers the key is made of
+    let varies = resp.headers().get_all(VARY).iter().any(|value| {
+        String::from_utf8_lossy(value.as_bytes()).split(',').map(str::trim).any(|name| {
+            !name.is_empty()
+                && !KEY_HEADERS.iter().any(|key| key.as_str().eq_ignore_ascii_case(name))
+        })
+    });
+
+    resp.status() == StatusCode::OK
+        && !resp.headers().contains_key(SET_COOKIE)
+        && !private
+        && !varies
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use hyper::Request;
+
+    use super::*;
+
+    fn join(coalescer: &Arc<Coalescer>, req: &Request<()>) -> Option<Flight> {
+        coalescer.join(req.method(), req.uri(), req.headers())
+    }
+
+    #[tokio::test]
+    async fn test_coalescer() {
+        let coalescer = Arc::new(Coalescer::default());
+        let req = Request::get("http://upstream/items").body(()).unwrap();
+
+        let Some(Flight::Leader(leader)) = join(&coalescer, &req) else {
+            panic!("the first request leads");
+        };
+        let Some(Flight::Follower(follower)) = join(&coalescer, &req) else {
+            panic!("an identical request follows");
+        };
+        let gzip = Request::get("http://upstream/items").header("accept-encoding", "gzip");
+        assert!(matches!(join(&coalescer, &gzip.body(()).unwrap()), Some(Flight::Leader(_))));
+        let post = Request::post("http://upstream/items").body(()).unwrap();
+        assert!(join(&coalescer, &post).is_none());
+        let auth = Request::get("http://upstream/items").header("authorization", "Bearer a");
+        assert!(join(&coalescer, &auth.body(()).unwrap()).is_none());
+
+        let resp = Response::builder().header("etag", "1").body(Body::from("items")).unwrap();
+        let resp = leader.share(resp).await.unwrap();
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "items");
+        let shared = follower.shared().await.unwrap().to_response();
+        assert_eq!(shared.headers()["etag"], "1");
+        assert_eq!(hyper::body::to_bytes(shared.into_body()).await.unwrap(), "items");
+
+        // The flight is over, the next request fetches again
+        assert!(matches!(join(&coalescer, &req), Some(Flight::Leader(_))));
+    }
+
+    #[tokio::test]
+    async fn test_coalescer_does_not_share_private_responses() {
+        let coalescer = Arc::new(Coalescer::default());
+        let req = Request::get("http://upstream/me").body(()).unwrap();
+        let Some(Flight::Leader(leader)) = join(&coalescer, &req) else {
+            panic!("the first request leads");
+        };
+        let Some(Flight::Follower(follower)) = join(&coalescer, &req) else {
+            panic!("an identical request follows");
+        };
+
+        let resp = Response::builder().header("cache-control", "max-age=60, Private");
+        let resp = leader.share(resp.body(Body::from("me")).unwrap()).await.unwrap();
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "me");
+        assert!(follower.shared().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_coalescer_does_not_share_varying_responses() {
+        let coalescer = Arc::new(Coalescer::default());
+        let req = Request::get("http://upstream/cors").body(()).unwrap();
+        for (vary, shared) in
+            [("Accept-Encoding, accept", true), ("Accept-Encoding, Origin", false)]
+        {
+            let Some(Flight::Leader(leader)) = join(&coalescer, &req) else {
+                panic!("the first request leads");
+            };
+            let Some(Flight::Follower(follower)) = join(&coalescer, &req) else {
+                panic!("an identical request follows");
+            };
+
+            let resp = Response::builder().header("vary", vary).body(Body::empty()).unwrap();
+            leader.share(resp).await.unwrap();
+            assert_eq!(follower.shared().await.is_some(), shared);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coalescer_does_not_share_large_responses() {
+        let coalescer = Arc::new(Coalescer::default());
+        let req = Request::get("http://upstream/large").body(()).unwrap();
+        let Some(Flight::Leader(leader)) = join(&coalescer, &req) else {
+            panic!("the first request leads");
+        };
+        let Some(Flight::Follower(follower)) = join(&coalescer, &req) else {
+            panic!("an identical request follows");
+        };
+
+        let chunks = ["a".repeat(MAX_SHARED_BYTES), "b".to_string(), "c".to_string()];
+        let body = Body::wrap_stream(stream::iter(chunks.map(Ok::<_, std::io::Error>)));
+        let resp = leader.share(Response::new(body)).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body.len(), MAX_SHARED_BYTES + 2);
+        assert!(body.ends_with(b"bc"));
+        assert!(follower.shared().await.is_none());
+    }
+}
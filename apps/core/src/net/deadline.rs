@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use hyper::header::HeaderValue;
+use hyper::HeaderMap;
+
+use crate::net::files::strip_static_prefix;
+
+/// Headers carrying the caller's remaining deadline
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// The deadline requested by the client, from `X-Request-Timeout` (milliseconds) or
+/// `grpc-timeout` (value and unit), whichever is shorter
+pub fn requested_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let plain = headers
+        .get(REQUEST_TIMEOUT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_millis);
+    let grpc =
+        headers.get(GRPC_TIMEOUT_HEADER).and_then(|v| v.to_str().ok()).and_then(parse_grpc_timeout);
+
+    match (plain, grpc) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The timeout to apply to a request: the client's deadline capped by the global timeout, or the
+/// global timeout when the client didn't ask for one
+pub fn effective_timeout(
+    requested: Option<Duration>,
+    global: Option<Duration>,
+) -> Option<Duration> {
+    match (requested, global) {
+        (Some(requested), Some(global)) => Some(requested.min(global)),
+        (requested, global) => requested.or(global),
+    }
+}
+
+/// The configured timeout for `path`: that of the longest matching `--endpoint-timeout` prefix,
+/// or the global `--timeout-ms`. Zero means no timeout.
+pub fn configured_timeout(
+    path: &str,
+    overrides: &[(String, u64)],
+    global_ms: u64,
+) -> Option<Duration> {
+    let millis = overrides
+        .iter()
+        .filter(|(prefix, _)| strip_static_prefix(path, prefix).is_some())
+        .max_by_key(|(prefix, _)| prefix.trim_end_matches('/').len())
+        .map_or(global_ms, |(_, millis)| *millis);
+
+    Some(Duration::from_millis(millis)).filter(|t| !t.is_zero())
+}
+
+/// Rewrite the deadline headers the client sent with the time that is left
+pub fn propagate_deadline(headers: &mut HeaderMap, remaining: Duration) {
+    if headers.contains_key(REQUEST_TIMEOUT_HEADER) {
+        headers.insert(REQUEST_TIMEOUT_HEADER, HeaderValue::from(remaining.as_millis() as u64));
+    }
+
+    if headers.contains_key(GRPC_TIMEOUT_HEADER) {
+        let value = format_grpc_timeout(remaining);
+        headers.insert(GRPC_TIMEOUT_HEADER, HeaderValue::from_str(&value).unwrap());
+    }
+}
+
+/// Parse a `grpc-timeout` value such as `250m` or `5S`
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let unit = value.chars().last()?;
+    let amount = value[..value.len() - unit.len_utf8()].parse::<u64>().ok()?;
+
+    Some(match unit {
+        'H' => Duration::from_secs(amount.checked_mul(3600)?),
+        'M' => Duration::from_secs(amount.checked_mul(60)?),
+        'S' => Duration::from_secs(amount),
+        'm' => Duration::from_millis(amount),
+        'u' => Duration::from_micros(amount),
+        'n' => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Format a duration as a `grpc-timeout` value, which allows at most 8 digits
+pub fn format_grpc_timeout(timeout: Duration) -> String {
+    let millis = timeout.as_millis();
+    if millis < 100_000_000 {
+        format!("{}m", millis)
+    } else {
+        format!("{}S", timeout.as_secs().min(99_999_999))
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("5S"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("100u"), Some(Duration::from_micros(100)));
+        assert_eq!(parse_grpc_timeout("100n"), Some(Duration::from_nanos(100)));
+        assert_eq!(parse_grpc_timeout("100"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+    }
+
+    #[test]
+    fn test_format_grpc_timeout() {
+        assert_eq!(format_grpc_timeout(Duration::from_millis(1500)), "1500m");
+        assert_eq!(format_grpc_timeout(Duration::from_secs(200_000)), "200000S");
+    }
+
+    #[test]
+    fn test_requested_timeout() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(requested_timeout(&headers), None);
+
+        headers.insert(REQUEST_TIMEOUT_HEADER, HeaderValue::from_static("800"));
+        assert_eq!(requested_timeout(&headers), Some(Duration::from_millis(800)));
+
+        headers.insert(GRPC_TIMEOUT_HEADER, HeaderValue::from_static("500m"));
+        assert_eq!(requested_timeout(&headers), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_effective_timeout() {
+        let second = Some(Duration::from_secs(1));
+        let minute = Some(Duration::from_secs(60));
+
+        assert_eq!(effective_timeout(None, None), None);
+        assert_eq!(effective_timeout(second, None), second);
+        assert_eq!(effective_timeout(None, minute), minute);
+        assert_eq!(effective_timeout(minute, second), second);
+    }
+
+    #[test]
+    fn test_configured_timeout() {
+        let overrides = vec![
+            ("/report".to_string(), 120_000),
+            ("/report/quick/".to_string(), 1_000),
+            ("/stream".to_string(), 0),
+        ];
+        let timeout = |path| configured_timeout(path, &overrides, 5_000);
+
+        assert_eq!(timeout("/report"), Some(Duration::from_secs(120)));
+        assert_eq!(timeout("/report/2024"), Some(Duration::from_secs(120)));
+        assert_eq!(timeout("/report/quick/1"), Some(Duration::from_secs(1)));
+        assert_eq!(timeout("/reports"), Some(Duration::from_secs(5)));
+        assert_eq!(timeout("/stream/events"), None);
+        assert_eq!(configured_timeout("/", &[], 0), None);
+    }
+
+    #[test]
+    fn test_propagate_deadline() {
+        let mut headers = HeaderMap::new();
+        propagate_deadline(&mut headers, Duration::from_millis(300));
+        assert!(headers.is_empty());
+
+        headers.insert(REQUEST_TIMEOUT_HEADER, HeaderValue::from_static("800"));
+        headers.insert(GRPC_TIMEOUT_HEADER, HeaderValue::from_static("1S"));
+        propagate_deadline(&mut headers, Duration::from_millis(300));
+        assert_eq!(headers[REQUEST_TIMEOUT_HEADER], "300");
+        assert_eq!(headers[GRPC_TIMEOUT_HEADER], "300m");
+    }
+}
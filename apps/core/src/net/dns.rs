@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::client::connect::dns::Name;
+use hyper::service::Service;
+use tokio::time;
+
+/// The addresses of every host the client connected to with `--dns-refresh-ms`, and the
+/// connections open to each of them. Re-resolving a host marks the connections to addresses it
+/// no longer resolves to as stale. Hosts without connections that weren't looked up again for a
+/// whole refresh interval are forgotten, so one-off mirror, route or override hosts don't pile up.
+#[derive(Default)]
+pub struct DnsCache {
+    hosts: Mutex<HashMap<String, CachedHost>>,
+    connections: Mutex<HashMap<u64, TrackedConnection>>,
+    next_id: AtomicU64,
+}
+
+struct CachedHost {
+    ips: Vec<IpAddr>,
+    /// Looked up since the last refresh
+    used: bool,
+}
+
+struct TrackedConnection {
+    host: String,
+    ip: IpAddr,
+    stale: StaleAddress,
+}
+
+/// Set once the address of a connection disappeared from its host's addresses. Responses carry it
+/// in their extensions, so the connection can be kept from going back to the pool.
+#[derive(Debug, Clone, Default)]
+pub struct StaleAddress(Arc<AtomicBool>);
+
+impl StaleAddress {
+    pub fn is_stale(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl DnsCache {
+    /// Re-resolve every cached host still in use, keeping the last addresses of hosts that fail to
+    /// resolve
+    pub async fn refresh(&self) {
+        let connected = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|conn| conn.host.clone())
+            .collect::<HashSet<_>>();
+        let hosts = {
+            let mut hosts = self.hosts.lock().unwrap();
+            hosts.retain(|host, cached| {
+                std::mem::take(&mut cached.used) || connected.contains(host)
+            });
+            hosts.keys().cloned().collect::<Vec<_>>()
+        };
+        for host in hosts {
+            match resolve(&host).await {
+                Ok(ips) => self.update(&host, ips),
+                Err(e) => eprintln!("warning: keeping the last addresses of {}, {}", host, e),
+            }
+        }
+    }
+
+    /// Replace the addresses of `host`, marking connections to the ones that disappeared
+    pub fn update(&self, host: &str, ips: Vec<IpAddr>) {
+        self.connections.lock().unwrap().retain(|_, conn| {
+            let stale = conn.host == host && !ips.contains(&conn.ip);
+            if stale {
+                conn.stale.0.store(true, Ordering::Relaxed);
+            }
+            !stale
+        });
+        let mut hosts = self.hosts.lock().unwrap();
+        let cached =
+            hosts.entry(host.to_string()).or_insert(CachedHost { ips: Vec::new(), used: false });
+        cached.ips = ips;
+    }
+
+    /// The cached addresses of `host`, marking it as still in use
+    fn lookup(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let cached = hosts.get_mut(host)?;
+        cached.used = true;
+        Some(cached.ips.clone())
+    }
+
+    /// An ID for a new connection to pass to `track`
+    pub fn connection_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Watch a connection to `ip`, the returned flag is set once `host` stops resolving to it
+    pub fn track(&self, id: u64, host: &str, ip: IpAddr) -> StaleAddress {
+        let stale = StaleAddress::default();
+        let conn = TrackedConnection { host: host.to_string(), ip, stale: stale.clone() };
+        self.connections.lock().unwrap().insert(id, conn);
+        stale
+    }
+
+    /// Forget a connection that was closed
+    pub fn untrack(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+}
+
+/// Resolves hosts for the client's connector. With a cache the addresses are only looked up the
+/// first time and on every refresh, otherwise on every new connection like hyper's own resolver.
+#[derive(Clone, Default)]
+pub struct CachingResolver {
+    pub cache: Option<Arc<DnsCache>>,
+}
+
+impl Service<Name> for CachingResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let cache = self.cache.clone();
+
+        Box::pin(async move {
+            let host = name.as_str();
+            let ips = match cache.as_ref().and_then(|cache| cache.lookup(host)) {
+                Some(ips) => ips,
+                None => {
+                    let ips = resolve(host).await?;
+                    if let Some(cache) = &cache {
+                        let cached = CachedHost { ips: ips.clone(), used: true };
+                        cache.hosts.lock().unwrap().insert(host.to_string(), cached);
+                    }
+                    ips
+                }
+            };
+
+            // The connector fills in the port of the URI
+            let addrs = ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect::<Vec<_>>();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Look up the addresses of `host` with the system resolver, in the order it returned them
+async fn resolve(host: &str) -> io::Result<Vec<IpAddr>> {
+    let mut ips = Vec::new();
+    for addr in tokio::net::lookup_host((host, 0)).await? {
+        if !ips.contains(&addr.ip()) {
+            ips.push(addr.ip());
+        }
+    }
+
+    Ok(ips)
+}
+
+/// Refresh the cache every `every` for as long as a client uses it
+pub fn spawn_dns_refresh(cache: &Arc<DnsCache>, every: Duration) {
+    let cache = Arc::downgrade(cache);
+    tokio::spawn(async move {
+        let mut interval = time::interval(every);
+        // The first tick completes immediately and nothing was resolved yet
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let Some(cache) = cache.upgrade() else {
+                return;
+            };
+            cache.refresh().await;
+        }
+    });
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_caching_resolver() {
+        let cache = Arc::new(DnsCache::default());
+        cache.update("upstream.internal", vec!["10.0.0.1".parse().unwrap()]);
+        let mut resolver = CachingResolver { cache: Some(Arc::clone(&cache)) };
+
+        let name = Name::from_str("upstream.internal").unwrap();
+        let addrs = resolver.call(name).await.unwrap().collect::<Vec<_>>();
+        assert_eq!(addrs, vec!["10.0.0.1:0".parse::<SocketAddr>().unwrap()]);
+
+        let addrs = resolver.call(Name::from_str("localhost").unwrap()).await.unwrap();
+        assert!(addrs.map(|addr| addr.ip()).any(|ip| ip.is_loopback()));
+        assert!(cache.hosts.lock().unwrap().contains_key("localhost"));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_evicts_stale_connections() {
+        let cache = DnsCache::default();
+        cache.update("localhost", vec!["10.0.0.1".parse().unwrap()]);
+        let gone = cache.track(0, "localhost", "10.0.0.1".parse().unwrap());
+        let kept = cache.track(1, "localhost", "127.0.0.1".parse().unwrap());
+
+        cache.refresh().await;
+        let ips = cache.hosts.lock().unwrap()["localhost"].ips.clone();
+        assert!(ips.contains(&"127.0.0.1".parse().unwrap()));
+        assert!(gone.is_stale());
+        assert!(!kept.is_stale());
+        assert_eq!(cache.connections.lock().unwrap().keys().collect::<Vec<_>>(), vec![&1]);
+
+        cache.untrack(1);
+        assert!(cache.connections.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_forgets_unused_hosts() {
+        let cache = Arc::new(DnsCache::default());
+        cache.update("connected.internal", vec!["10.0.0.1".parse().unwrap()]);
+        cache.update("unused.internal", vec!["10.0.0.2".parse().unwrap()]);
+        cache.track(0, "connected.internal", "10.0.0.1".parse().unwrap());
+        let mut resolver = CachingResolver { cache: Some(Arc::clone(&cache)) };
+        resolver.call(Name::from_str("localhost").unwrap()).await.unwrap();
+
+        // Looked up hosts stay for one more interval, connected ones as long as they are
+        cache.refresh().await;
+        let mut hosts = cache.hosts.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        hosts.sort();
+        assert_eq!(hosts, ["connected.internal", "localhost"]);
+
+        cache.refresh().await;
+        let hosts = cache.hosts.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(hosts, ["connected.internal"]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io;
+use std::path::Path;
+
+use hyper::body::Bytes;
+use hyper::header::{
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, TRANSFER_ENCODING
+};
+use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+
+use crate::config::ErrorFormat;
+use crate::net::files::content_type;
+
+/// The `--error-page-5xx` file, read once at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPage {
+    pub content_type: &'static str,
+    pub body: Bytes,
+}
+
+impl ErrorPage {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let body = std::fs::read(path)?;
+        Ok(ErrorPage { content_type: content_type(path), body: body.into() })
+    }
+
+    /// Replace the body of a 5xx from the target server with the page. The status and headers
+    /// are kept, except those describing the body that was dropped.
+    pub fn replace_body(&self, resp: Response<Body>) -> Response<Body> {
+        if !resp.status().is_server_error() {
+            return resp;
+        }
+
+        let (mut parts, _) = resp.into_parts();
+        let dropped = [
+            CONTENT_ENCODING,
+            CONTENT_LENGTH,
+            CONTENT_RANGE,
+            ETAG,
+            LAST_MODIFIED,
+            TRANSFER_ENCODING,
+        ];
+        for name in dropped {
+            parts.headers.remove(name);
+        }
+        parts.headers.insert(CONTENT_TYPE, self.content_type.parse().unwrap());
+
+        Response::from_parts(parts, Body::from(self.body.clone()))
+    }
+}
+
+/// Build a response for an error generated by the proxy itself
+pub fn error_response(
+    format: ErrorFormat,
+    status: StatusCode,
+    message: &str,
+    request_id: &str,
+) -> Response<Body> {
+    let builder = Response::builder().status(status);
+
+    match format {
+        ErrorFormat::Text => builder
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from(message.to_string())),
+        ErrorFormat::Json => {
+            let body = json!({
+                "error": message,
+                "status": status.as_u16(),
+                "request_id": request_id,
+            });
+            builder.header(CONTENT_TYPE, "application/json").body(Body::from(body.to_string()))
+        }
+    }
+    .unwrap()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let resp = error_response(ErrorFormat::Text, StatusCode::FORBIDDEN, "Access denied", "id");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "Access denied");
+
+        let resp =
+            error_response(ErrorFormat::Json, StatusCode::BAD_GATEWAY, "Bad \"gateway\"", "id");
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await.unwrap(),
+            r#"{"error":"Bad \"gateway\"","request_id":"id","status":502}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_page() {
+        let page =
+            ErrorPage { content_type: "text/html; charset=utf-8", body: "<h1>Sorry</h1>".into() };
+
+        let resp = Response::builder()
+            .status(StatusCode::BAD_GATEWAY)
+            .header(CONTENT_TYPE, "text/plain")
+            .header(CONTENT_LENGTH, "22")
+            .header("retry-after", "30")
+            .body(Body::from("Traceback (most recent"))
+            .unwrap();
+        let resp = page.replace_body(resp);
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(resp.headers()["retry-after"], "30");
+        assert!(!resp.headers().contains_key(CONTENT_LENGTH));
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "<h1>Sorry</h1>");
+
+        let resp = page.replace_body(Response::new(Body::from("fine")));
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "fine");
+    }
+}
@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Response, StatusCode};
+
+/// Return the part of `path` below `prefix`, if `path` falls under it
+pub fn strip_static_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Serve the file at `rel_path` below `dir`, refusing anything that escapes the directory
+pub async fn serve_file(dir: &Path, rel_path: &str, method: &Method) -> Response<Body> {
+    if method != Method::GET && method != Method::HEAD {
+        return plain_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+    }
+
+    let Some(mut path) = resolve_path(dir, rel_path) else {
+        return plain_response(StatusCode::NOT_FOUND, "Not found");
+    };
+
+    if path.is_dir() {
+        path.push("index.html");
+    }
+
+    // Symlinks may still point outside of the directory, so compare canonical paths
+    let inside_dir =
+        match (tokio::fs::canonicalize(dir).await, tokio::fs::canonicalize(&path).await) {
+            (Ok(dir), Ok(path)) => path.starts_with(dir),
+            _ => false,
+        };
+    if !inside_dir {
+        return plain_response(StatusCode::NOT_FOUND, "Not found");
+    }
+
+    match tokio::fs::read(&path).await {
+        Ok(contents) => {
+            let body = if method == Method::HEAD { Body::empty() } else { Body::from(contents) };
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, content_type(&path))
+                .body(body)
+                .unwrap()
+        }
+        Err(_) => plain_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// Answer requests for `/favicon.ico` and `/robots.txt` locally when configured to, so they
+/// neither reach the target server nor show up in the histograms
+pub fn builtin_response(
+    path: &str,
+    method: &Method,
+    serve_favicon: bool,
+    robots: Option<&str>,
+) -> Option<Response<Body>> {
+    let body = match path {
+        "/favicon.ico" if serve_favicon => None,
+        "/robots.txt" => Some(robots?.to_string()),
+        _ => return None,
+    };
+
+    if method != Method::GET && method != Method::HEAD {
+        return Some(plain_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"));
+    }
+
+    let resp = match body {
+        Some(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(if method == Method::HEAD { Body::empty() } else { Body::from(body) }),
+        None => Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()),
+    };
+    Some(resp.unwrap())
+}
+
+/// Join the percent-decoded segments of `rel_path` onto `dir`, rejecting traversal attempts
+pub fn resolve_path(dir: &Path, rel_path: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+
+    for segment in rel_path.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment)?;
+        if segment == "."
+            || segment == ".."
+            || segment.contains(['/', '\\', '\0'])
+            || Path::new(&segment).is_absolute()
+        {
+            return None;
+        }
+        path.push(segment);
+    }
+
+    Some(path)
+}
+
+/// Guess the `Content-Type` of a file from its extension
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decode the `%XX` escapes of `segment`, `None` if one is malformed or the result isn't UTF-8
+pub fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn plain_response(status: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder().status(status).body(Body::from(body)).unwrap()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_strip_static_prefix() {
+        assert_eq!(strip_static_prefix("/static/app.js", "/static"), Some("/app.js"));
+        assert_eq!(strip_static_prefix("/static/app.js", "/static/"), Some("/app.js"));
+        assert_eq!(strip_static_prefix("/static", "/static"), Some(""));
+        assert_eq!(strip_static_prefix("/staticfoo", "/static"), None);
+        assert_eq!(strip_static_prefix("/api", "/static"), None);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_response() {
+        let get = Method::GET;
+        assert!(builtin_response("/favicon.ico", &get, false, None).is_none());
+        assert!(builtin_response("/robots.txt", &get, true, None).is_none());
+        assert!(builtin_response("/index.html", &get, true, Some("")).is_none());
+
+        let favicon = builtin_response("/favicon.ico", &get, true, None).unwrap();
+        assert_eq!(favicon.status(), StatusCode::NO_CONTENT);
+
+        let robots = builtin_response("/robots.txt", &get, false, Some("Disallow: /")).unwrap();
+        assert_eq!(robots.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(robots.into_body()).await.unwrap(), "Disallow: /");
+
+        let post = builtin_response("/robots.txt", &Method::POST, false, Some("")).unwrap();
+        assert_eq!(post.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let dir = Path::new("/srv/www");
+
+        assert_eq!(resolve_path(dir, "/css/app.css"), Some(PathBuf::from("/srv/www/css/app.css")));
+        assert_eq!(resolve_path(dir, "/a%20b.txt"), Some(PathBuf::from("/srv/www/a b.txt")));
+        assert_eq!(resolve_path(dir, ""), Some(PathBuf::from("/srv/www")));
+        assert_eq!(resolve_path(dir, "/../etc/passwd"), None);
+        assert_eq!(resolve_path(dir, "/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve_path(dir, "/..%2fetc/passwd"), None);
+        assert_eq!(resolve_path(dir, "/%zz"), None);
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type(Path::new("index.html")), "text/html; charset=utf-8");
+        assert_eq!(content_type(Path::new("logo.PNG")), "image/png");
+        assert_eq!(content_type(Path::new("archive.tar")), "application/octet-stream");
+        assert_eq!(content_type(Path::new("README")), "application/octet-stream");
+    }
+}
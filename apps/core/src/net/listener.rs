@@ -0,0 +1,150 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::fd::FromRawFd;
+
+use hyper::server::Builder;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::net::proxy::MIN_READ_BUFFER_BYTES;
+use crate::state::Config;
+
+/// The listener for inbound traffic: the inherited `--listen-fd` socket if one was passed,
+/// otherwise a fresh socket bound to the proxy port with a `--listen-backlog` accept queue
+pub fn bind_listener(config: &Config) -> io::Result<TcpListener> {
+    let listener = match config.listen_fd {
+        Some(fd) => inherited_listener(fd)?,
+        None => {
+            let addr = SocketAddr::from(([127, 0, 0, 1], config.proxy));
+            listen_with_backlog(addr, config.listen_backlog)?
+        }
+    };
+
+    // Fails for file descriptors that are not a bound socket
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
+
+/// Apply the limits for inbound connections to the server: the request header limit and, for
+/// clients speaking HTTP/2, the stream limit and flow control windows
+pub fn configure_server<I>(mut builder: Builder<I>, config: &Config) -> Builder<I> {
+    if let Some(limit) = config.max_request_header_bytes {
+        // Hyper refuses read buffers below its minimum, proxy() enforces smaller limits itself
+        builder = builder.http1_max_buf_size(limit.max(MIN_READ_BUFFER_BYTES));
+    }
+
+    builder
+        .http2_max_concurrent_streams(config.http2_max_concurrent_streams)
+        .http2_initial_stream_window_size(config.http2_initial_stream_window_size)
+        .http2_initial_connection_window_size(config.http2_initial_connection_window_size)
+}
+
+/// Like `TcpListener::bind`, which always asks for a backlog of 128
+fn listen_with_backlog(addr: SocketAddr, backlog: i32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // Same as std, so a restarted proxy can rebind while old connections are in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn inherited_listener(fd: i32) -> io::Result<TcpListener> {
+    if fd < 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid listen fd"));
+    }
+
+    // SAFETY: the fd was handed to us to listen on and nothing else in the process owns it
+    Ok(unsafe { TcpListener::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn inherited_listener(_fd: i32) -> io::Result<TcpListener> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--listen-fd is only supported on unix"))
+}
+
+// unit test
+#[cfg(all(test, unix))]
+mod tests {
+
+    use std::convert::Infallible;
+    use std::os::fd::IntoRawFd;
+    use std::time::{Duration, Instant};
+
+    use clap::Parser;
+    use hyper::client::conn;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+
+    use super::*;
+    use crate::config::Args;
+
+    #[test]
+    fn test_bind_listener_from_fd() {
+        let inherited = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = inherited.local_addr().unwrap();
+        let fd = inherited.into_raw_fd().to_string();
+
+        let config = Config::from(&Args::parse_from(["test", "--listen-fd", &fd]));
+        let listener = bind_listener(&config).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_bind_listener_with_backlog() {
+        let config = Config::from(&Args::parse_from(["test", "--listen-backlog", "4096"]));
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let listener = listen_with_backlog(addr, config.listen_backlog).unwrap();
+
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        assert_eq!(accepted.peer_addr().unwrap(), client.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_bind_listener_rejects_invalid_fd() {
+        let config = Config::from(&Args::parse_from(["test", "--listen-fd=-1"]));
+        assert!(bind_listener(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_configure_server_limits_http2_streams() {
+        let config =
+            Config::from(&Args::parse_from(["test", "--http2-max-concurrent-streams", "1"]));
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok::<_, Infallible>(Response::new(Body::empty()))
+            }))
+        });
+        let server = configure_server(Server::bind(&"127.0.0.1:0".parse().unwrap()), &config);
+        let server = server.serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) =
+            conn::Builder::new().http2_only(true).handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let request = || Request::get("/").body(Body::empty()).unwrap();
+
+        // The first request makes sure the server's settings arrived
+        sender.send_request(request()).await.unwrap();
+        let start = Instant::now();
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            std::future::poll_fn(|cx| sender.poll_ready(cx)).await.unwrap();
+            responses.push(sender.send_request(request()));
+        }
+        for response in responses {
+            response.await.unwrap();
+        }
+        // The second request only got a stream once the first was answered
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
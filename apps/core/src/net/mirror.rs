@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use hyper::{Body, Request};
+use tokio::time;
+
+use crate::net::client::send_request;
+use crate::state::{HistogramMap, HttpClient, SharedCounters};
+use crate::statistics::{capped_endpoint, Counters, MIRROR_PREFIX};
+
+/// Send a copy of a request to the mirror target. The response is discarded and failures only
+/// show up in the statistics, the client is never affected. A mirror that doesn't answer within
+/// `timeout` counts as failed, so a hung mirror target can't pile up tasks holding request bodies.
+pub async fn mirror(
+    client: HttpClient,
+    req: Request<Body>,
+    histograms: HistogramMap,
+    counters: SharedCounters,
+    endpoint: String,
+    max_endpoints: usize,
+    timeout: Option<Duration>,
+) {
+    let timestamp = Utc::now();
+    let start = Instant::now();
+    let uri = req.uri().clone();
+
+    let sent = match timeout {
+        Some(timeout) => match time::timeout(timeout, send_request(&client, req)).await {
+            Ok(sent) => sent.map_err(|e| e.to_string()),
+            Err(_) => Err(format!("timed out after {:?}", timeout)),
+        },
+        None => send_request(&client, req).await.map_err(|e| e.to_string()),
+    };
+    let resp = match sent {
+        Ok(resp) => resp,
+        Err(e) => {
+            Counters::incr(&counters.mirror_errors);
+            eprintln!("Mirror request to {} failed: {}", uri, e);
+            return;
+        }
+    };
+
+    let duration = start.elapsed();
+    let status = resp.status().as_u16();
+
+    {
+        let mut histograms = counters.lock_stats(&histograms);
+        let overall = histograms.entry(format!("{}Overall", MIRROR_PREFIX)).or_default();
+        overall.add(duration, timestamp);
+        overall.add_status(status);
+
+        let endpoint =
+            capped_endpoint(&histograms, format!("{}{}", MIRROR_PREFIX, endpoint), max_endpoints);
+        let hist = histograms.entry(endpoint).or_default();
+        hist.add(duration, timestamp);
+        hist.add_status(status);
+    }
+
+    // Read the body so the connection can go back to the pool
+    let body = hyper::body::to_bytes(resp.into_body());
+    match timeout {
+        Some(timeout) => drop(time::timeout(timeout.saturating_sub(start.elapsed()), body).await),
+        None => drop(body.await),
+    }
+}
@@ -0,0 +1,81 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::body::Bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+
+/// A request as seen by the mock upstream
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    pub uri: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// A tiny upstream server for exercising `proxy()` end-to-end in tests
+pub struct MockUpstream {
+    pub addr: SocketAddr,
+    pub received: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl MockUpstream {
+    /// Spawn an upstream that answers every request with `status` and `body` after `delay`
+    pub async fn start(status: StatusCode, body: &'static str, delay: Duration) -> Self {
+        MockUpstream::start_with_headers(status, &[], body, delay).await
+    }
+
+    /// Like `start`, but also send the response `headers`
+    pub async fn start_with_headers(
+        status: StatusCode,
+        headers: &'static [(&'static str, &'static str)],
+        body: &'static str,
+        delay: Duration,
+    ) -> Self {
+        let received: Arc<Mutex<Vec<ReceivedRequest>>> = Arc::new(Mutex::new(Vec::new()));
+        let received_for_svc = Arc::clone(&received);
+
+        let make_svc = make_service_fn(move |_| {
+            let received = Arc::clone(&received_for_svc);
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let received = Arc::clone(&received);
+
+                    async move {
+                        let (parts, payload) = req.into_parts();
+                        let payload = hyper::body::to_bytes(payload).await.unwrap_or_default();
+                        received.lock().unwrap().push(ReceivedRequest {
+                            method: parts.method,
+                            uri: parts.uri.to_string(),
+                            headers: parts.headers,
+                            body: payload,
+                        });
+
+                        tokio::time::sleep(delay).await;
+
+                        let mut resp = Response::builder().status(status);
+                        for (name, value) in headers {
+                            resp = resp.header(*name, *value);
+                        }
+                        Ok::<_, Infallible>(resp.body(Body::from(body)).unwrap())
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        MockUpstream { addr, received }
+    }
+
+    /// The requests received so far
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.received.lock().unwrap().clone()
+    }
+}
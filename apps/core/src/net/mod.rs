@@ -1 +1,27 @@
+pub mod admin;
+pub mod blacklist;
+pub mod capture;
+pub mod client;
+pub mod coalesce;
+pub mod deadline;
+pub mod dns;
+pub mod error;
+pub mod files;
+pub mod listener;
+pub mod mirror;
+#[cfg(test)]
+pub mod mock;
+pub mod order;
+pub mod probe;
 pub mod proxy;
+pub mod proxy_protocol;
+pub mod queue;
+pub mod redirect;
+pub mod replay;
+pub mod rewrite;
+pub mod route;
+pub mod shutdown;
+pub mod spill;
+pub mod transform;
+pub mod upgrade;
+pub mod via;
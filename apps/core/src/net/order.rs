@@ -0,0 +1,68 @@
+use hyper::header::HeaderName;
+use hyper::HeaderMap;
+
+/// The header names of a request in the order they were received, each listed once
+pub fn header_order(headers: &HeaderMap) -> Vec<HeaderName> {
+    headers.keys().cloned().collect()
+}
+
+/// Put the headers named in `order` back in that order, followed by any headers the proxy added
+/// in the order it added them. Removing a header from a `HeaderMap` moves its last header into
+/// the gap, so without this every removal shuffles the headers that follow.
+///
+/// Repeated headers are grouped under their first occurrence, since the `HeaderMap` they were
+/// parsed into doesn't keep track of how they were interleaved with others.
+pub fn restore_header_order(headers: &mut HeaderMap, order: &[HeaderName]) {
+    let mut ordered = HeaderMap::with_capacity(headers.len());
+    for name in order {
+        for value in headers.get_all(name) {
+            ordered.append(name.clone(), value.clone());
+        }
+    }
+    for (name, value) in headers.iter() {
+        if !order.contains(name) {
+            ordered.append(name.clone(), value.clone());
+        }
+    }
+
+    *headers = ordered;
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn names(headers: &HeaderMap) -> Vec<&str> {
+        headers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_restore_header_order() {
+        let mut headers = HeaderMap::new();
+        for name in ["x-date", "x-secret", "accept", "x-signature", "x-nonce"] {
+            headers.append(name, HeaderValue::from_static("1"));
+        }
+        headers.append("accept", HeaderValue::from_static("2"));
+        let order = header_order(&headers);
+
+        headers.remove("x-secret");
+        headers.insert("x-forwarded-port", HeaderValue::from_static("8080"));
+        headers.insert("x-date", HeaderValue::from_static("3"));
+        assert_eq!(
+            names(&headers),
+            ["x-date", "x-nonce", "accept", "accept", "x-signature", "x-forwarded-port"]
+        );
+
+        restore_header_order(&mut headers, &order);
+        assert_eq!(
+            names(&headers),
+            ["x-date", "accept", "accept", "x-signature", "x-nonce", "x-forwarded-port"]
+        );
+        assert_eq!(headers["x-date"], "3");
+        assert_eq!(headers.get_all("accept").iter().collect::<Vec<_>>(), ["1", "2"]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use hyper::{Request, StatusCode, Uri};
+use tokio::time;
+
+use crate::state::{Config, HttpClient};
+
+/// Request `--health-path` from the target server until it answers with a success or redirect,
+/// trying `--startup-probe-retries` more times a second apart
+pub async fn startup_probe(client: &HttpClient, config: &Config) -> Result<StatusCode, String> {
+    let mut attempts_left = config.startup_probe_retries;
+    loop {
+        match probe_once(client, config).await {
+            Ok(status) => return Ok(status),
+            Err(e) if attempts_left == 0 => return Err(e),
+            Err(e) => {
+                eprintln!("warning: startup probe failed, {}, retrying", e);
+                attempts_left -= 1;
+                time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn probe_once(client: &HttpClient, config: &Config) -> Result<StatusCode, String> {
+    let uri = format!("http://{}:{}{}", config.host, config.port, config.health_path)
+        .parse::<Uri>()
+        .map_err(|e| format!("invalid health path {}: {}", config.health_path, e))?;
+    let req = Request::get(uri.clone()).body(hyper::Body::empty()).unwrap();
+
+    let timeout = Duration::from_millis(config.startup_probe_timeout_ms);
+    let resp = match time::timeout(timeout, client.request(req)).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(e)) => return Err(format!("could not reach {}: {}", uri, e)),
+        Err(_) => return Err(format!("{} did not answer within {:?}", uri, timeout)),
+    };
+
+    let status = resp.status();
+    if status.is_success() || status.is_redirection() {
+        Ok(status)
+    } else {
+        Err(format!("{} answered {}", uri, status))
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::client::build_client;
+    use crate::net::mock::MockUpstream;
+
+    fn probe_config(port: u16, extra_args: &[&str]) -> Config {
+        let port = port.to_string();
+        let mut argv = vec!["test", "--host", "127.0.0.1", "--port", &port];
+        argv.extend_from_slice(extra_args);
+        Config::from(&Args::parse_from(argv))
+    }
+
+    #[tokio::test]
+    async fn test_startup_probe() {
+        let upstream = MockUpstream::start(StatusCode::NO_CONTENT, "", Duration::ZERO).await;
+        let config = probe_config(upstream.addr.port(), &["--health-path", "/healthz"]);
+        let client = build_client(&config, Default::default());
+
+        assert_eq!(startup_probe(&client, &config).await, Ok(StatusCode::NO_CONTENT));
+        assert_eq!(upstream.requests()[0].uri, "/healthz");
+    }
+
+    #[tokio::test]
+    async fn test_startup_probe_fails() {
+        let broken = MockUpstream::start(StatusCode::NOT_FOUND, "", Duration::ZERO).await;
+        let config = probe_config(broken.addr.port(), &["--startup-probe-retries", "1"]);
+        let client = build_client(&config, Default::default());
+        assert!(startup_probe(&client, &config).await.unwrap_err().contains("404"));
+        assert_eq!(broken.requests().len(), 2);
+
+        let slow = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(500)).await;
+        let config = probe_config(slow.addr.port(), &["--startup-probe-timeout-ms", "50"]);
+        let client = build_client(&config, Default::default());
+        assert!(startup_probe(&client, &config).await.unwrap_err().contains("did not answer"));
+    }
+}
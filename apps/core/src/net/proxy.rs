@@ -1,13 +1,54 @@
-use std::collections::HashSet;
-use std::net::{IpAddr, SocketAddr};
-use std::sync::Arc;
-use std::time::Instant;
+use std::any::Any;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
 
-use chrono::{DateTime, Local, Utc};
-use hyper::{Body, Request, Response, StatusCode, Uri};
+use chrono::Utc;
+use futures_util::{FutureExt, TryStreamExt};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderValue, HOST, WWW_AUTHENTICATE};
+use hyper::http::uri::{Authority, PathAndQuery};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use tokio::time;
+use tracing::{error, field, info, info_span, Instrument};
+use uuid::Uuid;
 
-use crate::state::{HistogramMap, HttpClient, Log, LogList};
+use crate::net::admin::{admin_access, recent_response, stats_response, RECENT_PATH, STATS_PATH};
+use crate::net::capture::{sampled, Capture};
+use crate::net::client::ConnectTime;
+use crate::net::coalesce::Flight;
+use crate::net::deadline::{
+    configured_timeout, effective_timeout, propagate_deadline, requested_timeout
+};
+use crate::net::error::error_response;
+use crate::net::files::{builtin_response, serve_file, strip_static_prefix};
+use crate::net::mirror::mirror;
+use crate::net::order::{header_order, restore_header_order};
+use crate::net::redirect::{send_following_redirects, Replay};
+use crate::net::rewrite::{
+    normalize_trailing_slash, replace_path, take_query_param, upstream_path_and_query
+};
+use crate::net::route::route_by_header;
+use crate::net::spill::{BufferedBody, SpillError};
+use crate::net::transform::{limit_body, strip_json_fields};
+use crate::net::upgrade::{is_upgrade_request, spawn_tunnel};
+use crate::net::via::{append_via, via_contains};
+use crate::state::{
+    Config, HistogramMap, HttpClient, Log, LogList, SharedBlacklist, SharedCoalescer, SharedCounters, SharedGeoBlocker, SharedLimiter, SharedRecent, SharedTalkers, SharedUpstreams
+};
+use crate::statistics::{capped_endpoint, Counters, InFlight};
 
+/// The smallest read buffer hyper accepts, which bounds the request headers it can parse
+pub const MIN_READ_BUFFER_BYTES: usize = 8192;
+
+/// The query parameter `--allow-upstream-override` clients pick the upstream with
+const UPSTREAM_OVERRIDE_PARAM: &str = "__upstream";
+
+/// Handle a request in a `request` span carrying its method, URI and ID, which records the final
+/// status and duration once it is answered. A panic while handling it is answered with a 500 and
+/// recorded like any other request, under the overall statistics only.
 #[allow(clippy::too_many_arguments)]
 pub async fn proxy(
     client: HttpClient,
@@ -15,66 +56,2265 @@ pub async fn proxy(
     requester_ip: SocketAddr,
     histograms: HistogramMap,
     loglist: LogList,
-    target_host: String,
-    target_port: u16,
-    blacklist: Arc<HashSet<IpAddr>>,
+    config: Arc<Config>,
+    blacklist: SharedBlacklist,
+    geo_blocker: SharedGeoBlocker,
+    limiter: SharedLimiter,
+    counters: SharedCounters,
+    talkers: SharedTalkers,
+    recent: SharedRecent,
+    coalescer: SharedCoalescer,
+    upstreams: SharedUpstreams,
+) -> Result<Response<Body>, hyper::Error> {
+    let start = Instant::now();
+    let _in_flight = InFlight::start(Arc::clone(&counters));
+    let request_id = request_id(&req);
+    let span = info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        request_id = %request_id,
+        status = field::Empty,
+        duration_ms = field::Empty,
+    );
+    let timestamp = Utc::now();
+    let req_method = req.method().clone();
+    let req_uri = req.uri().to_string();
+
+    let handled = AssertUnwindSafe(forward(
+        client,
+        req,
+        requester_ip,
+        Arc::clone(&histograms),
+        Arc::clone(&loglist),
+        Arc::clone(&config),
+        blacklist,
+        geo_blocker,
+        limiter,
+        Arc::clone(&counters),
+        talkers,
+        recent,
+        coalescer,
+        upstreams,
+        request_id.clone(),
+    ))
+    .catch_unwind()
+    .instrument(span.clone())
+    .await;
+
+    let result = match handled {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            let duration = start.elapsed();
+            error!(target: "narrow::panic", request_id = %request_id, "handling {} {} panicked, {}", req_method, req_uri, message);
+            Counters::incr(&counters.panics);
+
+            // The endpoint is only known to the handler that panicked
+            let log = Log {
+                timestamp,
+                req_method: req_method.clone(),
+                req_uri,
+                requester_ip: config.requester_label(&requester_ip.ip()),
+                micros: duration.as_micros(),
+                endpoint: "Overall".to_string(),
+                status: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                ..Log::default()
+            };
+            loglist.lock().unwrap_or_else(PoisonError::into_inner).push(log);
+            let mut histograms = counters.lock_stats(&histograms);
+            let overall = histograms.entry("Overall".to_string()).or_default();
+            overall.add(duration, timestamp);
+            overall.add_status(StatusCode::INTERNAL_SERVER_ERROR.as_u16());
+            overall.methods.add(&req_method);
+
+            Ok(error_response(
+                config.error_format,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal proxy error",
+                &request_id,
+            ))
+        }
+    };
+
+    if let Ok(resp) = &result {
+        span.record("status", resp.status().as_u16());
+    }
+    span.record("duration_ms", start.elapsed().as_secs_f64() * 1000.0);
+
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn forward(
+    client: HttpClient,
+    mut req: Request<Body>,
+    requester_ip: SocketAddr,
+    histograms: HistogramMap,
+    loglist: LogList,
+    config: Arc<Config>,
+    blacklist: SharedBlacklist,
+    geo_blocker: SharedGeoBlocker,
+    limiter: SharedLimiter,
+    counters: SharedCounters,
+    talkers: SharedTalkers,
+    recent: SharedRecent,
+    coalescer: SharedCoalescer,
+    upstreams: SharedUpstreams,
+    request_id: String,
 ) -> Result<Response<Body>, hyper::Error> {
     let timestamp = Utc::now();
+    // Checks use the real address, only what is recorded may be anonymized
+    let requester = config.requester_label(&requester_ip.ip());
+
+    // Count rejected requests too, they are the interesting ones when investigating abuse
+    if let Some(talkers) = &talkers {
+        talkers.add(&requester);
+    }
+
+    if blacklist.read().unwrap().contains(&requester_ip.ip()) {
+        println!("Rejected blacklisted IP: {}", requester);
+        return Ok(error_response(
+            config.error_format,
+            StatusCode::FORBIDDEN,
+            "Access denied",
+            &request_id,
+        ));
+    }
 
-    let local_time: DateTime<Local> = DateTime::from(timestamp);
+    if geo_blocker.as_ref().is_some_and(|geo| geo.blocks(&requester_ip.ip())) {
+        println!("Rejected IP from a blocked country or ASN: {}", requester);
+        return Ok(error_response(
+            config.error_format,
+            StatusCode::FORBIDDEN,
+            "Access denied",
+            &request_id,
+        ));
+    }
+
+    // The address checks above don't look at the URI, the checks from here on do, so an abusive
+    // one is turned away before any of them has to work through it
+    if config.max_uri_bytes > 0 && req.uri().to_string().len() > config.max_uri_bytes {
+        return Ok(error_response(
+            config.error_format,
+            StatusCode::URI_TOO_LONG,
+            "URI too long",
+            &request_id,
+        ));
+    }
+
+    if !config.is_allowed_path(req.uri().path()) {
+        println!("Rejected request for a denied path: {}", req.uri().path());
+        let status = StatusCode::from_u16(config.deny_status).unwrap();
+        return Ok(error_response(
+            config.error_format,
+            status,
+            status.canonical_reason().unwrap_or("Access denied"),
+            &request_id,
+        ));
+    }
+
+    if let Some(limit) = config.max_request_header_bytes {
+        if header_bytes(req.headers()) > limit {
+            return Ok(error_response(
+                config.error_format,
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "Request header fields too large",
+                &request_id,
+            ));
+        }
+    }
+
+    let path = req.uri().path();
+    if (path == STATS_PATH && config.serve_stats) || (path == RECENT_PATH && recent.is_some()) {
+        match admin_access(&config, &requester_ip.ip(), req.headers()) {
+            Ok(()) => {}
+            Err(StatusCode::UNAUTHORIZED) => {
+                let mut resp = error_response(
+                    config.error_format,
+                    StatusCode::UNAUTHORIZED,
+                    "Authentication required",
+                    &request_id,
+                );
+                resp.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                return Ok(resp);
+            }
+            Err(status) => {
+                return Ok(error_response(
+                    config.error_format,
+                    status,
+                    "Access denied",
+                    &request_id,
+                ));
+            }
+        }
+        return Ok(match recent.as_ref().filter(|_| path == RECENT_PATH) {
+            Some(recent) => recent_response(recent, req.method()),
+            None => stats_response(&histograms, &upstreams, &config, req.method()),
+        });
+    }
+
+    if let Some(static_dir) = &config.static_dir {
+        let path = req.uri().path();
+        let rel_path =
+            config.static_prefix.iter().find_map(|prefix| strip_static_prefix(path, prefix));
+        if let Some(rel_path) = rel_path {
+            return Ok(serve_file(static_dir, rel_path, req.method()).await);
+        }
+    }
+
+    if let Some(resp) = builtin_response(
+        req.uri().path(),
+        req.method(),
+        config.serve_favicon,
+        config.robots.as_deref(),
+    ) {
+        return Ok(resp);
+    }
 
-    if blacklist.contains(&requester_ip.ip()) {
-        println!("Rejected blacklisted IP: {}", requester_ip.ip());
-        return Ok(Response::builder()
-            .status(StatusCode::FORBIDDEN)
-            .body(Body::from("Access denied"))
-            .unwrap());
+    // Our own name in the chain means the target server leads back to this proxy
+    if !config.no_via && via_contains(req.headers(), &config.via_name) {
+        eprintln!("{} {} - Forwarding loop detected", req.method(), req.uri());
+        return Ok(error_response(
+            config.error_format,
+            StatusCode::LOOP_DETECTED,
+            "The request was forwarded back to this proxy",
+            &request_id,
+        ));
+    }
+
+    // Held back only once the cheap rejections are done, so they don't tie up a task for the
+    // delay, and before the response time is taken, so it doesn't skew the statistics
+    if let Some(delay) = config.greylist_delay(&requester_ip.ip()) {
+        Counters::incr(&counters.greylisted);
+        time::sleep(delay).await;
     }
 
     let start = Instant::now();
 
+    let rewritten = config.rewrite_rules.as_ref().and_then(|rules| rules.rewrite(req.uri().path()));
+    // Anything else would end up in the authority of the upstream URI, and a query or fragment
+    // would run into the client's query and into the recorded endpoint
+    if let Some(path) = &rewritten {
+        if !path.starts_with('/')
+            || path.contains(['?', '#'])
+            || path.parse::<PathAndQuery>().is_err()
+        {
+            eprintln!("{} {} - Rewritten to an invalid path: {}", req.method(), req.uri(), path);
+            return Ok(error_response(
+                config.error_format,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "The request path was rewritten to an invalid one",
+                &request_id,
+            ));
+        }
+    }
+
+    let recorded_path = match &rewritten {
+        Some(path) if config.record_rewritten_path => path.as_str(),
+        _ => req.uri().path(),
+    };
+    let path = match config.normalize_trailing_slash {
+        Some(mode) => normalize_trailing_slash(recorded_path, mode),
+        None => recorded_path.to_string(),
+    };
+    let endpoint = match &config.group_by_header {
+        Some(name) => {
+            let group = req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("unknown");
+            format!("{}={} {}", name.to_ascii_lowercase(), group, path)
+        }
+        None => path,
+    };
+
     let req_method = req.method().clone();
     let req_uri = req.uri().clone();
-    let req_headers = req.headers().clone();
+    let req_version = req.version();
+    let mut req_headers = req.headers().clone();
+    let original_order = config.preserve_header_order.then(|| header_order(&req_headers));
 
-    let uri = format!(
-        "http://{}:{}{}",
-        target_host,
-        target_port,
-        req_uri.path_and_query().map(|x| x.as_str()).unwrap_or("")
-    )
-    .parse::<Uri>()
-    .unwrap();
+    // Only trusted proxies may vouch for security-relevant headers such as X-Client-Cert
+    if !config.is_trusted_proxy(&requester_ip.ip()) {
+        for name in &config.sensitive_headers {
+            req_headers.remove(name.to_ascii_lowercase().as_str());
+        }
+    }
 
+    // The override parameter never reaches the target server, whoever sent it
+    let original_path_and_query = req_uri.path_and_query().map(|x| x.as_str()).unwrap_or("");
+    let (client_path_and_query, upstream_override) =
+        take_query_param(original_path_and_query, UPSTREAM_OVERRIDE_PARAM);
+    let overridden = upstream_override.is_some();
+    let upstream_override =
+        upstream_override.filter(|_| config.may_override_upstream(&requester_ip.ip()));
+    if let Some(requested) = &upstream_override {
+        if requested.parse::<Authority>().ok().and_then(|a| a.port_u16()).is_none() {
+            return Ok(error_response(
+                config.error_format,
+                StatusCode::BAD_REQUEST,
+                "The upstream override has to be host:port",
+                &request_id,
+            ));
+        }
+    }
+
+    // Header routes see the headers as forwarded, so only trusted proxies can route by sensitive
+    // ones
+    let upstream = match (upstream_override, route_by_header(&config.header_routes, &req_headers)) {
+        (Some(requested), _) => requested,
+        (None, Some(routed)) => routed.to_string(),
+        (None, None) => format!("{}:{}", config.host, config.port),
+    };
+    let (path_and_query, uri) = match req_uri.path_and_query().filter(|_| config.preserve_raw_uri) {
+        // Reuse the parsed original so not even its encoding can change on the way
+        Some(raw) => {
+            let raw = match overridden {
+                true => {
+                    client_path_and_query.parse::<PathAndQuery>().unwrap_or_else(|_| raw.clone())
+                }
+                false => raw.clone(),
+            };
+            let uri = Uri::builder()
+                .scheme("http")
+                .authority(upstream.as_str())
+                .path_and_query(raw.clone())
+                .build()
+                .unwrap();
+            (raw.as_str().to_string(), uri)
+        }
+        None => {
+            let client_path_and_query = match &rewritten {
+                Some(path) => replace_path(&client_path_and_query, path),
+                None => client_path_and_query,
+            };
+            let path_and_query = upstream_path_and_query(
+                &client_path_and_query,
+                config.strip_prefix.as_deref(),
+                config.upstream_path_prefix.as_deref(),
+                config.normalize_trailing_slash.filter(|_| config.normalize_forwarded_path),
+            );
+            let uri = format!("http://{}{}", upstream, path_and_query).parse::<Uri>().unwrap();
+            (path_and_query, uri)
+        }
+    };
+
+    let timeout = effective_timeout(
+        requested_timeout(&req_headers),
+        configured_timeout(req_uri.path(), &config.endpoint_timeouts, config.timeout_ms),
+    );
+
+    // Keep hold of the client connection in case the target server agrees to switch protocols
+    let client_upgrade = is_upgrade_request(req.headers()).then(|| hyper::upgrade::on(&mut req));
+
+    // Hyper answers `Expect: 100-continue` once the body is first read, which only happens after
+    // the request has been forwarded, so the target server still gets to reject it up front
+    let capture = config.capture_dir.as_ref().filter(|_| sampled(config.capture_sample_rate));
+    let capture = capture.map(|dir| {
+        let mut redacted = config.capture_redact_headers.clone();
+        redacted.extend(config.sensitive_headers.iter().cloned());
+        Capture::start(dir, &request_id, config.capture_max_bytes, redacted, &req)
+    });
+    let (body, request_size) = measure_body(req.into_body());
+    let body = match &capture {
+        Some(capture) => capture.request_body(body),
+        None => body,
+    };
     let mut proxied_req =
-        Request::builder().method(req_method.clone()).uri(uri).body(req.into_body()).unwrap();
+        Request::builder().method(req_method.clone()).uri(uri).body(body).unwrap();
+
+    // Redirects to either the target server or the proxy itself are followed
+    let mut authorities = vec![upstream.clone()];
+    authorities.extend(req_headers.get(HOST).and_then(|v| v.to_str().ok()).map(str::to_string));
 
     *proxied_req.headers_mut() = req_headers;
 
-    let resp = client.request(proxied_req).await?;
+    // Let the upstream build absolute URLs (e.g. redirects) that point back at the proxy. A
+    // trusted proxy in front, such as a load balancer terminating TLS, knows better.
+    let headers = proxied_req.headers_mut();
+    let trusted = config.is_trusted_proxy(&requester_ip.ip());
+    if !trusted || !headers.contains_key("x-forwarded-proto") {
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("http"));
+    }
+    if !trusted || !headers.contains_key("x-forwarded-port") {
+        headers.insert("x-forwarded-port", HeaderValue::from(config.proxy));
+    }
+    if !config.no_via {
+        append_via(headers, req_version, &config.via_name);
+    }
+
+    // Mirrors and followed redirects send the body again, so it has to be buffered. An upgraded
+    // connection can't be replayed, so those are never mirrored or redirected. Large bodies go
+    // to --spill-to-disk, if given, and are streamed from there every time they are sent.
+    let replay_body = if client_upgrade.is_none()
+        && (config.mirror_to.is_some() || config.follow_redirects > 0)
+    {
+        let spill = config.spill_to_disk.as_deref().map(|dir| (dir, config.spill_threshold_bytes));
+        let body = match BufferedBody::read(std::mem::take(proxied_req.body_mut()), spill).await {
+            Ok(body) => body,
+            Err(SpillError::Body(e)) => return Err(e),
+            Err(SpillError::Disk(e)) => {
+                eprintln!("{} {} - Could not spill the request body: {}", req_method, req_uri, e);
+                return Ok(error_response(
+                    config.error_format,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not buffer the request body",
+                    &request_id,
+                ));
+            }
+        };
+        *proxied_req.body_mut() = body.body();
+        Some(body)
+    } else {
+        None
+    };
+
+    if let (Some(mirror_to), Some(body)) = (&config.mirror_to, &replay_body) {
+        let mut mirror_req = Request::builder()
+            .method(req_method.clone())
+            .uri(format!("http://{}{}", mirror_to, path_and_query))
+            .body(body.body())
+            .unwrap();
+        *mirror_req.headers_mut() = proxied_req.headers().clone();
+
+        tokio::spawn(mirror(
+            client.clone(),
+            mirror_req,
+            Arc::clone(&histograms),
+            Arc::clone(&counters),
+            endpoint.clone(),
+            config.max_endpoints,
+            Some(Duration::from_millis(config.mirror_timeout_ms)).filter(|t| !t.is_zero()),
+        ));
+    }
+
+    // Requests the proxy answers itself because forwarding failed still count for their endpoint,
+    // or an outage of the target server would look like an endpoint without requests
+    let record_failure = |status: StatusCode| {
+        let duration = start.elapsed();
+        let mut histograms = counters.lock_stats(&histograms);
+        let endpoint = capped_endpoint(&histograms, endpoint.clone(), config.max_endpoints);
+        for key in ["Overall".to_string(), endpoint] {
+            let hist = histograms.entry(key).or_default();
+            hist.add(duration, timestamp);
+            hist.add_status(status.as_u16());
+            hist.methods.add(&req_method);
+            if config.track_hourly {
+                hist.hourly.add(timestamp);
+            }
+        }
+    };
+
+    // Wait for a slot to forward the request in, held until the response headers arrive
+    let slot = match &limiter {
+        Some(limiter) => match limiter.acquire(&endpoint, &counters).await {
+            Some(slot) => Some(slot),
+            None => {
+                eprintln!("{} {} - Rejected, too many queued requests", req_method, req_uri);
+                record_failure(StatusCode::SERVICE_UNAVAILABLE);
+                return Ok(error_response(
+                    config.error_format,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Too many requests are waiting for the target server",
+                    &request_id,
+                ));
+            }
+        },
+        None => None,
+    };
+    let queue_wait = slot.as_ref().and_then(|slot| slot.waited);
+
+    if let Some(timeout) = timeout {
+        propagate_deadline(proxied_req.headers_mut(), timeout.saturating_sub(start.elapsed()));
+    }
+    if let Some(order) = &original_order {
+        restore_header_order(proxied_req.headers_mut(), order);
+    }
+    let replay = replay_body.filter(|_| config.follow_redirects > 0).map(|body| Replay {
+        method: req_method.clone(),
+        uri: proxied_req.uri().clone(),
+        headers: proxied_req.headers().clone(),
+        body,
+    });
+    let flight = coalescer.as_ref().filter(|_| client_upgrade.is_none()).and_then(|coalescer| {
+        coalescer.join(&req_method, proxied_req.uri(), proxied_req.headers())
+    });
+    let prepared = start.elapsed().saturating_sub(queue_wait.unwrap_or_default());
+    let sending = Instant::now();
+    let send = async {
+        // A follower gets a copy of the leader's response, or is forwarded after all if the
+        // response can't be shared
+        let leader = match flight {
+            Some(Flight::Leader(leader)) => Some(leader),
+            Some(Flight::Follower(follower)) => {
+                if let Some(shared) = follower.shared().await {
+                    Counters::incr(&counters.coalesced);
+                    return Ok((shared.to_response(), 0, true));
+                }
+                None
+            }
+            None => None,
+        };
+        let (resp, redirects) = send_following_redirects(
+            &client,
+            proxied_req,
+            replay,
+            config.follow_redirects,
+            &authorities,
+        )
+        .await?;
+        match leader {
+            Some(leader) => Ok((leader.share(resp).await?, redirects, false)),
+            None => Ok((resp, redirects, false)),
+        }
+    };
+
+    // The timeout covers every redirect followed
+    let result = match timeout {
+        Some(timeout) => match time::timeout(timeout, send).await {
+            Ok(result) => result,
+            Err(_) => {
+                Counters::incr(&counters.timeouts);
+                upstreams.add(&upstream, StatusCode::GATEWAY_TIMEOUT.as_u16(), true);
+                eprintln!("{} {} - Timed out after {:?}", req_method, req_uri, timeout);
+                record_failure(StatusCode::GATEWAY_TIMEOUT);
+
+                return Ok(error_response(
+                    config.error_format,
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "The target server did not respond in time",
+                    &request_id,
+                ));
+            }
+        },
+        None => send.await,
+    };
+
+    let (mut resp, redirects, coalesced) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            let message = if is_dns_error(&e) {
+                Counters::incr(&counters.dns_errors);
+                let host = upstream.rsplit_once(':').map_or(upstream.as_str(), |(host, _)| host);
+                format!("Could not resolve target host {}", host)
+            } else if e.is_connect() {
+                Counters::incr(&counters.connect_errors);
+                format!("Could not connect to target server {}", upstream)
+            } else {
+                "Error while forwarding the request".to_string()
+            };
+            eprintln!("{} {} - {}: {}", req_method, req_uri, message, e);
+            upstreams.add(&upstream, StatusCode::BAD_GATEWAY.as_u16(), false);
+            record_failure(StatusCode::BAD_GATEWAY);
+
+            return Ok(error_response(
+                config.error_format,
+                StatusCode::BAD_GATEWAY,
+                &message,
+                &request_id,
+            ));
+        }
+    };
+
+    // A copy of the leader's response never reached the upstream itself
+    if !coalesced {
+        upstreams.add(&upstream, resp.status().as_u16(), false);
+    }
+
+    // Coalesced and redirected requests count the time spent on other connections as upstream
+    let phases = config.profile.then(|| {
+        let connect = resp.extensions().get::<ConnectTime>().map(ConnectTime::take);
+        let connect = connect.unwrap_or_default();
+        (prepared, connect, sending.elapsed().saturating_sub(connect))
+    });
+
+    if resp.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let Some(client_upgrade) = client_upgrade else {
+            eprintln!("{} {} - Target server switched protocols unasked", req_method, req_uri);
+            record_failure(StatusCode::BAD_GATEWAY);
+            return Ok(error_response(
+                config.error_format,
+                StatusCode::BAD_GATEWAY,
+                "The target server switched protocols unexpectedly",
+                &request_id,
+            ));
+        };
+        spawn_tunnel(client_upgrade, hyper::upgrade::on(&mut resp));
+    }
+
+    if !config.no_via {
+        let version = resp.version();
+        append_via(resp.headers_mut(), version, &config.via_name);
+    }
+
+    if let Some(page) = &config.error_page_5xx {
+        resp = page.replace_body(resp);
+    }
 
     let duration = start.elapsed();
-    println!(
-        "{} {} {} - From: {} - Response time: {:?}",
-        local_time.format("%Y-%m-%d %H:%M:%S %Z"),
+
+    if req_method == Method::HEAD {
+        // A HEAD response describes the body a GET would get, so its headers (Content-Length
+        // included) are kept as they are while anything the target server sent along is dropped
+        *resp.body_mut() = Body::empty();
+    } else {
+        if let Some(limit) = config.max_response_bytes {
+            match resp.body().size_hint().exact() {
+                Some(len) if len > limit => {
+                    Counters::incr(&counters.oversized_responses);
+                    eprintln!(
+                        "{} {} - Response body of {} bytes is too large",
+                        req_method, req_uri, len
+                    );
+                    record_failure(StatusCode::BAD_GATEWAY);
+                    return Ok(error_response(
+                        config.error_format,
+                        StatusCode::BAD_GATEWAY,
+                        "The target server's response is too large",
+                        &request_id,
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    let body = std::mem::take(resp.body_mut());
+                    *resp.body_mut() = limit_body(body, limit, Arc::clone(&counters));
+                }
+            }
+        }
+
+        if !config.json_strip_fields.is_empty() {
+            resp = strip_json_fields(resp, &config.json_strip_fields, config.json_strip_max_bytes)
+                .await?;
+        }
+    }
+
+    if let Some(capture) = &capture {
+        resp = capture.response(resp);
+    }
+
+    let from = match config.anonymize_ips {
+        Some(_) => requester.clone(),
+        None => requester_ip.to_string(),
+    };
+    info!(
+        target: "narrow::access",
+        from = %from,
+        upstream = %upstream,
+        redirects,
+        micros = duration.as_micros() as u64,
+        "{} {} - Response time: {:?}",
         req_method,
         req_uri,
-        requester_ip,
         duration
     );
 
-    loglist.lock().unwrap().push(Log {
+    let log = Log {
         timestamp,
-        req_method,
+        req_method: req_method.clone(),
         req_uri: req_uri.to_string(),
-        requester_ip: requester_ip.ip().to_string(),
+        requester_ip: requester,
+        upstream,
+        redirects,
         micros: duration.as_micros(),
-    });
+        endpoint: endpoint.clone(),
+        status: resp.status().as_u16(),
+    };
+    if let Some(recent) = &recent {
+        recent.push(log.clone());
+    }
+    let status = log.status;
+    loglist.lock().unwrap_or_else(PoisonError::into_inner).push(log);
 
-    let mut histograms = histograms.lock().unwrap();
-    histograms.entry("Overall".to_string()).or_default().add(duration, timestamp);
+    let request_bytes = request_size.bytes();
+    let stats = Arc::clone(&histograms);
+    let mut histograms = counters.lock_stats(&histograms);
+    let overall = histograms.entry("Overall".to_string()).or_default();
+    overall.add(duration, timestamp);
+    overall.add_status(status);
+    overall.request_sizes.add(request_bytes);
+    overall.methods.add(&req_method);
+    if config.track_hourly {
+        overall.hourly.add(timestamp);
+    }
+    if let Some(waited) = queue_wait {
+        overall.queue_waits.add(waited);
+    }
+    if let Some((prepare, connect, upstream)) = phases {
+        overall.phases.add(prepare, connect, upstream);
+    }
 
-    histograms.entry(req_uri.path().to_string()).or_default().add(duration, timestamp);
+    let endpoint = capped_endpoint(&histograms, endpoint, config.max_endpoints);
+    let hist = histograms.entry(endpoint.clone()).or_default();
+    hist.add(duration, timestamp);
+    hist.add_status(status);
+    hist.request_sizes.add(request_bytes);
+    hist.methods.add(&req_method);
+    if config.track_hourly {
+        hist.hourly.add(timestamp);
+    }
+    if let Some(waited) = queue_wait {
+        hist.queue_waits.add(waited);
+    }
+    if let Some((prepare, connect, upstream)) = phases {
+        hist.phases.add(prepare, connect, upstream);
+    }
+    drop(histograms);
+
+    if phases.is_some() && req_method != Method::HEAD {
+        let body = std::mem::take(resp.body_mut());
+        *resp.body_mut() = time_body(body, BodyTimer::start(stats, counters, endpoint));
+    }
 
     Ok(resp)
 }
+
+/// Records how long a response body took to stream to the client with `--profile`, once it is
+/// dropped after its end or because the client went away
+struct BodyTimer {
+    start: Instant,
+    histograms: HistogramMap,
+    counters: SharedCounters,
+    endpoint: String,
+}
+
+impl BodyTimer {
+    fn start(histograms: HistogramMap, counters: SharedCounters, endpoint: String) -> Self {
+        BodyTimer { start: Instant::now(), histograms, counters, endpoint }
+    }
+}
+
+impl Drop for BodyTimer {
+    fn drop(&mut self) {
+        let streamed = self.start.elapsed();
+        let mut histograms = self.counters.lock_stats(&self.histograms);
+        histograms.entry("Overall".to_string()).or_default().phases.add_body(streamed);
+        // The endpoint may have expired with `--endpoint-ttl` in the meantime
+        if let Some(hist) = histograms.get_mut(&self.endpoint) {
+            hist.phases.add_body(streamed);
+        }
+    }
+}
+
+/// Keep `timer` alive for as long as the body is streamed
+fn time_body(body: Body, timer: BodyTimer) -> Body {
+    Body::wrap_stream(body.inspect_ok(move |_| {
+        let _ = &timer;
+    }))
+}
+
+/// The size of a request body, known up front or counted while it streams to the target server
+enum RequestSize {
+    Known(u64),
+    Counted(Arc<AtomicU64>),
+}
+
+impl RequestSize {
+    fn bytes(&self) -> u64 {
+        match self {
+            RequestSize::Known(bytes) => *bytes,
+            RequestSize::Counted(counted) => counted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Wrap a request body so its size can be recorded. Bodies of a known length are passed through
+/// as they are, so only chunked uploads are counted chunk by chunk.
+fn measure_body(body: Body) -> (Body, RequestSize) {
+    if body.is_end_stream() {
+        return (body, RequestSize::Known(0));
+    }
+    if let Some(bytes) = body.size_hint().exact() {
+        return (body, RequestSize::Known(bytes));
+    }
+
+    let counted = Arc::new(AtomicU64::new(0));
+    let counter = Arc::clone(&counted);
+    let body = Body::wrap_stream(body.inspect_ok(move |chunk| {
+        counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+    }));
+
+    (body, RequestSize::Counted(counted))
+}
+
+/// Whether the request failed while resolving the target host, as opposed to connecting to it
+fn is_dns_error(err: &hyper::Error) -> bool {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if e.to_string().starts_with("dns error") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// The size of the header fields as sent on the wire, `name: value\r\n` for each of them
+fn header_bytes(headers: &HeaderMap) -> usize {
+    headers.iter().map(|(name, value)| name.as_str().len() + value.len() + 4).sum()
+}
+
+/// The message a handler panicked with, as given to `panic!` or `unwrap`
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => {
+            payload.downcast_ref::<String>().cloned().unwrap_or_else(|| "unknown panic".to_string())
+        }
+    }
+}
+
+/// The client-supplied `X-Request-Id`, or a freshly generated one
+fn request_id(req: &Request<Body>) -> String {
+    req.headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::{Mutex, RwLock};
+    use std::time::Duration;
+
+    use clap::Parser;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::Server;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::client::build_client;
+    use crate::net::coalesce::Coalescer;
+    use crate::net::deadline::parse_grpc_timeout;
+    use crate::net::mock::MockUpstream;
+    use crate::net::queue::UpstreamLimiter;
+    use crate::state::{Blacklist, GeoBlocker, GeoInfo, GeoLookup, RecentRequests};
+    use crate::statistics::{RequesterCounts, StatsSnapshot};
+
+    #[derive(Clone)]
+    struct Harness {
+        histograms: HistogramMap,
+        loglist: LogList,
+        config: Arc<Config>,
+        counters: SharedCounters,
+        geo_blocker: SharedGeoBlocker,
+        limiter: SharedLimiter,
+        talkers: SharedTalkers,
+        recent: SharedRecent,
+        coalescer: SharedCoalescer,
+        upstreams: SharedUpstreams,
+    }
+
+    impl Harness {
+        fn new(upstream: &MockUpstream, extra_args: &[&str]) -> Self {
+            let port = upstream.addr.port().to_string();
+            Harness::with_target("127.0.0.1", &port, extra_args)
+        }
+
+        fn with_target(host: &str, port: &str, extra_args: &[&str]) -> Self {
+            let mut argv = vec!["test", "--host", host, "--port", port];
+            argv.extend_from_slice(extra_args);
+
+            let config = Config::from(&Args::parse_from(argv));
+            Harness {
+                histograms: Arc::new(Mutex::new(HashMap::new())),
+                loglist: Arc::new(Mutex::new(Vec::new())),
+                limiter: UpstreamLimiter::from_config(&config).map(Arc::new),
+                talkers: RequesterCounts::from_config(&config).map(Arc::new),
+                recent: RecentRequests::from_config(&config).map(Arc::new),
+                coalescer: Coalescer::from_config(&config).map(Arc::new),
+                config: Arc::new(config),
+                counters: Arc::new(Counters::default()),
+                geo_blocker: None,
+                upstreams: SharedUpstreams::default(),
+            }
+        }
+
+        async fn send(&self, req: Request<Body>) -> Response<Body> {
+            let blacklist = Arc::new(RwLock::new(Blacklist::new(&self.config.blacklist, vec![])));
+            proxy(
+                build_client(&self.config, Arc::default()),
+                req,
+                SocketAddr::from(([127, 0, 0, 1], 40000)),
+                Arc::clone(&self.histograms),
+                Arc::clone(&self.loglist),
+                Arc::clone(&self.config),
+                blacklist,
+                self.geo_blocker.clone(),
+                self.limiter.clone(),
+                Arc::clone(&self.counters),
+                self.talkers.clone(),
+                self.recent.clone(),
+                self.coalescer.clone(),
+                Arc::clone(&self.upstreams),
+            )
+            .await
+            .unwrap()
+        }
+
+        /// Serve the proxy on a local port, for tests that need a real client connection
+        async fn serve(&self) -> SocketAddr {
+            let harness = self.clone();
+            let make_svc = make_service_fn(move |_| {
+                let harness = harness.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        let harness = harness.clone();
+                        async move { Ok::<_, Infallible>(harness.send(req).await) }
+                    }))
+                }
+            });
+
+            let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+            let addr = server.local_addr();
+            tokio::spawn(server);
+            addr
+        }
+    }
+
+    /// Read up to and including the blank line that ends a response head
+    async fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_request() {
+        let upstream = MockUpstream::start(StatusCode::CREATED, "created", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost:8000/items?id=1")
+            .header("x-custom", "value")
+            .body(Body::from("payload"))
+            .unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "created");
+
+        let received = upstream.requests();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, Method::POST);
+        assert_eq!(received[0].uri, "/items?id=1");
+        assert_eq!(received[0].headers["x-custom"], "value");
+        assert_eq!(received[0].headers["x-forwarded-proto"], "http");
+        assert_eq!(received[0].headers["x-forwarded-port"], "8000");
+        assert_eq!(received[0].body, "payload");
+        assert_eq!(harness.histograms.lock().unwrap()["/items"].methods.post, 1);
+
+        let loglist = harness.loglist.lock().unwrap();
+        assert_eq!(loglist.len(), 1);
+        assert_eq!(loglist[0].req_method, Method::POST);
+        assert_eq!(loglist[0].req_uri, "http://localhost:8000/items?id=1");
+        assert_eq!(loglist[0].requester_ip, "127.0.0.1");
+        assert_eq!(loglist[0].upstream, upstream.addr.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_records_histogram_bucket() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(150)).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let req = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let histograms = harness.histograms.lock().unwrap();
+        for endpoint in ["Overall", "/slow"] {
+            let hist = &histograms[endpoint];
+            assert_eq!(hist.count_101_250, 1);
+            assert_eq!(hist.total_requests, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_groups_by_header() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness =
+            Harness::new(&upstream, &["--group-by-header", "X-Tenant-Id", "--max-endpoints", "2"]);
+
+        for tenant in [Some("acme"), None, Some("globex"), Some("initech")] {
+            let mut req = Request::builder().uri("/api");
+            if let Some(tenant) = tenant {
+                req = req.header("x-tenant-id", tenant);
+            }
+            harness.send(req.body(Body::empty()).unwrap()).await;
+        }
+
+        let histograms = harness.histograms.lock().unwrap();
+        let mut keys = histograms.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec!["Other", "Overall", "x-tenant-id=acme /api", "x-tenant-id=unknown /api"]
+        );
+        assert_eq!(histograms["Other"].total_requests, 2);
+        assert_eq!(histograms["Overall"].total_requests, 4);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rewrites_upstream_path() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(
+            &upstream,
+            &["--strip-prefix", "/api", "--upstream-path-prefix", "/service-a/"],
+        );
+
+        let req = Request::builder().uri("/api/users?page=2").body(Body::empty()).unwrap();
+        harness.send(req).await;
+
+        assert_eq!(upstream.requests()[0].uri, "/service-a/users?page=2");
+        assert!(harness.histograms.lock().unwrap().contains_key("/api/users"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_normalizes_trailing_slash() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--normalize-trailing-slash", "strip"]);
+
+        for uri in ["/users", "/users/", "/"] {
+            harness.send(Request::builder().uri(uri).body(Body::empty()).unwrap()).await;
+        }
+
+        let histograms = harness.histograms.lock().unwrap();
+        assert_eq!(histograms["/users"].total_requests, 2);
+        assert_eq!(histograms["/"].total_requests, 1);
+        assert!(!histograms.contains_key("/users/"));
+
+        // Only the histogram key is normalized unless asked otherwise
+        assert_eq!(upstream.requests()[1].uri, "/users/");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_serves_static_files() {
+        let dir = std::env::temp_dir().join(format!("narrow-static-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("status.html"), "<p>ok</p>").unwrap();
+
+        let upstream = MockUpstream::start(StatusCode::OK, "upstream", Duration::ZERO).await;
+        let harness = Harness::new(
+            &upstream,
+            &["--static-dir", dir.to_str().unwrap(), "--static-prefix", "/status"],
+        );
+
+        let req = Request::builder().uri("/status/status.html").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/html; charset=utf-8");
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "<p>ok</p>");
+
+        let req = Request::builder().uri("/status/%2e%2e/secret").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::builder().uri("/api").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "upstream");
+        assert_eq!(upstream.requests().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_records_upstream_errors() {
+        let upstream =
+            MockUpstream::start(StatusCode::SERVICE_UNAVAILABLE, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let req = Request::builder().uri("/down").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let histograms = harness.histograms.lock().unwrap();
+        assert_eq!(histograms["/down"].count_5xx, 1);
+        assert_eq!(histograms["Overall"].count_5xx, 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_blacklisted_ip() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--blacklist", "127.0.0.1"]);
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "Access denied");
+        assert!(upstream.requests().is_empty());
+        assert!(harness.histograms.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_json_errors() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness =
+            Harness::new(&upstream, &["--blacklist", "127.0.0.1", "--error-format", "json"]);
+
+        let req = Request::builder().uri("/").header("x-request-id", "abc").body(Body::empty());
+        let resp = harness.send(req.unwrap()).await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "Access denied", "status": 403, "request_id": "abc"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_reports_dns_errors() {
+        let harness = Harness::with_target("narrow.invalid", "3000", &[]);
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await.unwrap(),
+            "Could not resolve target host narrow.invalid"
+        );
+        assert_eq!(harness.counters.dns_errors.load(Ordering::Relaxed), 1);
+        assert_eq!(harness.counters.connect_errors.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_reports_dns_errors_of_routed_hosts() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--header-route", "X-API-Version=2:v2.invalid:80"]);
+
+        let req = Request::get("/").header("x-api-version", "2").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await.unwrap(),
+            "Could not resolve target host v2.invalid"
+        );
+        assert_eq!(harness.counters.dns_errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_reports_connect_errors() {
+        // Grab a free port and close it again so nothing is listening there
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let harness = Harness::with_target("127.0.0.1", &port.to_string(), &[]);
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(harness.counters.dns_errors.load(Ordering::Relaxed), 0);
+        assert_eq!(harness.counters.connect_errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_times_out() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(300)).await;
+        let harness = Harness::new(&upstream, &["--timeout-ms", "100"]);
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(harness.counters.timeouts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_endpoint_timeout() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(300)).await;
+        let harness =
+            Harness::new(&upstream, &["--timeout-ms", "100", "--endpoint-timeout", "/report=1000"]);
+
+        let req = Request::builder().uri("/report/monthly").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::OK);
+        let req = Request::builder().uri("/other").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_propagates_deadline() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(300)).await;
+        let harness = Harness::new(&upstream, &["--timeout-ms", "5000"]);
+
+        // The client's deadline is shorter than the global timeout
+        let req = Request::builder().uri("/").header("x-request-timeout", "100");
+        assert_eq!(
+            harness.send(req.body(Body::empty()).unwrap()).await.status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+
+        // The client's deadline is capped by the global timeout and forwarded
+        let req = Request::builder().uri("/").header("grpc-timeout", "1H");
+        assert_eq!(harness.send(req.body(Body::empty()).unwrap()).await.status(), StatusCode::OK);
+
+        let forwarded =
+            upstream.requests()[1].headers["grpc-timeout"].to_str().unwrap().to_string();
+        let remaining = parse_grpc_timeout(&forwarded).unwrap();
+        assert!(remaining <= Duration::from_secs(5) && remaining > Duration::from_secs(4));
+    }
+
+    /// Wait for background work such as mirrored requests to satisfy `done`
+    async fn eventually(done: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_mirrors_requests() {
+        let upstream = MockUpstream::start(StatusCode::OK, "primary", Duration::ZERO).await;
+        let shadow = MockUpstream::start(StatusCode::OK, "mirror", Duration::ZERO).await;
+        let mirror_to = shadow.addr.to_string();
+        let harness = Harness::new(&upstream, &["--mirror-to", &mirror_to]);
+
+        let req = Request::builder().method(Method::PUT).uri("/items/1").body(Body::from("data"));
+        let resp = harness.send(req.unwrap()).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "primary");
+
+        eventually(|| harness.histograms.lock().unwrap().contains_key("mirror /items/1")).await;
+        let mirrored = shadow.requests();
+        assert_eq!(mirrored[0].method, Method::PUT);
+        assert_eq!(mirrored[0].uri, "/items/1");
+        assert_eq!(mirrored[0].body, "data");
+        assert_eq!(upstream.requests()[0].body, "data");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_ignores_mirror_failures() {
+        let upstream = MockUpstream::start(StatusCode::OK, "primary", Duration::ZERO).await;
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mirror_to = format!("127.0.0.1:{}", port);
+        let harness = Harness::new(&upstream, &["--mirror-to", &mirror_to]);
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::OK);
+
+        eventually(|| harness.counters.mirror_errors.load(Ordering::Relaxed) == 1).await;
+    }
+
+    #[tokio::test]
+    async fn test_proxy_times_out_mirror_requests() {
+        let upstream = MockUpstream::start(StatusCode::OK, "primary", Duration::ZERO).await;
+        let shadow = MockUpstream::start(StatusCode::OK, "", Duration::from_secs(5)).await;
+        let mirror_to = shadow.addr.to_string();
+        let args = ["--mirror-to", &mirror_to, "--mirror-timeout-ms", "50"];
+        let harness = Harness::new(&upstream, &args);
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::OK);
+
+        eventually(|| harness.counters.mirror_errors.load(Ordering::Relaxed) == 1).await;
+        assert!(!harness.histograms.lock().unwrap().contains_key("mirror /"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_strips_sensitive_headers() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let req = || {
+            Request::builder()
+                .uri("/")
+                .header("x-client-cert", "forged")
+                .header("x-other", "kept")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let untrusted = Harness::new(&upstream, &["--sensitive-headers", "X-Client-Cert"]);
+        untrusted.send(req()).await;
+
+        let trusted = Harness::new(
+            &upstream,
+            &["--sensitive-headers", "X-Client-Cert", "--trusted-proxies", "127.0.0.0/8"],
+        );
+        trusted.send(req()).await;
+
+        let received = upstream.requests();
+        assert!(!received[0].headers.contains_key("x-client-cert"));
+        assert_eq!(received[0].headers["x-other"], "kept");
+        assert_eq!(received[1].headers["x-client-cert"], "forged");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_records_request_sizes() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let req = Request::builder().method(Method::POST).uri("/upload");
+        harness.send(req.body(Body::from(vec![0u8; 2048])).unwrap()).await;
+
+        let chunks: Vec<Result<_, std::io::Error>> = vec![Ok(vec![0u8; 600]), Ok(vec![0u8; 600])];
+        let req = Request::builder().method(Method::POST).uri("/upload");
+        harness
+            .send(req.body(Body::wrap_stream(futures_util::stream::iter(chunks))).unwrap())
+            .await;
+
+        harness.send(Request::builder().uri("/upload").body(Body::empty()).unwrap()).await;
+
+        let histograms = harness.histograms.lock().unwrap();
+        let sizes = &histograms["/upload"].request_sizes;
+        assert_eq!(sizes.buckets(), [1, 2, 0, 0, 0]);
+        assert_eq!(sizes.total_bytes, 3248);
+        assert_eq!(upstream.requests()[1].body.len(), 1200);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tunnels_upgraded_connections() {
+        // A target server that switches to echoing back whatever it receives
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_head(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nconnection: upgrade\r\nupgrade: echo\r\n\r\n")
+                .await
+                .unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.ok();
+        });
+
+        let harness = Harness::with_target("127.0.0.1", &port, &[]);
+        let mut stream = TcpStream::connect(harness.serve().await).await.unwrap();
+        stream
+            .write_all(b"GET /ws HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade\r\nupgrade: echo\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_head(&mut stream).await.starts_with("HTTP/1.1 101"));
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut echoed = [0; 4];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+
+        assert_eq!(harness.histograms.lock().unwrap()["/ws"].total_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_unrequested_upgrade() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_head(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nupgrade: echo\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let harness = Harness::with_target("127.0.0.1", &port, &[]);
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_expect_continue() {
+        let upstream = MockUpstream::start(StatusCode::OK, "done", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let mut stream = TcpStream::connect(harness.serve().await).await.unwrap();
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nhost: localhost\r\ncontent-length: 7\r\nexpect: 100-continue\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_head(&mut stream).await.starts_with("HTTP/1.1 100"));
+
+        stream.write_all(b"payload").await.unwrap();
+        assert!(read_head(&mut stream).await.starts_with("HTTP/1.1 200"));
+
+        let received = upstream.requests();
+        assert_eq!(received[0].headers["expect"], "100-continue");
+        assert_eq!(received[0].body, "payload");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_keeps_forwarded_proto_from_trusted_proxies() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let forwarded = || {
+            Request::get("/")
+                .header("x-forwarded-proto", "https")
+                .header("x-forwarded-port", "443")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        Harness::new(&upstream, &["--trusted-proxies", "127.0.0.1/32"]).send(forwarded()).await;
+        let req = Request::get("/").header("x-forwarded-port", "443").body(Body::empty());
+        Harness::new(&upstream, &["--trusted-proxies", "127.0.0.1/32"]).send(req.unwrap()).await;
+        // Anyone else could claim the connection was secure
+        Harness::new(&upstream, &[]).send(forwarded()).await;
+
+        let received = upstream.requests();
+        assert_eq!(received[0].headers["x-forwarded-proto"], "https");
+        assert_eq!(received[0].headers["x-forwarded-port"], "443");
+        assert_eq!(received[1].headers["x-forwarded-proto"], "http");
+        assert_eq!(received[1].headers["x-forwarded-port"], "443");
+        assert_eq!(received[2].headers["x-forwarded-proto"], "http");
+        assert_eq!(received[2].headers["x-forwarded-port"], "8000");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_records_its_own_failures() {
+        let slow = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(500)).await;
+        let args = ["--timeout-ms", "50", "--endpoint-min-success", "/checkout=0.99"];
+        let harness = Harness::new(&slow, &args);
+        let resp = harness.send(Request::get("/checkout").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let snapshot = StatsSnapshot::take(&mut harness.histograms.lock().unwrap(), None);
+        for endpoint in ["Overall", "/checkout"] {
+            let hist = &snapshot.histograms[endpoint];
+            assert_eq!((hist.total_requests, hist.count_5xx), (1, 1));
+            assert!(hist.percentile(50.0).unwrap() >= 50.0);
+        }
+        let alerts = snapshot.success_alerts(&harness.config.endpoint_min_success);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].success_ratio, 0.0);
+
+        // Nothing listens on the port of a stopped target server
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let harness = Harness::with_target("127.0.0.1", &port.to_string(), &[]);
+        let resp = harness.send(Request::get("/items").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(harness.histograms.lock().unwrap()["/items"].count_5xx, 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_large_headers() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--max-request-header-bytes", "64"]);
+
+        let req = Request::builder().uri("/").header("x-small", "a".repeat(32));
+        let resp = harness.send(req.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = Request::builder().uri("/").header("x-large", "a".repeat(64));
+        let resp = harness.send(req.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_long_uris() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--max-uri-bytes", "32"]);
+
+        let uri = format!("/search?q={}", "a".repeat(22));
+        let resp = harness.send(Request::builder().uri(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let uri = format!("/search?q={}", "a".repeat(23));
+        let resp = harness.send(Request::builder().uri(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_strips_json_fields() {
+        let headers = &[("content-type", "application/json")];
+        let body = r#"{"id":1,"debug":"x"}"#;
+        let upstream =
+            MockUpstream::start_with_headers(StatusCode::OK, headers, body, Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--json-strip-fields", "debug"]);
+
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), r#"{"id":1}"#);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_blocked_country() {
+        struct Netherlands;
+
+        impl GeoLookup for Netherlands {
+            fn lookup(&self, _ip: std::net::IpAddr) -> GeoInfo {
+                GeoInfo { country: Some("NL".to_string()), asn: None }
+            }
+        }
+
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let mut harness = Harness::new(&upstream, &[]);
+        let countries = vec!["NL".to_string()];
+        harness.geo_blocker =
+            Some(Arc::new(GeoBlocker::new(Box::new(Netherlands), &countries, &[])));
+
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_answers_panics_with_500() {
+        struct Broken;
+
+        impl GeoLookup for Broken {
+            fn lookup(&self, _ip: std::net::IpAddr) -> GeoInfo {
+                panic!("database closed");
+            }
+        }
+
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let mut harness = Harness::new(&upstream, &[]);
+        harness.geo_blocker = Some(Arc::new(GeoBlocker::new(Box::new(Broken), &[], &[])));
+
+        let resp =
+            harness.send(Request::builder().uri("/items").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(upstream.requests().is_empty());
+        assert_eq!(harness.counters.panics.load(Ordering::Relaxed), 1);
+        let loglist = harness.loglist.lock().unwrap();
+        assert_eq!(loglist[0].status, 500);
+        assert_eq!(loglist[0].req_uri, "/items");
+        let histograms = harness.histograms.lock().unwrap();
+        assert_eq!(histograms["Overall"].count_5xx, 1);
+        assert!(!histograms.contains_key("/items"));
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("code {}", 7)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "code 7");
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(7)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "unknown panic");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_delays_greylisted_clients() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let args = ["--greylist", "127.0.0.0/8=150", "--max-uri-bytes", "100"];
+        let harness = Harness::new(&upstream, &args);
+
+        let start = Instant::now();
+        let resp = harness.send(Request::get("/items").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(harness.counters.greylisted.load(Ordering::Relaxed), 1);
+        assert_eq!(harness.histograms.lock().unwrap()["/items"].count_0_10, 1);
+
+        // Cheap rejections don't wait
+        let start = Instant::now();
+        let uri = format!("/{}", "a".repeat(100));
+        let resp = harness.send(Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+        assert!(start.elapsed() < Duration::from_millis(150));
+        assert_eq!(harness.counters.greylisted.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_when_queue_is_full() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(200)).await;
+        let harness =
+            Harness::new(&upstream, &["--max-upstream-requests", "1", "--max-queued", "0"]);
+
+        let in_flight = {
+            let harness = harness.clone();
+            tokio::spawn(async move {
+                harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await
+            })
+        };
+        eventually(|| upstream.requests().len() == 1).await;
+
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(in_flight.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(harness.counters.queue_rejections.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_connect_timeout() {
+        // A listener that never accepts, once its backlog is full new connections hang
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        while let Ok(Ok(stream)) =
+            time::timeout(Duration::from_millis(100), TcpStream::connect(addr)).await
+        {
+            backlog.push(stream);
+        }
+
+        let port = addr.port().to_string();
+        let harness = Harness::with_target("127.0.0.1", &port, &["--connect-timeout-ms", "100"]);
+
+        let started = Instant::now();
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(harness.counters.connect_errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_answers_favicon_and_robots_locally() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--serve-favicon", "--robots", "Disallow: /"]);
+
+        for (path, status) in
+            [("/favicon.ico", StatusCode::NO_CONTENT), ("/robots.txt", StatusCode::OK)]
+        {
+            let resp =
+                harness.send(Request::builder().uri(path).body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), status);
+        }
+        assert!(upstream.requests().is_empty());
+        assert!(harness.histograms.lock().unwrap().is_empty());
+
+        let harness = Harness::new(&upstream, &[]);
+        harness.send(Request::builder().uri("/favicon.ico").body(Body::empty()).unwrap()).await;
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_follows_redirects() {
+        // A target server that moved everything under /old to /new
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let path_and_query = req.uri().path_and_query().unwrap().as_str();
+                let resp = match path_and_query.strip_prefix("/old") {
+                    Some(rest) => Response::builder()
+                        .status(StatusCode::SEE_OTHER)
+                        .header("location", format!("/new{}", rest)),
+                    None => Response::builder().header("x-method", req.method().as_str()),
+                };
+                Ok::<_, Infallible>(resp.body(Body::from(req.uri().to_string())).unwrap())
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let port = server.local_addr().port().to_string();
+        tokio::spawn(server);
+
+        let harness = Harness::with_target("127.0.0.1", &port, &["--follow-redirects", "3"]);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/old/items?id=1")
+            .body(Body::from("payload"))
+            .unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["x-method"], "GET");
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "/new/items?id=1");
+        assert_eq!(harness.loglist.lock().unwrap()[0].redirects, 1);
+
+        // Not followed unless asked to
+        let harness = Harness::with_target("127.0.0.1", &port, &[]);
+        let resp = harness.send(Request::builder().uri("/old").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert_eq!(harness.loglist.lock().unwrap()[0].redirects, 0);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_stops_at_redirect_loops_and_other_hosts() {
+        let looping = MockUpstream::start_with_headers(
+            StatusCode::FOUND,
+            &[("location", "/again")],
+            "",
+            Duration::ZERO,
+        )
+        .await;
+        let harness = Harness::new(&looping, &["--follow-redirects", "5"]);
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(looping.requests().len(), 2);
+        assert_eq!(harness.loglist.lock().unwrap()[0].redirects, 1);
+
+        let elsewhere = MockUpstream::start_with_headers(
+            StatusCode::FOUND,
+            &[("location", "http://elsewhere.example/")],
+            "",
+            Duration::ZERO,
+        )
+        .await;
+        let harness = Harness::new(&elsewhere, &["--follow-redirects", "5"]);
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(elsewhere.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_adds_via() {
+        let upstream = MockUpstream::start_with_headers(
+            StatusCode::OK,
+            &[("via", "1.1 origin-cache")],
+            "",
+            Duration::ZERO,
+        )
+        .await;
+        let harness = Harness::new(&upstream, &["--via-name", "edge-1"]);
+        let req =
+            Request::builder().uri("/").header("via", "1.0 corp").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(upstream.requests()[0].headers["via"], "1.0 corp, 1.1 edge-1");
+        assert_eq!(resp.headers()["via"], "1.1 origin-cache, 1.1 edge-1");
+
+        let harness = Harness::new(&upstream, &["--no-via"]);
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert!(!upstream.requests()[1].headers.contains_key("via"));
+        assert_eq!(resp.headers()["via"], "1.1 origin-cache");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_detects_loops() {
+        // A proxy pointed at its own port
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let harness = Harness::with_target("127.0.0.1", &port, &[]);
+        let svc_harness = harness.clone();
+        let make_svc = make_service_fn(move |_| {
+            let harness = svc_harness.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let harness = harness.clone();
+                    async move { Ok::<_, Infallible>(harness.send(req).await) }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_svc));
+
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::LOOP_DETECTED);
+        assert_eq!(harness.histograms.lock().unwrap()["/"].total_requests, 1);
+
+        // Without a Via header there is nothing to recognize the loop by
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--no-via"]);
+        let req =
+            Request::builder().uri("/").header("via", "1.1 narrow").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_head_has_no_body() {
+        let upstream = MockUpstream::start_with_headers(
+            StatusCode::OK,
+            &[("content-type", "application/json")],
+            r#"{"id":1,"debug":true}"#,
+            Duration::ZERO,
+        )
+        .await;
+        let harness = Harness::new(&upstream, &["--json-strip-fields", "debug"]);
+
+        let req =
+            Request::builder().method(Method::HEAD).uri("/items").body(Body::empty()).unwrap();
+        let resp = harness.send(req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-length"], "21");
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        assert!(hyper::body::to_bytes(resp.into_body()).await.unwrap().is_empty());
+        assert_eq!(upstream.requests()[0].method, Method::HEAD);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_denied_paths() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--deny-path", "^/admin", "--deny-status", "403"]);
+
+        let resp =
+            harness.send(Request::builder().uri("/admin/users").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "Forbidden");
+
+        let resp =
+            harness.send(Request::builder().uri("/items").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_large_responses() {
+        let upstream = MockUpstream::start(StatusCode::OK, "0123456789", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--max-response-bytes", "8"]);
+
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(harness.counters.oversized_responses.load(Ordering::Relaxed), 1);
+
+        let harness = Harness::new(&upstream, &["--max-response-bytes", "10"]);
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_cuts_off_large_streamed_responses() {
+        // A target server streaming a chunked body without announcing its length
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_head(&mut stream).await;
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+                      5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n",
+                )
+                .await
+                .unwrap();
+        });
+
+        let harness = Harness::with_target("127.0.0.1", &port, &["--max-response-bytes", "8"]);
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(hyper::body::to_bytes(resp.into_body()).await.is_err());
+        assert_eq!(harness.counters.oversized_responses.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_preserves_raw_uri() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--preserve-raw-uri"]);
+
+        let uri = "/files/a%2Fb%2fc/%7Euser?sig=abc%3D%3D&x=%20";
+        harness.send(Request::builder().uri(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(upstream.requests()[0].uri, uri);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tracks_hourly() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let untracked = Harness::new(&upstream, &[]);
+        untracked.send(Request::builder().uri("/api").body(Body::empty()).unwrap()).await;
+        assert_eq!(untracked.histograms.lock().unwrap()["/api"].hourly.counts(), None);
+
+        let harness = Harness::new(&upstream, &["--track-hourly"]);
+        harness.send(Request::builder().uri("/api").body(Body::empty()).unwrap()).await;
+        harness.send(Request::builder().uri("/api").body(Body::empty()).unwrap()).await;
+
+        let histograms = harness.histograms.lock().unwrap();
+        let hourly = histograms["/api"].hourly.counts().unwrap();
+        assert_eq!(hourly.iter().sum::<u64>(), 2);
+        assert_eq!(histograms["Overall"].hourly.counts(), Some(hourly));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_counts_top_talkers() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--top-talkers", "5", "--deny-path", "/admin"]);
+
+        harness.send(Request::builder().uri("/api").body(Body::empty()).unwrap()).await;
+        let resp =
+            harness.send(Request::builder().uri("/admin").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let talkers = harness.talkers.as_ref().unwrap().take();
+        assert_eq!(talkers.top, vec![("127.0.0.1".to_string(), 2)]);
+        assert!(Harness::new(&upstream, &[]).talkers.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_preserves_header_order() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+
+        for (extra_args, expected) in [
+            (&["--sensitive-headers", "x-secret"][..], ["x-date", "x-nonce", "x-body-hash"]),
+            (
+                &["--sensitive-headers", "x-secret", "--preserve-header-order"][..],
+                ["x-date", "x-body-hash", "x-nonce"],
+            ),
+        ] {
+            let harness = Harness::new(&upstream, extra_args);
+            let mut req = Request::builder().uri("/signed");
+            for name in ["x-date", "x-secret", "x-body-hash", "x-nonce"] {
+                req = req.header(name, "1");
+            }
+            harness.send(req.body(Body::empty()).unwrap()).await;
+
+            let received = upstream.requests().pop().unwrap();
+            let names: Vec<_> = received
+                .headers
+                .keys()
+                .map(|name| name.as_str())
+                .filter(|name| name.starts_with("x-") && !name.starts_with("x-forwarded"))
+                .collect();
+            assert_eq!(names, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_counts_in_flight_requests() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(200)).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let sending = {
+            let harness = harness.clone();
+            tokio::spawn(async move {
+                harness.send(Request::builder().uri("/slow").body(Body::empty()).unwrap()).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(harness.counters.in_flight.load(Ordering::Relaxed), 1);
+
+        sending.await.unwrap();
+        assert_eq!(harness.counters.in_flight.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_profiles_phases() {
+        let upstream =
+            MockUpstream::start(StatusCode::OK, "items", Duration::from_millis(100)).await;
+        let harness = Harness::new(&upstream, &["--profile"]);
+
+        let resp = harness.send(Request::get("/items").body(Body::empty()).unwrap()).await;
+        let phases = harness.histograms.lock().unwrap()["/items"].phases.clone();
+        assert_eq!((phases.requests, phases.bodies), (1, 0));
+        assert!(phases.upstream_micros >= 100_000);
+        assert!(phases.prepare_micros < 100_000);
+
+        // The body is recorded once it has been streamed
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "items");
+        let histograms = harness.histograms.lock().unwrap();
+        assert_eq!(histograms["/items"].phases.bodies, 1);
+        assert_eq!(histograms["Overall"].phases.bodies, 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_records_queue_waits() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(100)).await;
+        let harness = Harness::new(&upstream, &["--max-upstream-requests", "1", "--fair-queueing"]);
+
+        let request = || Request::builder().uri("/api").body(Body::empty()).unwrap();
+        tokio::join!(harness.send(request()), harness.send(request()));
+
+        let histograms = harness.histograms.lock().unwrap();
+        let waits = &histograms["/api"].queue_waits;
+        assert_eq!(waits.queued, 1);
+        assert!(waits.average().unwrap() >= Duration::from_millis(50));
+        assert_eq!(histograms["Overall"].queue_waits, *waits);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_anonymizes_ips() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(
+            &upstream,
+            &["--anonymize-ips", "truncate", "--top-talkers", "1", "--blacklist", "127.0.0.1"],
+        );
+        let resp = harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let harness =
+            Harness::new(&upstream, &["--anonymize-ips", "truncate", "--top-talkers", "1"]);
+        harness.send(Request::builder().uri("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(harness.loglist.lock().unwrap()[0].requester_ip, "127.0.0.0/24");
+        let talkers = harness.talkers.as_ref().unwrap().take();
+        assert_eq!(talkers.top, vec![("127.0.0.0/24".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_captures_sampled_requests() {
+        let upstream = MockUpstream::start(StatusCode::OK, "created", Duration::ZERO).await;
+        let dir = std::env::temp_dir().join(format!("narrow-proxy-capture-{}", std::process::id()));
+        let dir_arg = dir.to_str().unwrap();
+        let args = [
+            "--capture-dir",
+            dir_arg,
+            "--capture-sample-rate",
+            "1",
+            "--sensitive-headers",
+            "x-token",
+        ];
+        let harness = Harness::new(&upstream, &args);
+
+        let req = Request::post("/orders")
+            .header("x-request-id", "order-1")
+            .header("x-token", "secret")
+            .header("content-length", "5")
+            .body(Body::from("hello"))
+            .unwrap();
+        let resp = harness.send(req).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "created");
+
+        // Captures don't change what the target server gets
+        let received = &upstream.requests()[0];
+        assert_eq!(received.body, "hello");
+        assert_eq!(received.headers["content-length"], "5");
+
+        let path = dir.join("order-1.http");
+        for _ in 0..100 {
+            if std::fs::read_to_string(&path).is_ok_and(|contents| contents.ends_with("created\n"))
+            {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("POST /orders HTTP/1.1\n"));
+        assert!(contents.contains("x-token: [redacted]\n"));
+        assert!(contents.contains("\n\nhello\n\nHTTP/1.1 200 OK\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_spills_large_bodies() {
+        let upstream = MockUpstream::start(StatusCode::OK, "primary", Duration::ZERO).await;
+        let shadow = MockUpstream::start(StatusCode::OK, "mirror", Duration::ZERO).await;
+        let dir = std::env::temp_dir().join(format!("narrow-proxy-spill-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (mirror_to, spill_dir) = (shadow.addr.to_string(), dir.to_str().unwrap());
+        let args = [
+            "--mirror-to",
+            &mirror_to,
+            "--spill-to-disk",
+            spill_dir,
+            "--spill-threshold-bytes",
+            "4",
+        ];
+        let harness = Harness::new(&upstream, &args);
+
+        let req =
+            Request::builder().method(Method::PUT).uri("/items/1").body(Body::from("0123456789"));
+        let resp = harness.send(req.unwrap()).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "primary");
+
+        eventually(|| harness.histograms.lock().unwrap().contains_key("mirror /items/1")).await;
+        assert_eq!(upstream.requests()[0].body, "0123456789");
+        assert_eq!(shadow.requests()[0].body, "0123456789");
+        eventually(|| std::fs::read_dir(&dir).unwrap().count() == 0).await;
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_routes_by_header() {
+        let upstream = MockUpstream::start(StatusCode::OK, "v1", Duration::ZERO).await;
+        let v2 = MockUpstream::start(StatusCode::OK, "v2", Duration::ZERO).await;
+        let route = format!("X-API-Version=2*:{}", v2.addr);
+        let harness = Harness::new(&upstream, &["--header-route", &route]);
+
+        for (version, expected) in [("2.1", "v2"), ("1", "v1")] {
+            let req = Request::get("/items").header("x-api-version", version).body(Body::empty());
+            let resp = harness.send(req.unwrap()).await;
+            assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), expected);
+        }
+        let resp = harness.send(Request::get("/items").body(Body::empty()).unwrap()).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "v1");
+
+        assert_eq!(v2.requests().len(), 1);
+        assert_eq!(upstream.requests().len(), 2);
+        assert_eq!(harness.loglist.lock().unwrap()[0].upstream, v2.addr.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_serves_recent_requests() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--recent-buffer", "2"]);
+
+        for uri in ["/first", "/second", "/third"] {
+            harness.send(Request::get(uri).body(Body::empty()).unwrap()).await;
+            // The log list is drained every interval, the recent requests are not
+            harness.loglist.lock().unwrap().clear();
+        }
+
+        let resp =
+            harness.send(Request::get("/__narrow/recent").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let recent = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(recent["requests"][0]["uri"], "/third");
+        assert_eq!(recent["requests"][1]["uri"], "/second");
+        assert_eq!(recent["requests"].as_array().unwrap().len(), 2);
+        assert_eq!(upstream.requests().len(), 3);
+
+        // Without a buffer the path is forwarded like any other
+        let harness = Harness::new(&upstream, &[]);
+        harness.send(Request::get("/__narrow/recent").body(Body::empty()).unwrap()).await;
+        assert_eq!(upstream.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_refuses_admin_api_to_trusted_proxies() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let args = ["--recent-buffer", "2", "--trusted-proxies", "127.0.0.1/32"];
+        let harness = Harness::new(&upstream, &args);
+
+        let resp =
+            harness.send(Request::get("/__narrow/recent").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_requires_admin_token() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--recent-buffer", "2", "--admin-token", "s3cret"]);
+
+        let resp =
+            harness.send(Request::get("/__narrow/recent").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers()[WWW_AUTHENTICATE], "Bearer");
+
+        let req = Request::get("/__narrow/recent").header("authorization", "Bearer s3cret");
+        let resp = harness.send(req.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_serves_current_stats() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--serve-stats"]);
+        harness.send(Request::get("/items").body(Body::empty()).unwrap()).await;
+
+        let resp = harness.send(Request::get("/__narrow/stats").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let stats = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(stats["endpoints"]["/items"]["requests"], 1);
+        assert!(stats["endpoints"]["/items"]["last_request"].is_string());
+        assert_eq!(stats["endpoints"]["/items"]["generation"], 0);
+        assert_eq!(stats["upstreams"][upstream.addr.to_string()]["requests"], 1);
+        // Neither forwarded nor counted, and nothing was reset
+        assert_eq!(upstream.requests().len(), 1);
+        assert_eq!(harness.histograms.lock().unwrap()["/items"].total_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_stats_path_unless_enabled() {
+        let upstream = MockUpstream::start(StatusCode::OK, "backend stats", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &[]);
+
+        let resp = harness.send(Request::get("/__narrow/stats").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "backend stats");
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_counts_requests_per_upstream() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let failing = MockUpstream::start(StatusCode::BAD_GATEWAY, "", Duration::ZERO).await;
+        let slow = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(500)).await;
+
+        let harness = Harness::new(&upstream, &["--timeout-ms", "100"]);
+        harness.send(Request::get("/").body(Body::empty()).unwrap()).await;
+        for target in [&failing, &slow] {
+            let harness = Harness {
+                upstreams: Arc::clone(&harness.upstreams),
+                ..Harness::new(target, &["--timeout-ms", "100"])
+            };
+            harness.send(Request::get("/").body(Body::empty()).unwrap()).await;
+        }
+
+        let stats = harness.upstreams.take().into_iter().collect::<HashMap<_, _>>();
+        let counts = |target: &MockUpstream| {
+            let stats = stats[&target.addr.to_string()];
+            (stats.requests, stats.errors, stats.timeouts)
+        };
+        assert_eq!(counts(&upstream), (1, 0, 0));
+        assert_eq!(counts(&failing), (1, 1, 0));
+        assert_eq!(counts(&slow), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_overrides_upstream_for_allowed_clients() {
+        let upstream = MockUpstream::start(StatusCode::OK, "default", Duration::ZERO).await;
+        let staging = MockUpstream::start(StatusCode::OK, "staging", Duration::ZERO).await;
+        let uri = format!("/items?page=2&__upstream={}", staging.addr);
+
+        // Ignored and stripped unless the client is allowed to use it
+        let harness = Harness::new(&upstream, &[]);
+        let resp = harness.send(Request::get(&uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "default");
+        assert_eq!(upstream.requests()[0].uri, "/items?page=2");
+
+        for extra in [&[][..], &["--preserve-raw-uri"][..]] {
+            let mut args = vec!["--allow-upstream-override", "127.0.0.0/8"];
+            args.extend_from_slice(extra);
+            let harness = Harness::new(&upstream, &args);
+            let resp = harness.send(Request::get(&uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "staging");
+        }
+        assert_eq!(staging.requests()[0].uri, "/items?page=2");
+        assert_eq!(staging.requests()[1].uri, "/items?page=2");
+
+        let harness = Harness::new(&upstream, &["--allow-upstream-override", "127.0.0.0/8"]);
+        let req = Request::get("/items?__upstream=staging").body(Body::empty()).unwrap();
+        assert_eq!(harness.send(req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_coalesces_identical_gets() {
+        let upstream =
+            MockUpstream::start(StatusCode::OK, "items", Duration::from_millis(200)).await;
+        let harness = Harness::new(&upstream, &["--coalesce"]);
+
+        let get = |uri: &'static str| {
+            let harness = harness.clone();
+            async move {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let resp = harness.send(req).await;
+                hyper::body::to_bytes(resp.into_body()).await.unwrap()
+            }
+        };
+        let (a, b, c, other) = tokio::join!(get("/items"), get("/items"), get("/items"), get("/x"));
+        assert_eq!([a, b, c, other], ["items"; 4]);
+        assert_eq!(upstream.requests().len(), 2);
+
+        let counters = harness.counters.take();
+        assert!(counters.contains(&("Coalesced requests", 2)));
+        assert_eq!(harness.histograms.lock().unwrap()["/items"].total_requests, 3);
+        assert_eq!(harness.upstreams.take()[0].1.requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rewrites_paths() {
+        let path =
+            std::env::temp_dir().join(format!("narrow-rewrite-{}.rules", std::process::id()));
+        std::fs::write(&path, "^/old/(.*)$ /new/$1\n^/bad/(.*)$ $1\n^/query$ /new?v=1\n").unwrap();
+        let rules = path.to_str().unwrap();
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+
+        let harness = Harness::new(&upstream, &["--rewrite-rules", rules]);
+        harness.send(Request::get("/old/items?page=2").body(Body::empty()).unwrap()).await;
+        harness.send(Request::get("/kept").body(Body::empty()).unwrap()).await;
+        assert_eq!(upstream.requests()[0].uri, "/new/items?page=2");
+        assert_eq!(upstream.requests()[1].uri, "/kept");
+        assert!(harness.histograms.lock().unwrap().contains_key("/old/items"));
+
+        let resp = harness.send(Request::get("/bad/x").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let resp = harness.send(Request::get("/query?orig=1").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(upstream.requests().len(), 2);
+
+        let harness =
+            Harness::new(&upstream, &["--rewrite-rules", rules, "--record-rewritten-path"]);
+        std::fs::remove_file(&path).unwrap();
+        harness.send(Request::get("/old/items").body(Body::empty()).unwrap()).await;
+        let histograms = harness.histograms.lock().unwrap();
+        assert!(histograms.contains_key("/new/items"));
+        assert!(!histograms.contains_key("/old/items"));
+
+        assert!(Args::try_parse_from(["test", "--rewrite-rules", rules]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_replaces_5xx_body_with_error_page() {
+        let path = std::env::temp_dir().join(format!("narrow-error-{}.html", std::process::id()));
+        std::fs::write(&path, "<h1>Sorry</h1>").unwrap();
+        let upstream =
+            MockUpstream::start(StatusCode::BAD_GATEWAY, "stack trace", Duration::ZERO).await;
+        let harness = Harness::new(&upstream, &["--error-page-5xx", path.to_str().unwrap()]);
+        std::fs::remove_file(&path).unwrap();
+
+        let resp = harness.send(Request::get("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(resp.headers()["content-type"], "text/html; charset=utf-8");
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await.unwrap(), "<h1>Sorry</h1>");
+
+        let args = ["test", "--error-page-5xx", "/nonexistent/error.html"];
+        assert!(Args::try_parse_from(args).is_err());
+    }
+}
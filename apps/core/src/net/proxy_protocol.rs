@@ -0,0 +1,306 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::time;
+
+/// Clients that don't finish their PROXY header in time are dropped
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest v1 header allowed by the specification, CRLF included
+const MAX_V1_BYTES: usize = 107;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// An inbound connection and the address of the client behind it, which with `--proxy-protocol`
+/// is the one the load balancer passed on rather than the load balancer's own
+pub struct ClientStream {
+    stream: AddrStream,
+    remote_addr: SocketAddr,
+}
+
+impl ClientStream {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+/// Accepts connections like `AddrIncoming`. With `--proxy-protocol` every connection has to start
+/// with a PROXY protocol v1 or v2 header, which is read in a task of its own so a slow client
+/// doesn't hold up the others. Connections with a malformed header are closed.
+pub struct ClientAcceptor {
+    incoming: AddrIncoming,
+    proxy_protocol: bool,
+    ready_tx: mpsc::UnboundedSender<ClientStream>,
+    ready_rx: mpsc::UnboundedReceiver<ClientStream>,
+}
+
+impl ClientAcceptor {
+    pub fn new(incoming: AddrIncoming, proxy_protocol: bool) -> Self {
+        let (ready_tx, ready_rx) = mpsc::unbounded_channel();
+        ClientAcceptor { incoming, proxy_protocol, ready_tx, ready_rx }
+    }
+}
+
+impl Accept for ClientAcceptor {
+    type Conn = ClientStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<ClientStream, io::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.incoming).poll_accept(cx) {
+                Poll::Ready(Some(Ok(stream))) if !this.proxy_protocol => {
+                    let remote_addr = stream.remote_addr();
+                    return Poll::Ready(Some(Ok(ClientStream { stream, remote_addr })));
+                }
+                Poll::Ready(Some(Ok(mut stream))) => {
+                    let ready = this.ready_tx.clone();
+                    tokio::spawn(async move {
+                        let peer = stream.remote_addr();
+                        match time::timeout(HEADER_TIMEOUT, read_proxy_header(&mut stream)).await {
+                            Ok(Ok(client)) => {
+                                let remote_addr = client.unwrap_or(peer);
+                                ready.send(ClientStream { stream, remote_addr }).ok();
+                            }
+                            Ok(Err(e)) => eprintln!("Rejected PROXY header from {}: {}", peer, e),
+                            Err(_) => eprintln!("Timed out reading PROXY header from {}", peer),
+                        }
+                    });
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => break,
+            }
+        }
+
+        // The sender kept in `self` means this never ends
+        this.ready_rx.poll_recv(cx).map(|stream| stream.map(Ok))
+    }
+}
+
+/// Read a PROXY protocol header and nothing after it, returning the client address it carries.
+/// `None` for `UNKNOWN` and `LOCAL` headers, whose connection is the client's own.
+pub async fn read_proxy_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0; 12];
+    reader.read_exact(&mut start[..6]).await?;
+    if &start[..6] == b"PROXY " {
+        // Byte by byte, so the request that follows stays unread
+        let mut line = start[..6].to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() == MAX_V1_BYTES {
+                return Err(invalid("v1 header too long"));
+            }
+            line.push(reader.read_u8().await?);
+        }
+        return parse_v1(&line);
+    }
+
+    reader.read_exact(&mut start[6..]).await?;
+    if &start != V2_SIGNATURE {
+        return Err(invalid("missing PROXY header"));
+    }
+    let mut head = [0; 4];
+    reader.read_exact(&mut head).await?;
+    let mut addresses = vec![0; u16::from_be_bytes([head[2], head[3]]) as usize];
+    reader.read_exact(&mut addresses).await?;
+
+    parse_v2(head[0], head[1], &addresses)
+}
+
+/// `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n`, or `PROXY UNKNOWN ...\r\n`
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let fields = line.trim_end_matches("\r\n").split(' ').collect::<Vec<_>>();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _, port, _] => {
+            let ip = source.parse::<IpAddr>().map_err(|_| invalid("invalid source address"))?;
+            let port = port.parse::<u16>().map_err(|_| invalid("invalid source port"))?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                return Err(invalid("source address doesn't match the protocol"));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+/// The binary header after its signature: version and command, family and protocol, then the
+/// addresses block
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL, e.g. the load balancer's own health checks
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unsupported v2 command")),
+    }
+
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    match family {
+        // TCP or UDP over IPv4: source and destination address, then their ports
+        0x11 | 0x12 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).unwrap());
+            Ok(Some(SocketAddr::new(ip.into(), port(8))))
+        }
+        0x21 | 0x22 if addresses.len() >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).unwrap());
+            Ok(Some(SocketAddr::new(ip.into(), port(32))))
+        }
+        0x11 | 0x12 | 0x21 | 0x22 => Err(invalid("v2 address block too short")),
+        // Unspecified or Unix sockets, there is no client address to use
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    async fn read(header: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let mut reader = header;
+        let client = read_proxy_header(&mut reader).await;
+        // The request after the header is left for hyper
+        if client.is_ok() {
+            assert_eq!(reader, b"GET /");
+        }
+        client
+    }
+
+    fn v2(version_command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([version_command, family]);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header.extend(b"GET /");
+        header
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_header() {
+        let tcp4 = read(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 8000\r\nGET /").await.unwrap();
+        assert_eq!(tcp4, Some("203.0.113.7:51234".parse().unwrap()));
+        let tcp6 = read(b"PROXY TCP6 2001:db8::7 2001:db8::1 443 8000\r\nGET /").await.unwrap();
+        assert_eq!(tcp6, Some("[2001:db8::7]:443".parse().unwrap()));
+        assert_eq!(read(b"PROXY UNKNOWN\r\nGET /").await.unwrap(), None);
+
+        assert!(read(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+        assert!(read(b"PROXY TCP4 2001:db8::7 10.0.0.1 1 2\r\nGET /").await.is_err());
+        assert!(read(b"PROXY TCP4 203.0.113.7 10.0.0.1 port 8000\r\nGET /").await.is_err());
+        assert!(read(&[b"PROXY TCP4 ".repeat(10), b"\r\n".to_vec()].concat()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_header() {
+        let mut tcp4 = vec![203, 0, 113, 7, 10, 0, 0, 1];
+        tcp4.extend(51234u16.to_be_bytes());
+        tcp4.extend(8000u16.to_be_bytes());
+        // TLVs after the addresses are skipped
+        tcp4.extend([0x04, 0, 1, 0]);
+        let client = read(&v2(0x21, 0x11, &tcp4)).await.unwrap();
+        assert_eq!(client, Some("203.0.113.7:51234".parse().unwrap()));
+
+        let mut tcp6 = "2001:db8::7".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        tcp6.extend([0; 16]);
+        tcp6.extend(443u16.to_be_bytes());
+        tcp6.extend(8000u16.to_be_bytes());
+        let client = read(&v2(0x21, 0x21, &tcp6)).await.unwrap();
+        assert_eq!(client, Some("[2001:db8::7]:443".parse().unwrap()));
+
+        assert_eq!(read(&v2(0x20, 0x00, &[])).await.unwrap(), None);
+        assert!(read(&v2(0x21, 0x11, &tcp4[..6])).await.is_err());
+        assert!(read(&v2(0x11, 0x11, &tcp4)).await.is_err());
+        assert!(read(&v2(0x22, 0x11, &tcp4)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_acceptor() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+        let mut acceptor =
+            ClientAcceptor::new(AddrIncoming::from_listener(listener).unwrap(), true);
+
+        let mut malformed = TcpStream::connect(addr).await.unwrap();
+        malformed.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut proxied = TcpStream::connect(addr).await.unwrap();
+        proxied.write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 8000\r\nGET /").await.unwrap();
+
+        let accepted = std::future::poll_fn(|cx| Pin::new(&mut acceptor).poll_accept(cx));
+        let mut stream = accepted.await.unwrap().unwrap();
+        assert_eq!(stream.remote_addr(), "203.0.113.7:51234".parse().unwrap());
+        let mut request = [0; 5];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"GET /");
+
+        // The malformed one is closed without ever being handed out
+        let mut buf = [0; 1];
+        assert_eq!(malformed.read(&mut buf).await.unwrap_or(0), 0);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{oneshot, Semaphore, SemaphorePermit};
+
+use crate::state::Config;
+use crate::statistics::Counters;
+
+/// Limits how many requests are forwarded to the target server at once. Requests over the limit
+/// wait for a slot, up to `max_queued` of them when set. Slots go to waiting requests in arrival
+/// order, or in turn across endpoints with `--fair-queueing`.
+pub struct UpstreamLimiter {
+    slots: Slots,
+    max_queued: Option<u64>,
+    queued: AtomicU64,
+}
+
+enum Slots {
+    Fifo(Semaphore),
+    Fair(FairQueue),
+}
+
+/// A slot to forward a request in, freed for the next waiting request when dropped
+pub struct Slot<'a> {
+    _permit: SlotPermit<'a>,
+
+    /// How long the request had to wait for the slot, `None` if one was free straight away
+    pub waited: Option<Duration>,
+}
+
+// Only held to free the slot once dropped
+#[allow(dead_code)]
+enum SlotPermit<'a> {
+    Fifo(SemaphorePermit<'a>),
+    Fair(FairPermit<'a>),
+}
+
+impl UpstreamLimiter {
+    pub fn new(max_in_flight: usize, max_queued: Option<usize>, fair: bool) -> Self {
+        let slots = if fair {
+            Slots::Fair(FairQueue::new(max_in_flight))
+        } else {
+            Slots::Fifo(Semaphore::new(max_in_flight))
+        };
+
+        UpstreamLimiter {
+            slots,
+            max_queued: max_queued.map(|m| m as u64),
+            queued: AtomicU64::new(0),
+        }
+    }
+
+    /// The limiter for `--max-upstream-requests`, if it is enabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.max_upstream_requests > 0).then(|| {
+            UpstreamLimiter::new(
+                config.max_upstream_requests,
+                config.max_queued,
+                config.fair_queueing,
+            )
+        })
+    }
+
+    /// Wait for a slot to forward a request for `endpoint` in, or `None` when the queue is
+    /// already full
+    pub async fn acquire(&self, endpoint: &str, counters: &Counters) -> Option<Slot<'_>> {
+        if let Some(permit) = self.try_acquire() {
+            return Some(Slot { _permit: permit, waited: None });
+        }
+
+        let depth = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        Counters::incr(&counters.queue_depth);
+        // Given back however the wait ends, also when the request is dropped while waiting
+        let _queued = Queued { queued: &self.queued, counters };
+        if self.max_queued.is_some_and(|max| depth > max) {
+            Counters::incr(&counters.queue_rejections);
+            return None;
+        }
+        counters.peak_queue_depth.fetch_max(depth, Ordering::Relaxed);
+
+        let queued_at = Instant::now();
+        let permit = match &self.slots {
+            // The semaphore is never closed, so waiting for it can't fail
+            Slots::Fifo(slots) => slots.acquire().await.ok().map(SlotPermit::Fifo),
+            Slots::Fair(queue) => Some(SlotPermit::Fair(queue.acquire(endpoint).await)),
+        };
+
+        permit.map(|permit| Slot { _permit: permit, waited: Some(queued_at.elapsed()) })
+    }
+
+    fn try_acquire(&self) -> Option<SlotPermit<'_>> {
+        match &self.slots {
+            Slots::Fifo(slots) => slots.try_acquire().ok().map(SlotPermit::Fifo),
+            Slots::Fair(queue) => queue.try_acquire().map(SlotPermit::Fair),
+        }
+    }
+}
+
+/// A request's place in the queue, counted until it is dropped
+struct Queued<'a> {
+    queued: &'a AtomicU64,
+    counters: &'a Counters,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Hands free slots to waiting requests one endpoint at a time, so a flood of requests for one
+/// endpoint only delays the others by a slot each instead of all of its queue
+struct FairQueue {
+    state: Mutex<FairState>,
+}
+
+struct FairState {
+    free: usize,
+    /// Endpoints with waiting requests, the next slot goes to the one in front
+    waiting: VecDeque<(String, VecDeque<oneshot::Sender<()>>)>,
+}
+
+struct FairPermit<'a> {
+    queue: &'a FairQueue,
+}
+
+impl FairQueue {
+    fn new(slots: usize) -> Self {
+        FairQueue { state: Mutex::new(FairState { free: slots, waiting: VecDeque::new() }) }
+    }
+
+    fn try_acquire(&self) -> Option<FairPermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        // Skipping the queue would be unfair to the requests already waiting
+        if state.free > 0 && state.waiting.is_empty() {
+            state.free -= 1;
+            return Some(FairPermit { queue: self });
+        }
+        None
+    }
+
+    async fn acquire(&self, endpoint: &str) -> FairPermit<'_> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.free > 0 && state.waiting.is_empty() {
+                state.free -= 1;
+                return FairPermit { queue: self };
+            }
+            match state.waiting.iter_mut().find(|(waiting, _)| waiting == endpoint) {
+                Some((_, senders)) => senders.push_back(tx),
+                None => state.waiting.push_back((endpoint.to_string(), VecDeque::from([tx]))),
+            }
+        }
+
+        let mut waiter = Waiter { rx: Some(rx), queue: self };
+        // Senders are only dropped unsent once their request stopped waiting
+        if let Some(rx) = waiter.rx.as_mut() {
+            rx.await.ok();
+        }
+        waiter.rx = None;
+
+        FairPermit { queue: self }
+    }
+
+    /// Give a freed slot to the endpoint in front, moving it to the back if more of its requests
+    /// are waiting
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some((endpoint, mut senders)) = state.waiting.pop_front() {
+            // A request that stopped waiting doesn't need the slot, its endpoint keeps the turn
+            let handed_over = senders.pop_front().is_some_and(|tx| tx.send(()).is_ok());
+            match (handed_over, senders.is_empty()) {
+                (_, true) => {}
+                (true, false) => state.waiting.push_back((endpoint, senders)),
+                (false, false) => state.waiting.push_front((endpoint, senders)),
+            }
+            if handed_over {
+                return;
+            }
+        }
+        state.free += 1;
+    }
+}
+
+impl Drop for FairPermit<'_> {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+/// A request waiting for a fair slot. If it gives up right after it was handed one, the slot is
+/// passed on instead of being lost.
+struct Waiter<'a> {
+    rx: Option<oneshot::Receiver<()>>,
+    queue: &'a FairQueue,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.queue.release();
+            }
+        }
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upstream_limiter() {
+        let limiter = Arc::new(UpstreamLimiter::new(1, Some(1), false));
+        let counters = Arc::new(Counters::default());
+
+        let permit = limiter.acquire("/", &counters).await.unwrap();
+
+        let queued = {
+            let (limiter, counters) = (Arc::clone(&limiter), Arc::clone(&counters));
+            tokio::spawn(async move { limiter.acquire("/", &counters).await.is_some() })
+        };
+        while limiter.queued.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        assert!(limiter.acquire("/", &counters).await.is_none());
+        assert_eq!(counters.queue_depth.load(Ordering::Relaxed), 1);
+        drop(permit);
+        assert!(queued.await.unwrap());
+
+        assert_eq!(counters.queue_rejections.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.peak_queue_depth.load(Ordering::Relaxed), 1);
+        assert_eq!(limiter.queued.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fair_queueing() {
+        let limiter = Arc::new(UpstreamLimiter::new(1, None, true));
+        let counters = Arc::new(Counters::default());
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let permit = limiter.acquire("/flood", &counters).await.unwrap();
+        assert_eq!(permit.waited, None);
+
+        // A request that gives up waiting must not take a slot with it
+        let gave_up = limiter.acquire("/flood", &counters);
+        assert!(tokio::time::timeout(Duration::from_millis(10), gave_up).await.is_err());
+        assert_eq!(limiter.queued.load(Ordering::Relaxed), 0);
+        assert_eq!(counters.queue_depth.load(Ordering::Relaxed), 0);
+
+        let mut waiting = Vec::new();
+        for (i, endpoint) in ["/flood", "/flood", "/flood", "/other"].into_iter().enumerate() {
+            let (queue, counters, served) =
+                (Arc::clone(&limiter), Arc::clone(&counters), Arc::clone(&served));
+            waiting.push(tokio::spawn(async move {
+                let slot = queue.acquire(endpoint, &counters).await.unwrap();
+                served.lock().unwrap().push(endpoint);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                slot.waited
+            }));
+            while limiter.queued.load(Ordering::Relaxed) <= i as u64 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+
+        drop(permit);
+        for waited in waiting {
+            assert!(waited.await.unwrap().is_some());
+        }
+        assert_eq!(*served.lock().unwrap(), ["/flood", "/other", "/flood", "/flood"]);
+        assert!(limiter.acquire("/other", &counters).await.unwrap().waited.is_none());
+    }
+}
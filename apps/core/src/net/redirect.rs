@@ -0,0 +1,148 @@
+use hyper::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, TRANSFER_ENCODING};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+
+use crate::net::client::send_request;
+use crate::net::spill::BufferedBody;
+use crate::state::HttpClient;
+
+/// What it takes to send a request again to wherever the target server redirects it
+pub struct Replay {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: BufferedBody,
+}
+
+/// Send `req`, then follow up to `max_hops` redirects that stay on one of the `authorities`
+/// (`host:port`) the target server is known by. Redirects off the host, back to an already
+/// visited URI, or past the limit are returned to the client as they are. Returns the final
+/// response and the number of redirects followed.
+pub async fn send_following_redirects(
+    client: &HttpClient,
+    req: Request<Body>,
+    replay: Option<Replay>,
+    max_hops: usize,
+    authorities: &[String],
+) -> Result<(Response<Body>, usize), hyper::Error> {
+    let mut resp = send_request(client, req).await?;
+    let Some(mut replay) = replay else {
+        return Ok((resp, 0));
+    };
+
+    let mut visited = vec![replay.uri.clone()];
+    while visited.len() <= max_hops {
+        let Some(target) = redirect_target(&resp, &replay.uri, authorities) else {
+            break;
+        };
+        if visited.contains(&target) {
+            break;
+        }
+
+        // Like browsers, 303s and 301/302s of anything but GET or HEAD continue as a bodiless GET
+        let status = resp.status();
+        if status == StatusCode::SEE_OTHER
+            || (matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND)
+                && replay.method != Method::GET
+                && replay.method != Method::HEAD)
+        {
+            replay.method = Method::GET;
+            replay.body = BufferedBody::empty();
+            for name in [CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING] {
+                replay.headers.remove(name);
+            }
+        }
+
+        let mut req = Request::builder()
+            .method(replay.method.clone())
+            .uri(target.clone())
+            .body(replay.body.body())
+            .unwrap();
+        *req.headers_mut() = replay.headers.clone();
+
+        resp = send_request(client, req).await?;
+        replay.uri = target.clone();
+        visited.push(target);
+    }
+
+    Ok((resp, visited.len() - 1))
+}
+
+/// The URI a redirect response points to, if it stays on one of the `authorities`
+fn redirect_target(resp: &Response<Body>, current: &Uri, authorities: &[String]) -> Option<Uri> {
+    if !matches!(
+        resp.status(),
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    ) {
+        return None;
+    }
+
+    let location = resp.headers().get(LOCATION)?.to_str().ok()?;
+    let location = location.parse::<Uri>().ok()?;
+
+    let path_and_query = match location.authority() {
+        // Relative to the current one, e.g. `/login?next=/`
+        None => location.path_and_query().filter(|pq| pq.as_str().starts_with('/'))?,
+        Some(authority) => {
+            let on_host = location.scheme_str() == Some("http")
+                && authorities.iter().any(|a| a.eq_ignore_ascii_case(authority.as_str()));
+            if !on_host {
+                return None;
+            }
+            location.path_and_query()?
+        }
+    };
+
+    // Always forward to the target server, even when it redirected by the proxy's own address
+    Uri::builder()
+        .scheme("http")
+        .authority(current.authority()?.clone())
+        .path_and_query(path_and_query.clone())
+        .build()
+        .ok()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn redirect(status: StatusCode, location: &str) -> Response<Body> {
+        Response::builder().status(status).header(LOCATION, location).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        let current = "http://127.0.0.1:3000/old".parse::<Uri>().unwrap();
+        let authorities = vec!["127.0.0.1:3000".to_string(), "proxy.example:8000".to_string()];
+        let target = |status, location| {
+            redirect_target(&redirect(status, location), &current, &authorities)
+                .map(|uri| uri.to_string())
+        };
+
+        let expected = Some("http://127.0.0.1:3000/new?a=1".to_string());
+        assert_eq!(target(StatusCode::FOUND, "/new?a=1"), expected);
+        assert_eq!(
+            target(StatusCode::PERMANENT_REDIRECT, "http://127.0.0.1:3000/new?a=1"),
+            expected
+        );
+        assert_eq!(target(StatusCode::SEE_OTHER, "http://PROXY.example:8000/new?a=1"), expected);
+
+        assert_eq!(target(StatusCode::FOUND, "https://127.0.0.1:3000/new"), None);
+        assert_eq!(target(StatusCode::FOUND, "http://elsewhere.example/new"), None);
+        assert_eq!(target(StatusCode::FOUND, "new"), None);
+        assert_eq!(target(StatusCode::OK, "/new"), None);
+        assert_eq!(
+            redirect_target(
+                &Response::builder().status(StatusCode::FOUND).body(Body::empty()).unwrap(),
+                &current,
+                &authorities
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+
+use chrono::Utc;
+use hyper::{Body, Request, Uri};
+use serde_json::Value;
+use tokio::time;
+
+use crate::net::deadline::configured_timeout;
+use crate::state::{Config, HttpClient, Log};
+use crate::statistics::{capped_endpoint, Histogram};
+
+/// Send the requests of a `--loglist-file` to the target server at their original pace sped up
+/// `--replay-speed` times, recording the response times like the proxy does. Bodies aren't
+/// logged, so every request is sent without one.
+pub async fn replay(
+    client: &HttpClient,
+    config: &Config,
+    path: &Path,
+) -> Result<HashMap<String, Histogram>, String> {
+    let logs = read_logs(path).await?;
+    let first = match logs.first() {
+        Some(log) => log.timestamp,
+        None => return Ok(HashMap::new()),
+    };
+
+    let start = time::Instant::now();
+    let mut sent = Vec::with_capacity(logs.len());
+    for log in logs {
+        let offset = (log.timestamp - first).to_std().unwrap_or_default();
+        time::sleep_until(start + offset.div_f64(config.replay_speed)).await;
+
+        let client = client.clone();
+        let uri = format!("http://{}:{}{}", config.host, config.port, log.req_uri)
+            .parse::<Uri>()
+            .map_err(|e| format!("invalid logged URI {}: {}", log.req_uri, e))?;
+        let timeout = configured_timeout(uri.path(), &config.endpoint_timeouts, config.timeout_ms);
+        let req = Request::builder().method(log.req_method).uri(uri).body(Body::empty()).unwrap();
+
+        sent.push(tokio::spawn(async move {
+            let path = req.uri().path().to_string();
+            let started = Instant::now();
+            let sending = client.request(req);
+            let status = match timeout {
+                Some(timeout) => time::timeout(timeout, sending).await.ok().and_then(Result::ok),
+                None => sending.await.ok(),
+            }
+            .map(|resp| resp.status().as_u16());
+
+            (path, started.elapsed(), status)
+        }));
+    }
+
+    let mut histograms: HashMap<String, Histogram> = HashMap::new();
+    for request in sent {
+        let (path, duration, status) = request.await.map_err(|e| e.to_string())?;
+        // The proxy answers with a 502 when it can't reach the target server or gives up on it
+        let status = status.unwrap_or(502);
+        let timestamp = Utc::now();
+
+        let overall = histograms.entry("Overall".to_string()).or_default();
+        overall.add(duration, timestamp);
+        overall.add_status(status);
+
+        let endpoint = capped_endpoint(&histograms, path, config.max_endpoints);
+        let hist = histograms.entry(endpoint).or_default();
+        hist.add(duration, timestamp);
+        hist.add_status(status);
+    }
+
+    Ok(histograms)
+}
+
+/// The entries of a `--loglist-file`, skipping blank lines
+async fn read_logs(path: &Path) -> Result<Vec<Log>, String> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<Value>(line)
+                .ok()
+                .as_ref()
+                .and_then(Log::from_json)
+                .ok_or_else(|| format!("{}:{}: not a logged request", path.display(), i + 1))
+        })
+        .collect()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use clap::Parser;
+    use hyper::{Method, StatusCode};
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::client::build_client;
+    use crate::net::mock::MockUpstream;
+
+    fn replay_config(port: u16, extra_args: &[&str]) -> Config {
+        let port = port.to_string();
+        let mut argv = vec!["test", "--host", "127.0.0.1", "--port", &port];
+        argv.extend_from_slice(extra_args);
+        Config::from(&Args::parse_from(argv))
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let upstream = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let config = replay_config(upstream.addr.port(), &["--replay-speed", "10"]);
+        let client = build_client(&config, Default::default());
+
+        let path = std::env::temp_dir().join(format!("narrow-replay-{}.jsonl", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"timestamp":"2024-05-01T12:00:00Z","method":"GET","uri":"/items?page=2"}
+
+{"timestamp":"2024-05-01T12:00:01Z","method":"DELETE","uri":"/items/1","micros":900}
+{"timestamp":"2024-05-01T12:00:02Z","method":"GET","uri":"/items?page=3"}
+"#,
+        )
+        .unwrap();
+
+        let started = Instant::now();
+        let histograms = replay(&client, &config, &path).await.unwrap();
+        // Two seconds of traffic ten times as fast
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        let received = upstream.requests();
+        assert_eq!(
+            received.iter().map(|r| (r.method.clone(), r.uri.as_str())).collect::<Vec<_>>(),
+            vec![
+                (Method::GET, "/items?page=2"),
+                (Method::DELETE, "/items/1"),
+                (Method::GET, "/items?page=3")
+            ]
+        );
+        assert_eq!(histograms["Overall"].total_requests, 3);
+        assert_eq!(histograms["/items"].total_requests, 2);
+        assert_eq!(histograms["/items/1"].total_requests, 1);
+
+        std::fs::write(&path, "{\"method\":\"GET\"}\n").unwrap();
+        let err = replay(&client, &config, &path).await.unwrap_err();
+        assert!(err.ends_with(":1: not a logged request"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::config::TrailingSlash;
+use crate::net::files::percent_decode;
+
+/// The `--rewrite-rules` file, read and compiled once at startup. Every line holds a regex and
+/// its replacement separated by whitespace, which may refer to capture groups as `$1` or
+/// `${name}`. Blank lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone)]
+pub struct RewriteRules {
+    rules: Vec<(Regex, String)>,
+}
+
+impl RewriteRules {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let rule = line.split_whitespace().collect::<Vec<_>>();
+            let [pattern, replacement] = rule[..] else {
+                return Err(format!("line {}: expected a regex and a replacement", i + 1));
+            };
+            let regex = Regex::new(pattern).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            check_groups(&regex, replacement).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            rules.push((regex, replacement.to_string()));
+        }
+
+        Ok(RewriteRules { rules })
+    }
+
+    /// `path` rewritten by the first rule matching it, `None` when no rule does
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(path))
+            .map(|(regex, replacement)| regex.replace(path, replacement.as_str()).into_owned())
+    }
+}
+
+/// Make sure every group `replacement` refers to exists, the regex crate would silently
+/// substitute nothing for the others
+fn check_groups(regex: &Regex, replacement: &str) -> Result<(), String> {
+    let mut rest = replacement;
+    while let Some(i) = rest.find('$') {
+        rest = &rest[i + 1..];
+        if let Some(escaped) = rest.strip_prefix('$') {
+            rest = escaped;
+            continue;
+        }
+
+        let (group, after) = match rest.strip_prefix('{') {
+            Some(braced) => braced.split_once('}').ok_or("unclosed ${ in the replacement")?,
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                rest.split_at(end)
+            }
+        };
+        let exists = match group.parse::<usize>() {
+            Ok(n) => n < regex.captures_len(),
+            Err(_) => regex.capture_names().flatten().any(|name| name == group),
+        };
+        if !group.is_empty() && !exists {
+            return Err(format!("the replacement refers to a missing group ${}", group));
+        }
+        rest = after;
+    }
+
+    Ok(())
+}
+
+/// Put `path` in place of the path of `path_and_query`, keeping its query
+pub fn replace_path(path_and_query: &str, path: &str) -> String {
+    match path_and_query.find('?') {
+        Some(i) => format!("{}{}", path, &path_and_query[i..]),
+        None => path.to_string(),
+    }
+}
+
+/// Strip or add a trailing slash on `path`, leaving the root path alone
+pub fn normalize_trailing_slash(path: &str, mode: TrailingSlash) -> String {
+    if path.trim_end_matches('/').is_empty() {
+        return "/".to_string();
+    }
+
+    match mode {
+        TrailingSlash::Strip => path.trim_end_matches('/').to_string(),
+        TrailingSlash::Add if path.ends_with('/') => path.to_string(),
+        TrailingSlash::Add => format!("{}/", path),
+    }
+}
+
+/// Rewrite the client-facing `path_and_query` into the one sent upstream, first removing
+/// `strip_prefix` and then prepending `upstream_prefix`. Prefixes are matched on whole path
+/// segments and a trailing slash on either prefix is ignored. With `normalize` the client path's
+/// trailing slash is canonicalized first.
+pub fn upstream_path_and_query(
+    path_and_query: &str,
+    strip_prefix: Option<&str>,
+    upstream_prefix: Option<&str>,
+    normalize: Option<TrailingSlash>,
+) -> String {
+    let (path, query) = match path_and_query.find('?') {
+        Some(i) => path_and_query.split_at(i),
+        None => (path_and_query, ""),
+    };
+    let mut path = if path.is_empty() { "/" } else { path };
+    let normalized;
+    if let Some(mode) = normalize {
+        normalized = normalize_trailing_slash(path, mode);
+        path = &normalized;
+    }
+
+    if let Some(prefix) = strip_prefix.map(normalize_prefix).filter(|p| !p.is_empty()) {
+        match path.strip_prefix(prefix) {
+            Some("") => path = "/",
+            Some(rest) if rest.starts_with('/') => path = rest,
+            _ => {}
+        }
+    }
+
+    let prefix = upstream_prefix.map(normalize_prefix).unwrap_or("");
+    format!("{}{}{}", prefix, path, query)
+}
+
+/// Remove every `name` parameter from the query of `path_and_query`, returning what is left and
+/// the percent-decoded value of the last one. Without such a parameter `path_and_query` is
+/// returned as it is.
+pub fn take_query_param(path_and_query: &str, name: &str) -> (String, Option<String>) {
+    let Some((path, query)) = path_and_query.split_once('?') else {
+        return (path_and_query.to_string(), None);
+    };
+
+    let mut taken = None;
+    let kept = query
+        .split('&')
+        .filter(|param| match param.split_once('=').unwrap_or((param, "")) {
+            (key, value) if key == name => {
+                taken = Some(percent_decode(value).unwrap_or_else(|| value.to_string()));
+                false
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+
+    match taken {
+        None => (path_and_query.to_string(), None),
+        Some(_) if kept.is_empty() => (path.to_string(), taken),
+        Some(_) => (format!("{}?{}", path, kept.join("&")), taken),
+    }
+}
+
+fn normalize_prefix(prefix: &str) -> &str {
+    prefix.trim_end_matches('/')
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_take_query_param() {
+        let param = "__upstream";
+        assert_eq!(
+            take_query_param("/items?__upstream=backend-2%3A3002&page=2", param),
+            ("/items?page=2".to_string(), Some("backend-2:3002".to_string()))
+        );
+        assert_eq!(
+            take_query_param("/items?__upstream=a:1&__upstream=b:2", param),
+            ("/items".to_string(), Some("b:2".to_string()))
+        );
+        assert_eq!(
+            take_query_param("/items?__upstream", param),
+            ("/items".to_string(), Some("".to_string()))
+        );
+        assert_eq!(take_query_param("/items?a=%2f&b", param), ("/items?a=%2f&b".to_string(), None));
+        assert_eq!(take_query_param("/items", param), ("/items".to_string(), None));
+    }
+
+    #[test]
+    fn test_upstream_path_and_query() {
+        assert_eq!(upstream_path_and_query("/users?id=1", None, None, None), "/users?id=1");
+        assert_eq!(upstream_path_and_query("", None, None, None), "/");
+
+        let prefix = Some("/service-a");
+        assert_eq!(upstream_path_and_query("/users", None, prefix, None), "/service-a/users");
+        assert_eq!(upstream_path_and_query("/", None, prefix, None), "/service-a/");
+        assert_eq!(
+            upstream_path_and_query("/?q=1", None, Some("/service-a/"), None),
+            "/service-a/?q=1"
+        );
+
+        let strip = Some("/api/");
+        assert_eq!(upstream_path_and_query("/api/users?id=1", strip, None, None), "/users?id=1");
+        assert_eq!(upstream_path_and_query("/api", strip, None, None), "/");
+        assert_eq!(upstream_path_and_query("/api?x", strip, None, None), "/?x");
+        assert_eq!(upstream_path_and_query("/apis/users", strip, None, None), "/apis/users");
+        assert_eq!(
+            upstream_path_and_query("/api/users/", strip, prefix, None),
+            "/service-a/users/"
+        );
+
+        let normalize = Some(TrailingSlash::Strip);
+        assert_eq!(upstream_path_and_query("/users/?a=1", None, None, normalize), "/users?a=1");
+        assert_eq!(upstream_path_and_query("/api/", strip, prefix, normalize), "/service-a/");
+    }
+
+    #[test]
+    fn test_rewrite_rules() {
+        let rules = RewriteRules::parse(
+            "# Moved APIs\n\
+             ^/old/(.*)$   /new/$1\n\
+             \n\
+             ^/old          /never\n\
+             ^/v1/(?P<rest>.+)$ /api/${rest}/v1\n",
+        )
+        .unwrap();
+        assert_eq!(rules.rewrite("/old/items/5"), Some("/new/items/5".to_string()));
+        assert_eq!(rules.rewrite("/v1/users"), Some("/api/users/v1".to_string()));
+        assert_eq!(rules.rewrite("/other"), None);
+        assert_eq!(replace_path("/old/items?page=2", "/new/items"), "/new/items?page=2");
+        assert_eq!(replace_path("/old/items", "/new/items"), "/new/items");
+
+        let error = |text: &str| RewriteRules::parse(text).unwrap_err();
+        assert!(error("# ok\n^/a(\n").starts_with("line 2:"));
+        assert_eq!(error("^/a"), "line 1: expected a regex and a replacement");
+        assert_eq!(error("^/(a) /$2"), "line 1: the replacement refers to a missing group $2");
+        assert!(error("^/(a) /${name}").ends_with("missing group $name"));
+        assert!(RewriteRules::parse("^/(a) /$$1/${1}x").is_ok());
+    }
+
+    #[test]
+    fn test_normalize_trailing_slash() {
+        assert_eq!(normalize_trailing_slash("/users/", TrailingSlash::Strip), "/users");
+        assert_eq!(normalize_trailing_slash("/users//", TrailingSlash::Strip), "/users");
+        assert_eq!(normalize_trailing_slash("/users", TrailingSlash::Strip), "/users");
+        assert_eq!(normalize_trailing_slash("/users", TrailingSlash::Add), "/users/");
+        assert_eq!(normalize_trailing_slash("/users/", TrailingSlash::Add), "/users/");
+        assert_eq!(normalize_trailing_slash("/", TrailingSlash::Strip), "/");
+        assert_eq!(normalize_trailing_slash("/", TrailingSlash::Add), "/");
+        assert_eq!(normalize_trailing_slash("//", TrailingSlash::Strip), "/");
+    }
+}
@@ -0,0 +1,73 @@
+use hyper::header::HeaderMap;
+
+/// A `--header-route`: requests whose `header` matches `value` go to `upstream` instead of the
+/// target server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRoute {
+    pub header: String,
+    pub value: String,
+    /// Match any value starting with `value`, written as a trailing `*`
+    pub prefix: bool,
+    /// The `host:port` to forward to
+    pub upstream: String,
+}
+
+impl HeaderRoute {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        headers.get_all(self.header.as_str()).iter().filter_map(|v| v.to_str().ok()).any(|v| {
+            match self.prefix {
+                true => v.starts_with(&self.value),
+                false => v == self.value,
+            }
+        })
+    }
+}
+
+/// The upstream of the first header route matching `headers`, in the order they were given
+pub fn route_by_header<'a>(routes: &'a [HeaderRoute], headers: &HeaderMap) -> Option<&'a str> {
+    routes.iter().find(|route| route.matches(headers)).map(|route| route.upstream.as_str())
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn route(header: &str, value: &str, prefix: bool, upstream: &str) -> HeaderRoute {
+        HeaderRoute {
+            header: header.to_string(),
+            value: value.to_string(),
+            prefix,
+            upstream: upstream.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_route_by_header() {
+        let routes = [
+            route("x-api-version", "2", false, "backend-v2:3002"),
+            route("x-api-version", "2", true, "backend-v2-preview:3003"),
+            route("x-tenant", "beta-", true, "beta:3004"),
+        ];
+        let headers = |name: &'static str, value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(
+            route_by_header(&routes, &headers("x-api-version", "2")),
+            Some("backend-v2:3002")
+        );
+        assert_eq!(
+            route_by_header(&routes, &headers("X-API-Version", "2.1")),
+            Some("backend-v2-preview:3003")
+        );
+        assert_eq!(route_by_header(&routes, &headers("x-tenant", "beta-acme")), Some("beta:3004"));
+        assert_eq!(route_by_header(&routes, &headers("x-api-version", "1")), None);
+        assert_eq!(route_by_header(&routes, &HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,188 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures_util::{stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::Body;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// Bodies spilled to disk are streamed back in chunks of this size
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// A request body read to the end so it can be sent more than once, kept in memory or, past the
+/// `--spill-threshold-bytes`, in a temp file in the `--spill-to-disk` directory
+#[derive(Debug, Clone)]
+pub enum BufferedBody {
+    Memory(Bytes),
+    Spilled(Arc<SpillFile>),
+}
+
+/// A spilled body, whose file is removed once the last body streaming from it is dropped
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    len: u64,
+}
+
+#[derive(Debug)]
+pub enum SpillError {
+    /// Reading the body from the client failed
+    Body(hyper::Error),
+
+    /// Writing the body to its temp file failed
+    Disk(io::Error),
+}
+
+impl BufferedBody {
+    pub fn empty() -> Self {
+        BufferedBody::Memory(Bytes::new())
+    }
+
+    /// Read `body` to the end. With `spill` set to a directory and a threshold, the body moves to
+    /// a temp file in that directory once it grows past the threshold.
+    pub async fn read(mut body: Body, spill: Option<(&Path, usize)>) -> Result<Self, SpillError> {
+        let mut buffered = Vec::new();
+        let mut file: Option<(File, SpillFile)> = None;
+
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(SpillError::Body)?;
+            match (&mut file, spill) {
+                (Some((file, spilled)), _) => {
+                    file.write_all(&chunk).await.map_err(SpillError::Disk)?;
+                    spilled.len += chunk.len() as u64;
+                }
+                (None, Some((dir, threshold))) if buffered.len() + chunk.len() > threshold => {
+                    // From here on the file is removed again whatever goes wrong
+                    let spilled = SpillFile {
+                        path: dir.join(format!("narrow-{}.body", Uuid::new_v4().simple())),
+                        len: (buffered.len() + chunk.len()) as u64,
+                    };
+                    let mut created =
+                        File::create(&spilled.path).await.map_err(SpillError::Disk)?;
+                    created.write_all(&buffered).await.map_err(SpillError::Disk)?;
+                    created.write_all(&chunk).await.map_err(SpillError::Disk)?;
+                    buffered = Vec::new();
+                    file = Some((created, spilled));
+                }
+                (None, _) => buffered.extend_from_slice(&chunk),
+            }
+        }
+
+        match file {
+            Some((mut file, spilled)) => {
+                file.flush().await.map_err(SpillError::Disk)?;
+                Ok(BufferedBody::Spilled(Arc::new(spilled)))
+            }
+            None => Ok(BufferedBody::Memory(buffered.into())),
+        }
+    }
+
+    /// The size of the body in bytes
+    #[allow(dead_code)]
+    pub fn len(&self) -> u64 {
+        match self {
+            BufferedBody::Memory(bytes) => bytes.len() as u64,
+            BufferedBody::Spilled(spilled) => spilled.len,
+        }
+    }
+
+    /// A fresh body to send, streamed from the temp file if the body was spilled
+    pub fn body(&self) -> Body {
+        let spilled = match self {
+            BufferedBody::Memory(bytes) => return Body::from(bytes.clone()),
+            BufferedBody::Spilled(spilled) => Arc::clone(spilled),
+        };
+
+        let chunks = stream::try_unfold(None, move |reader: Option<File>| {
+            let spilled = Arc::clone(&spilled);
+            async move {
+                let mut reader = match reader {
+                    Some(reader) => reader,
+                    None => File::open(&spilled.path).await?,
+                };
+                let mut chunk = vec![0; READ_CHUNK_BYTES];
+                let read = reader.read(&mut chunk).await?;
+                if read == 0 {
+                    return Ok::<_, io::Error>(None);
+                }
+                chunk.truncate(read);
+                Ok(Some((Bytes::from(chunk), Some(reader))))
+            }
+        });
+
+        Body::wrap_stream(chunks)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Could not remove spilled body {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn chunked(chunks: &'static [&'static str]) -> Body {
+        Body::wrap_stream(stream::iter(chunks.iter().map(|c| Ok::<_, io::Error>(*c))))
+    }
+
+    fn spill_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("narrow-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body_in_memory() {
+        let dir = spill_dir("memory");
+        let spill = Some((dir.as_path(), 8));
+        let buffered = BufferedBody::read(chunked(&["0123", "4567"]), spill).await.unwrap();
+        assert!(matches!(buffered, BufferedBody::Memory(_)));
+        assert_eq!(hyper::body::to_bytes(buffered.body()).await.unwrap(), "01234567");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body_spilled() {
+        let dir = spill_dir("spilled");
+        let spill = Some((dir.as_path(), 8));
+        let buffered = BufferedBody::read(chunked(&["0123", "4567", "89"]), spill).await.unwrap();
+        assert!(matches!(buffered, BufferedBody::Spilled(_)));
+        assert_eq!(buffered.len(), 10);
+
+        // Every body streams the whole file again
+        let first = buffered.body();
+        let second = buffered.body();
+        drop(buffered);
+        assert_eq!(hyper::body::to_bytes(first).await.unwrap(), "0123456789");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(hyper::body::to_bytes(second).await.unwrap(), "0123456789");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body_removes_file_on_error() {
+        let dir = spill_dir("failed");
+        let (mut sender, body) = Body::channel();
+        sender.send_data(Bytes::from("0123456789")).await.unwrap();
+        sender.abort();
+
+        let spill = Some((dir.as_path(), 8));
+        assert!(matches!(BufferedBody::read(body, spill).await, Err(SpillError::Body(_))));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
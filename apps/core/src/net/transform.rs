@@ -0,0 +1,139 @@
+use std::io;
+
+use futures_util::StreamExt;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Response};
+use serde_json::Value;
+
+use crate::state::SharedCounters;
+use crate::statistics::Counters;
+
+/// Remove `fields` from the top level of a JSON response. Only bodies that declare a length of at
+/// most `max_bytes` are buffered, anything larger, compressed or not JSON is passed through.
+pub async fn strip_json_fields(
+    resp: Response<Body>,
+    fields: &[String],
+    max_bytes: u64,
+) -> Result<Response<Body>, hyper::Error> {
+    let size = resp.body().size_hint().exact();
+    if !is_json(resp.headers()) || size.is_none_or(|size| size > max_bytes) {
+        return Ok(resp);
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+
+    let stripped = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Object(mut object)) => {
+            let before = object.len();
+            object.retain(|key, _| !fields.contains(key));
+            (object.len() != before).then(|| Value::Object(object).to_string())
+        }
+        _ => None,
+    };
+
+    let body = match stripped {
+        Some(stripped) => {
+            parts.headers.insert(CONTENT_LENGTH, stripped.len().into());
+            Body::from(stripped)
+        }
+        None => Body::from(body),
+    };
+
+    Ok(Response::from_parts(parts, body))
+}
+
+/// Cut off a streamed response body once it exceeds `limit` bytes. The client sees the connection
+/// end early, since the headers already promised a complete body.
+pub fn limit_body(body: Body, limit: u64, counters: SharedCounters) -> Body {
+    let mut seen = 0u64;
+    let mut exceeded = false;
+    let limited = body.map(move |chunk| {
+        let chunk = chunk?;
+        seen = seen.saturating_add(chunk.len() as u64);
+        if seen > limit && !exceeded {
+            exceeded = true;
+            Counters::incr(&counters.oversized_responses);
+            eprintln!("Cut off a response body of more than {} bytes", limit);
+        }
+        if exceeded {
+            return Err(io::Error::other(format!("response body exceeds {} bytes", limit)).into());
+        }
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(chunk)
+    });
+
+    Body::wrap_stream(limited)
+}
+
+/// Whether the headers announce an uncompressed `application/json` body
+fn is_json(headers: &HeaderMap) -> bool {
+    let json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    let encoded = headers
+        .get(CONTENT_ENCODING)
+        .is_some_and(|v| !v.as_bytes().eq_ignore_ascii_case(b"identity"));
+
+    json && !encoded
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn json_response(content_type: &str, body: &'static str) -> Response<Body> {
+        Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_LENGTH, body.len())
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn strip(resp: Response<Body>, max_bytes: u64) -> (HeaderMap, String) {
+        let fields = vec!["debug".to_string(), "trace".to_string()];
+        let resp = strip_json_fields(resp, &fields, max_bytes).await.unwrap();
+        let headers = resp.headers().clone();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_strip_json_fields() {
+        let body = r#"{"id":1,"debug":{"sql":"select 1"},"nested":{"debug":true}}"#;
+        let (headers, body) =
+            strip(json_response("application/json; charset=utf-8", body), 1024).await;
+        assert_eq!(body, r#"{"id":1,"nested":{"debug":true}}"#);
+        assert_eq!(headers[CONTENT_LENGTH], body.len().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_strip_json_fields_passes_through() {
+        let body = r#"{"id":1,"debug":true}"#;
+
+        // Too large for the cap
+        assert_eq!(strip(json_response("application/json", body), 8).await.1, body);
+        // Not JSON
+        assert_eq!(strip(json_response("text/plain", body), 1024).await.1, body);
+        // Not an object, or not valid JSON at all
+        assert_eq!(strip(json_response("application/json", "[1,2]"), 1024).await.1, "[1,2]");
+        assert_eq!(strip(json_response("application/json", "{oops"), 1024).await.1, "{oops");
+
+        // Unknown length
+        let chunks: Vec<Result<_, std::io::Error>> = vec![Ok(body)];
+        let resp = Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::wrap_stream(futures_util::stream::iter(chunks)))
+            .unwrap();
+        assert_eq!(strip(resp, 1024).await.1, body);
+
+        // Compressed
+        let mut resp = json_response("application/json", body);
+        resp.headers_mut().insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+        assert_eq!(strip(resp, 1024).await.1, body);
+    }
+}
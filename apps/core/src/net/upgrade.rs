@@ -0,0 +1,57 @@
+use hyper::header::{CONNECTION, UPGRADE};
+use hyper::upgrade::OnUpgrade;
+use hyper::HeaderMap;
+
+/// Whether the client asks to switch protocols, e.g. to open a websocket
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+
+    connection_upgrade && headers.contains_key(UPGRADE)
+}
+
+/// Once both sides have switched protocols, copy bytes between the client and the target server
+/// until either of them closes the connection
+pub fn spawn_tunnel(client: OnUpgrade, upstream: OnUpgrade) {
+    tokio::spawn(async move {
+        let (mut client, mut upstream) = match tokio::try_join!(client, upstream) {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                eprintln!("Could not upgrade connection: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            eprintln!("Upgraded connection closed with an error: {}", e);
+        }
+    });
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_is_upgrade_request() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_upgrade_request(&headers));
+
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        assert!(!is_upgrade_request(&headers));
+
+        headers.insert(CONNECTION, HeaderValue::from_static("keep-alive, Upgrade"));
+        assert!(is_upgrade_request(&headers));
+
+        headers.remove(UPGRADE);
+        assert!(!is_upgrade_request(&headers));
+    }
+}
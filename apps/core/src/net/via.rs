@@ -0,0 +1,67 @@
+use hyper::header::{HeaderValue, VIA};
+use hyper::{HeaderMap, Version};
+
+/// Append this proxy to the `Via` chain of a message received with `version`, merging any `Via`
+/// headers already present into a single one
+pub fn append_via(headers: &mut HeaderMap, version: Version, name: &str) {
+    let protocol = match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+
+    let mut chain: Vec<&str> =
+        headers.get_all(VIA).iter().filter_map(|v| v.to_str().ok()).collect();
+    let entry = format!("{} {}", protocol, name);
+    chain.push(&entry);
+
+    // The name is validated as a token and existing values were valid headers to begin with
+    let value = HeaderValue::from_str(&chain.join(", ")).unwrap();
+    headers.insert(VIA, value);
+}
+
+/// Whether a proxy called `name` already handled the message, which means it is going in circles
+pub fn via_contains(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get_all(VIA)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|entry| entry.split_whitespace().nth(1))
+        .any(|received_by| received_by.eq_ignore_ascii_case(name))
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_append_via() {
+        let mut headers = HeaderMap::new();
+        append_via(&mut headers, Version::HTTP_11, "narrow");
+        assert_eq!(headers[VIA], "1.1 narrow");
+
+        let mut headers = HeaderMap::new();
+        headers.append(VIA, HeaderValue::from_static("1.0 fred, 1.1 p.example.net"));
+        headers.append(VIA, HeaderValue::from_static("2 cdn"));
+        append_via(&mut headers, Version::HTTP_10, "edge-1");
+        assert_eq!(headers.get_all(VIA).iter().count(), 1);
+        assert_eq!(headers[VIA], "1.0 fred, 1.1 p.example.net, 2 cdn, 1.0 edge-1");
+    }
+
+    #[test]
+    fn test_via_contains() {
+        let mut headers = HeaderMap::new();
+        assert!(!via_contains(&headers, "narrow"));
+
+        headers.append(VIA, HeaderValue::from_static("1.0 fred (narrow build), 1.1 narrow-2"));
+        assert!(!via_contains(&headers, "narrow"));
+
+        headers.append(VIA, HeaderValue::from_static("1.1 Narrow"));
+        assert!(via_contains(&headers, "narrow"));
+    }
+}
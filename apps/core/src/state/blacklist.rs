@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/// Blocked client addresses, as single IPs and CIDR ranges
+#[derive(Debug, Default, Clone)]
+pub struct Blacklist {
+    pub ips: HashSet<IpAddr>,
+    pub nets: Vec<IpNet>,
+}
+
+impl Blacklist {
+    pub fn new(ips: &[IpAddr], nets: Vec<IpNet>) -> Self {
+        Blacklist { ips: ips.iter().copied().collect(), nets }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.ips.contains(ip) || self.nets.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Parse a newline-delimited list of IPs and CIDR ranges. Blank lines and `#` comments are
+/// skipped, and invalid entries are returned separately so they can be reported.
+pub fn parse_blacklist(list: &str) -> (Vec<IpNet>, Vec<String>) {
+    let mut nets = Vec::new();
+    let mut invalid = Vec::new();
+
+    for line in list.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        match line.parse::<IpNet>().or_else(|_| line.parse::<IpAddr>().map(IpNet::from)) {
+            Ok(net) => nets.push(net),
+            Err(_) => invalid.push(line.to_string()),
+        }
+    }
+
+    (nets, invalid)
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_blacklist() {
+        let (nets, invalid) = parse_blacklist("10.0.0.0/8\n# comment\n\n 2001:db8::1 \nnope\n");
+        assert_eq!(invalid, vec!["nope"]);
+
+        let blacklist = Blacklist::new(&["1.1.1.1".parse().unwrap()], nets);
+        assert!(blacklist.contains(&"1.1.1.1".parse().unwrap()));
+        assert!(blacklist.contains(&"10.20.30.40".parse().unwrap()));
+        assert!(blacklist.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!blacklist.contains(&"2001:db8::2".parse().unwrap()));
+        assert!(!blacklist.contains(&"192.168.0.1".parse().unwrap()));
+    }
+}
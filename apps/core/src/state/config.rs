@@ -1,4 +1,16 @@
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ipnet::IpNet;
+use regex::Regex;
+use uuid::Uuid;
+
+use crate::config::{AnonymizeIps, Args, ErrorFormat, SortBy, SyslogFacility, TrailingSlash};
+use crate::net::error::ErrorPage;
+use crate::net::rewrite::RewriteRules;
+use crate::net::route::HeaderRoute;
+use crate::state::siphash::siphash24;
 
 pub struct Config {
     /// The port number to run the proxy server on
@@ -32,6 +44,646 @@ pub struct Config {
     /// The key to authenticate with the monitoring server
     #[allow(dead_code)]
     pub key: String,
+
+    /// Directory to serve static files from instead of forwarding matching paths
+    #[allow(dead_code)]
+    pub static_dir: Option<PathBuf>,
+
+    /// Path prefixes served from the static directory (comma-separated)
+    #[allow(dead_code)]
+    pub static_prefix: Vec<String>,
+
+    /// Request header whose value is added to the histogram key (e.g. X-Tenant-Id)
+    #[allow(dead_code)]
+    pub group_by_header: Option<String>,
+
+    /// The maximum number of distinct histogram endpoints, the rest are counted as "Other" (0 for
+    /// no limit)
+    #[allow(dead_code)]
+    pub max_endpoints: usize,
+
+    /// Path prefix prepended to every request forwarded to the target server
+    #[allow(dead_code)]
+    pub upstream_path_prefix: Option<String>,
+
+    /// Client-facing path prefix removed before forwarding to the target server
+    #[allow(dead_code)]
+    pub strip_prefix: Option<String>,
+
+    /// The body format of errors generated by the proxy (upstream responses are untouched)
+    #[allow(dead_code)]
+    pub error_format: ErrorFormat,
+
+    /// URL of a newline-delimited IP/CIDR list to add to the blacklist (http only)
+    #[allow(dead_code)]
+    pub blacklist_url: Option<String>,
+
+    /// The interval in seconds to refresh the blacklist from the blacklist URL
+    #[allow(dead_code)]
+    pub blacklist_refresh: u64,
+
+    /// Disable highlighting of slow or failing endpoints in the histogram table
+    #[allow(dead_code)]
+    pub no_color: bool,
+
+    /// Highlight endpoints whose estimated p95 latency exceeds this many milliseconds
+    #[allow(dead_code)]
+    pub highlight_p95_ms: f64,
+
+    /// Highlight endpoints whose share of 5xx responses exceeds this ratio
+    #[allow(dead_code)]
+    pub highlight_error_ratio: f64,
+
+    /// The timeout in milliseconds for requests to the target server (0 for no timeout)
+    #[allow(dead_code)]
+    pub timeout_ms: u64,
+
+    /// An inherited, already bound TCP socket to accept connections on instead of binding the
+    /// proxy port (e.g. from socket activation)
+    #[allow(dead_code)]
+    pub listen_fd: Option<i32>,
+
+    /// Send a copy of every request to this host:port, discarding its responses
+    #[allow(dead_code)]
+    pub mirror_to: Option<String>,
+
+    /// Request headers removed before forwarding unless the client is a trusted proxy
+    /// (comma-separated)
+    #[allow(dead_code)]
+    pub sensitive_headers: Vec<String>,
+
+    /// Client addresses or CIDR ranges allowed to send sensitive headers (comma-separated)
+    #[allow(dead_code)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// OTLP/HTTP endpoint to push the histograms to every interval
+    #[cfg(feature = "otlp")]
+    #[allow(dead_code)]
+    pub otlp_endpoint: Option<String>,
+
+    /// File the latency buckets are rendered to as an SVG bar chart every interval
+    #[cfg(feature = "chart")]
+    #[allow(dead_code)]
+    pub chart_output: Option<PathBuf>,
+
+    /// Strip or add a trailing slash on histogram endpoints so /users and /users/ share a row
+    #[allow(dead_code)]
+    pub normalize_trailing_slash: Option<TrailingSlash>,
+
+    /// Also apply --normalize-trailing-slash to the path forwarded to the target server
+    #[allow(dead_code)]
+    pub normalize_forwarded_path: bool,
+
+    /// Reject requests whose header fields add up to more than this many bytes with a 431.
+    /// Defaults to hyper's own read buffer limit of about 400KB
+    #[allow(dead_code)]
+    pub max_request_header_bytes: Option<usize>,
+
+    /// Add a column with the number of requests per method to the histogram table, e.g.
+    /// `G:120 P:5` for GET and POST (U is PUT, D is DELETE, O any other method)
+    #[allow(dead_code)]
+    pub show_methods: bool,
+
+    /// Top-level fields removed from JSON responses of the target server (comma-separated)
+    #[allow(dead_code)]
+    pub json_strip_fields: Vec<String>,
+
+    /// JSON responses larger than this, or without a Content-Length, are passed through without
+    /// removing --json-strip-fields
+    #[allow(dead_code)]
+    pub json_strip_max_bytes: u64,
+
+    /// On Ctrl-C or SIGTERM, how long in milliseconds to wait for open requests and for flushing
+    /// the statistics of the partial interval before exiting
+    #[allow(dead_code)]
+    pub shutdown_grace_ms: u64,
+
+    /// MaxMind GeoLite2 Country and/or ASN database used by --block-countries and --block-asn
+    #[allow(dead_code)]
+    pub geoip_db: Option<PathBuf>,
+
+    /// Reject clients from these ISO country codes, e.g. `NL,BE` (comma-separated)
+    #[allow(dead_code)]
+    pub block_countries: Vec<String>,
+
+    /// Reject clients from these autonomous system numbers (comma-separated)
+    #[allow(dead_code)]
+    pub block_asn: Vec<u32>,
+
+    /// Forward at most this many requests to the target server at once, queueing the rest (0
+    /// disables the limit)
+    #[allow(dead_code)]
+    pub max_upstream_requests: usize,
+
+    /// Answer with a 503 instead of queueing once this many requests wait for
+    /// --max-upstream-requests
+    #[allow(dead_code)]
+    pub max_queued: Option<usize>,
+
+    /// Give up connecting to the target server after this many milliseconds with a 502 (0 leaves
+    /// it to the OS)
+    #[allow(dead_code)]
+    pub connect_timeout_ms: u64,
+
+    /// Warn when an endpoint's estimated p95 latency worsens by more than this many percent from
+    /// one interval to the next
+    #[allow(dead_code)]
+    pub regression_pct: Option<f64>,
+
+    /// Answer `/favicon.ico` with an empty 204 instead of forwarding it
+    #[allow(dead_code)]
+    pub serve_favicon: bool,
+
+    /// Answer `/robots.txt` with this content instead of forwarding it
+    #[allow(dead_code)]
+    pub robots: Option<String>,
+
+    /// Follow up to this many redirects from the target server before answering, as long as they
+    /// stay on its host
+    #[allow(dead_code)]
+    pub follow_redirects: usize,
+
+    /// The pseudonym this proxy adds to `Via` headers
+    #[allow(dead_code)]
+    pub via_name: String,
+
+    /// Don't add a `Via` header to forwarded requests and responses
+    #[allow(dead_code)]
+    pub no_via: bool,
+
+    /// Override --timeout-ms for paths under a prefix, as PREFIX=MILLISECONDS (repeatable, the
+    /// longest matching prefix wins)
+    #[allow(dead_code)]
+    pub endpoint_timeouts: Vec<(String, u64)>,
+
+    /// Append the requests of every interval to this file as JSON lines
+    #[allow(dead_code)]
+    pub loglist_file: Option<PathBuf>,
+
+    /// Latency percentiles shown in the histogram table (comma-separated, fractions allowed)
+    #[allow(dead_code)]
+    pub percentiles: Vec<f64>,
+
+    /// Answer requests whose path matches this regex with --deny-status instead of forwarding them
+    /// (repeatable)
+    #[allow(dead_code)]
+    pub deny_path: Vec<Regex>,
+
+    /// Only forward requests whose path matches one of these regexes (repeatable), --deny-path
+    /// still applies on top
+    #[allow(dead_code)]
+    pub allow_path: Vec<Regex>,
+
+    /// The status answered to requests refused by --deny-path or --allow-path
+    #[allow(dead_code)]
+    pub deny_status: u16,
+
+    /// Answer with a 502 when the target server announces a larger response body than this, or cut
+    /// off a streamed body once it exceeds it
+    #[allow(dead_code)]
+    pub max_response_bytes: Option<u64>,
+
+    /// Check that the target server answers --health-path before accepting connections, and exit
+    /// if it doesn't
+    #[allow(dead_code)]
+    pub startup_probe: bool,
+
+    /// The path of the target server checked by --startup-probe
+    #[allow(dead_code)]
+    pub health_path: String,
+
+    /// How many more times --startup-probe tries, a second apart, before giving up
+    #[allow(dead_code)]
+    pub startup_probe_retries: u32,
+
+    /// How long every --startup-probe attempt may take in milliseconds
+    #[allow(dead_code)]
+    pub startup_probe_timeout_ms: u64,
+
+    /// Which tracing events to log, as a level such as `debug` or directives such as
+    /// `narrow=debug,hyper=info`
+    #[allow(dead_code)]
+    pub log_level: String,
+
+    /// Threads the runtime handles connections on. Defaults to the CPUs available to the process,
+    /// which already honors container CPU quotas, so set it when quotas are fractional or absent
+    #[allow(dead_code)]
+    pub worker_threads: Option<usize>,
+
+    /// Forward the request path and query exactly as received, bypassing --strip-prefix,
+    /// --upstream-path-prefix and --normalize-forwarded-path
+    #[allow(dead_code)]
+    pub preserve_raw_uri: bool,
+
+    /// Write every interval's histograms as one NDJSON line to this file, named pipe or /dev/fd/N,
+    /// or to stdout when given `-` or no value
+    #[allow(dead_code)]
+    pub stats_stream: Option<PathBuf>,
+
+    /// Count requests per local hour of the day for every endpoint and print them in an extra
+    /// table
+    #[allow(dead_code)]
+    pub track_hourly: bool,
+
+    /// Send TCP keepalive probes on connections to the target server once they are idle for this
+    /// many milliseconds, so connections silently dropped by a firewall or NAT leave the pool (0
+    /// disables)
+    #[allow(dead_code)]
+    pub upstream_tcp_keepalive_ms: u64,
+
+    /// Instead of proxying, send the requests of a `--loglist-file` to the target server at their
+    /// original pace and print the response time histogram
+    #[allow(dead_code)]
+    pub replay: Option<PathBuf>,
+
+    /// Replay requests this many times as fast as they were logged
+    #[allow(dead_code)]
+    pub replay_speed: f64,
+
+    /// Count requests per requester IP and report this many of the busiest addresses every
+    /// interval
+    #[allow(dead_code)]
+    pub top_talkers: Option<usize>,
+
+    /// The most requester IPs `--top-talkers` counts per interval, requests from further addresses
+    /// are only counted as untracked
+    #[allow(dead_code)]
+    pub max_tracked_ips: usize,
+
+    /// Forward request headers in the order the client sent them, for target servers that sign or
+    /// verify requests over their raw headers. Repeated headers are grouped under their first
+    /// occurrence. Over HTTP/2 the client's order carries less meaning because pseudo-headers
+    /// always come first and header compression may have reordered fields on the way.
+    #[allow(dead_code)]
+    pub preserve_header_order: bool,
+
+    /// Hand free --max-upstream-requests slots to the waiting requests of each endpoint in turn,
+    /// so a flood of requests for one endpoint doesn't starve the others
+    #[allow(dead_code)]
+    pub fair_queueing: bool,
+
+    /// Keep requester IPs out of logs and statistics, recording a salted hash or the /24 (IPv4) or
+    /// /48 (IPv6) network instead. Blacklist, geo and trusted proxy checks still see the real
+    /// address.
+    #[allow(dead_code)]
+    pub anonymize_ips: Option<AnonymizeIps>,
+
+    /// The salt for `--anonymize-ips hash`, `--anonymize-salt` or a random one picked on start
+    #[allow(dead_code)]
+    pub anonymize_salt: String,
+
+    /// Reject requests whose URI is longer than this many bytes with a 414 (0 disables the limit)
+    #[allow(dead_code)]
+    pub max_uri_bytes: usize,
+
+    /// How many connections the kernel queues for the proxy port before refusing more, raise it
+    /// for bursty traffic (ignored with --listen-fd, whose socket is already listening)
+    #[allow(dead_code)]
+    pub listen_backlog: i32,
+
+    /// Write a sample of full requests and responses to this directory, one file per request ID
+    #[allow(dead_code)]
+    pub capture_dir: Option<PathBuf>,
+
+    /// The share of requests written to --capture-dir, by default 0.01 for one in a hundred
+    #[allow(dead_code)]
+    pub capture_sample_rate: f64,
+
+    /// Bytes of each body kept in a capture, the rest is cut off
+    #[allow(dead_code)]
+    pub capture_max_bytes: usize,
+
+    /// Headers whose values are left out of captures, as are those of --sensitive-headers
+    /// (comma-separated)
+    #[allow(dead_code)]
+    pub capture_redact_headers: Vec<String>,
+
+    /// Buffer request bodies that have to be sent more than once (for --mirror-to or
+    /// --follow-redirects) in temp files in this directory once they exceed
+    /// --spill-threshold-bytes
+    #[allow(dead_code)]
+    pub spill_to_disk: Option<PathBuf>,
+
+    /// Bytes of a buffered request body kept in memory before it is spilled to --spill-to-disk
+    #[allow(dead_code)]
+    pub spill_threshold_bytes: usize,
+
+    /// Send the access logs of every interval to syslog as RFC 5424 messages, to the local
+    /// daemon when given `local` or no value, or over UDP to a remote host:port
+    #[allow(dead_code)]
+    pub syslog: Option<String>,
+
+    /// The facility of the --syslog messages
+    #[allow(dead_code)]
+    pub syslog_facility: SyslogFacility,
+
+    /// Forward requests with a matching header to another upstream, as NAME=VALUE:HOST:PORT with a
+    /// trailing `*` on VALUE to match by prefix (repeatable, the first matching route wins).
+    /// Requests matching no route go to --host and --port, there are no path routes.
+    #[allow(dead_code)]
+    pub header_routes: Vec<HeaderRoute>,
+
+    /// Keep the last N forwarded requests across intervals and serve them at GET
+    /// /__narrow/recent, to the clients allowed to use the admin API (see
+    /// --admin-allow)
+    #[allow(dead_code)]
+    pub recent_buffer: Option<usize>,
+
+    /// Client CIDR ranges allowed to pick the upstream of a request with a `__upstream=host:port`
+    /// query parameter, for testing (comma-separated). The parameter is always removed before
+    /// forwarding, and ignored for everyone else.
+    #[allow(dead_code)]
+    pub allow_upstream_override: Vec<IpNet>,
+
+    /// Warn when the share of an endpoint's requests answered without a 5xx drops below a ratio
+    /// over an interval, as ENDPOINT=RATIO (repeatable)
+    #[allow(dead_code)]
+    pub endpoint_min_success: Vec<(String, f64)>,
+
+    /// Cache the addresses of the target server and resolve it again every this many milliseconds,
+    /// closing connections to addresses it no longer resolves to
+    #[allow(dead_code)]
+    pub dns_refresh_ms: Option<u64>,
+
+    /// Forget endpoints this many seconds after their last request instead of after an interval
+    /// without requests ("Overall" is always kept)
+    #[allow(dead_code)]
+    pub endpoint_ttl: Option<u64>,
+
+    /// Send the request durations and counts of every interval to this StatsD server (host:port)
+    /// over UDP
+    #[allow(dead_code)]
+    pub statsd: Option<String>,
+
+    /// Let identical GETs without credentials that arrive while one of them is being forwarded
+    /// share its response instead of reaching the target server each (only 200s without cookies,
+    /// Cache-Control: private or no-store, not varying on other request headers and of up to 1 MiB
+    /// are shared)
+    #[allow(dead_code)]
+    pub coalesce: bool,
+
+    /// Replace the body of 5xx responses from the target server with this file, keeping their
+    /// status and headers
+    #[allow(dead_code)]
+    pub error_page_5xx: Option<ErrorPage>,
+
+    /// Client CIDR ranges allowed to use the admin API under `/__narrow/` (comma-separated).
+    /// Without it only loopback clients that aren't --trusted-proxies may, everyone else gets a
+    /// 403. The admin paths share the proxy port, so this list is the only network boundary
+    /// around them.
+    #[allow(dead_code)]
+    pub admin_allow: Vec<IpNet>,
+
+    /// Bearer token the admin API additionally requires in `Authorization`, requests without it
+    /// get a 401
+    #[allow(dead_code)]
+    pub admin_token: Option<String>,
+
+    /// Time the phases of every request (preparing it, connecting, waiting for the response and
+    /// streaming the body) and report their averages, for tuning. Off by default, as it costs
+    /// some time itself.
+    #[allow(dead_code)]
+    pub profile: bool,
+
+    /// Delay requests from a client address or CIDR range by some milliseconds before forwarding
+    /// them, rather than rejecting them (e.g. 203.0.113.0/24=2000, repeatable). The first
+    /// matching range applies, the delay is not counted in the response times.
+    #[allow(dead_code)]
+    pub greylist: Vec<(IpNet, u64)>,
+
+    /// Expect a PROXY protocol v1 or v2 header from the load balancer in front of the proxy on
+    /// every connection, and use the client address it carries. Connections without a valid
+    /// one are closed.
+    #[allow(dead_code)]
+    pub proxy_protocol: bool,
+
+    /// The most streams an HTTP/2 client may have open on one connection at once
+    #[allow(dead_code)]
+    pub http2_max_concurrent_streams: Option<u32>,
+
+    /// The initial HTTP/2 flow control window of every stream in bytes, hyper defaults to 1 MiB
+    #[allow(dead_code)]
+    pub http2_initial_stream_window_size: Option<u32>,
+
+    /// The initial HTTP/2 flow control window of every connection in bytes, hyper defaults to
+    /// 1 MiB
+    #[allow(dead_code)]
+    pub http2_initial_connection_window_size: Option<u32>,
+
+    /// URL to POST a JSON alert to when --regression-pct or --endpoint-min-success is crossed
+    /// (http only). An alert is sent when its threshold is first crossed, not again every interval
+    /// until it clears.
+    #[allow(dead_code)]
+    pub alert_webhook: Option<String>,
+
+    /// Address of the running proxy whose admin API `stats` queries, as host:port. Defaults to
+    /// the loopback address on `--proxy`, which has to run with `--serve-stats`.
+    #[allow(dead_code)]
+    pub admin_addr: Option<String>,
+
+    /// Add an "EWMA p95" column with each endpoint's p95 latency smoothed across intervals, the
+    /// weight between 0 and 1 of the latest interval. A p95 in the open-ended top bucket counts as
+    /// its lower bound.
+    #[allow(dead_code)]
+    pub ewma_alpha: Option<f64>,
+
+    /// Rewrite request paths on the way to the target server with the rules in this file, one
+    /// regex and replacement per line where the first matching rule wins. Capture groups are
+    /// available as `$1` or `${name}`. The query is kept, a rewritten path with a query or
+    /// fragment of its own is answered with a 500.
+    #[allow(dead_code)]
+    pub rewrite_rules: Option<RewriteRules>,
+
+    /// Record histogram endpoints under the path as rewritten by --rewrite-rules, instead of the
+    /// path the client requested
+    #[allow(dead_code)]
+    pub record_rewritten_path: bool,
+
+    /// Order the endpoints of the interval table, in no particular order by default
+    #[allow(dead_code)]
+    pub sort_by: Option<SortBy>,
+
+    /// Show only this many endpoints in the interval table, the busiest or those first by
+    /// --sort-by, and sum up the rest in an "Other" row. Exports still get every endpoint.
+    #[allow(dead_code)]
+    pub top_n: Option<usize>,
+
+    /// Give up on a mirrored request after this many milliseconds, counting it as a mirror error
+    /// (0 for no timeout)
+    #[allow(dead_code)]
+    pub mirror_timeout_ms: u64,
+
+    /// Serve the histograms recorded so far this interval at GET /__narrow/stats for `narrow
+    /// stats`, to the clients allowed to use the admin API. Without it the path is forwarded like
+    /// any other.
+    #[allow(dead_code)]
+    pub serve_stats: bool,
+}
+
+impl Config {
+    /// Whether `ip` belongs to a trusted proxy allowed to send sensitive headers
+    pub fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// Whether `ip` may pick the upstream with `__upstream`, see `--allow-upstream-override`
+    pub fn may_override_upstream(&self, ip: &IpAddr) -> bool {
+        self.allow_upstream_override.iter().any(|net| net.contains(ip))
+    }
+
+    /// How long to hold requests from `ip` back, see `--greylist`
+    pub fn greylist_delay(&self, ip: &IpAddr) -> Option<Duration> {
+        let (_, ms) = self.greylist.iter().find(|(net, _)| net.contains(ip))?;
+        Some(Duration::from_millis(*ms))
+    }
+
+    /// How `ip` is recorded in logs and statistics under `--anonymize-ips`
+    pub fn requester_label(&self, ip: &IpAddr) -> String {
+        match self.anonymize_ips {
+            None => ip.to_string(),
+            Some(AnonymizeIps::Truncate) => {
+                let prefix = if ip.is_ipv4() { 24 } else { 48 };
+                IpNet::new(*ip, prefix).unwrap().trunc().to_string()
+            }
+            Some(AnonymizeIps::Hash) => {
+                // Keyed by the salt, so the pseudonyms can't be looked up without it
+                let salt = self.anonymize_salt.as_bytes();
+                let k0 = siphash24(0, 0, salt);
+                let k1 = siphash24(k0, 0, salt);
+                let octets = match ip {
+                    IpAddr::V4(ip) => ip.octets().to_vec(),
+                    IpAddr::V6(ip) => ip.octets().to_vec(),
+                };
+                format!("{:016x}", siphash24(k0, k1, &octets))
+            }
+        }
+    }
+
+    /// Whether requests for `path` may be forwarded under `--allow-path` and `--deny-path`
+    pub fn is_allowed_path(&self, path: &str) -> bool {
+        let allowed =
+            self.allow_path.is_empty() || self.allow_path.iter().any(|r| r.is_match(path));
+        allowed && !self.deny_path.iter().any(|r| r.is_match(path))
+    }
+}
+
+impl From<&Args> for Config {
+    fn from(args: &Args) -> Self {
+        Config {
+            admin_addr: args.admin_addr.clone(),
+            admin_allow: args.admin_allow.clone(),
+            admin_token: args.admin_token.clone(),
+            alert_webhook: args.alert_webhook.clone(),
+            allow_path: args.allow_path.clone(),
+            allow_upstream_override: args.allow_upstream_override.clone(),
+            anonymize_ips: args.anonymize_ips,
+            anonymize_salt: args
+                .anonymize_salt
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().simple().to_string()),
+            blacklist: args.blacklist.clone(),
+            blacklist_refresh: args.blacklist_refresh,
+            blacklist_url: args.blacklist_url.clone(),
+            block_asn: args.block_asn.clone(),
+            block_countries: args.block_countries.clone(),
+            capture_dir: args.capture_dir.clone(),
+            capture_max_bytes: args.capture_max_bytes,
+            capture_redact_headers: args.capture_redact_headers.clone(),
+            capture_sample_rate: args.capture_sample_rate,
+            #[cfg(feature = "chart")]
+            chart_output: args.chart_output.clone(),
+            coalesce: args.coalesce,
+            connect_timeout_ms: args.connect_timeout_ms,
+            deny_path: args.deny_path.clone(),
+            deny_status: args.deny_status,
+            dns_refresh_ms: args.dns_refresh_ms,
+            endpoint_min_success: args.endpoint_min_success.clone(),
+            endpoint_timeouts: args.endpoint_timeouts.clone(),
+            endpoint_ttl: args.endpoint_ttl,
+            error_format: args.error_format,
+            error_page_5xx: args.error_page_5xx.clone(),
+            ewma_alpha: args.ewma_alpha,
+            fair_queueing: args.fair_queueing,
+            follow_redirects: args.follow_redirects,
+            geoip_db: args.geoip_db.clone(),
+            greylist: args.greylist.clone(),
+            group_by_header: args.group_by_header.clone(),
+            header_routes: args.header_routes.clone(),
+            health_path: args.health_path.clone(),
+            highlight_error_ratio: args.highlight_error_ratio,
+            highlight_p95_ms: args.highlight_p95_ms,
+            host: args.host.clone(),
+            http2_initial_connection_window_size: args.http2_initial_connection_window_size,
+            http2_initial_stream_window_size: args.http2_initial_stream_window_size,
+            http2_max_concurrent_streams: args.http2_max_concurrent_streams,
+            interval: args.interval,
+            json_strip_fields: args.json_strip_fields.clone(),
+            json_strip_max_bytes: args.json_strip_max_bytes,
+            key: args.key.clone(),
+            listen_backlog: args.listen_backlog,
+            listen_fd: args.listen_fd,
+            log_level: args.log_level.clone(),
+            loglist_file: args.loglist_file.clone(),
+            max_endpoints: args.max_endpoints,
+            max_queued: args.max_queued,
+            max_request_header_bytes: args.max_request_header_bytes,
+            max_response_bytes: args.max_response_bytes,
+            max_tracked_ips: args.max_tracked_ips,
+            max_upstream_requests: args.max_upstream_requests,
+            max_uri_bytes: args.max_uri_bytes,
+            mirror_timeout_ms: args.mirror_timeout_ms,
+            mirror_to: args.mirror_to.clone(),
+            monitoring: args.monitoring,
+            no_color: args.no_color,
+            no_via: args.no_via,
+            normalize_forwarded_path: args.normalize_forwarded_path,
+            normalize_trailing_slash: args.normalize_trailing_slash,
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: args.otlp_endpoint.clone(),
+            percentiles: args.percentiles.clone(),
+            port: args.port,
+            preserve_header_order: args.preserve_header_order,
+            preserve_raw_uri: args.preserve_raw_uri,
+            profile: args.profile,
+            proxy: args.proxy,
+            proxy_protocol: args.proxy_protocol,
+            recent_buffer: args.recent_buffer,
+            record_rewritten_path: args.record_rewritten_path,
+            regression_pct: args.regression_pct,
+            replay: args.replay.clone(),
+            replay_speed: args.replay_speed,
+            rewrite_rules: args.rewrite_rules.clone(),
+            robots: args.robots.clone(),
+            sensitive_headers: args.sensitive_headers.clone(),
+            serve_favicon: args.serve_favicon,
+            serve_stats: args.serve_stats,
+            server: args.server.clone(),
+            show_methods: args.show_methods,
+            shutdown_grace_ms: args.shutdown_grace_ms,
+            sort_by: args.sort_by,
+            spill_threshold_bytes: args.spill_threshold_bytes,
+            spill_to_disk: args.spill_to_disk.clone(),
+            startup_probe: args.startup_probe,
+            startup_probe_retries: args.startup_probe_retries,
+            startup_probe_timeout_ms: args.startup_probe_timeout_ms,
+            static_dir: args.static_dir.clone(),
+            static_prefix: args.static_prefix.clone(),
+            stats_stream: args.stats_stream.clone(),
+            statsd: args.statsd.clone(),
+            strip_prefix: args.strip_prefix.clone(),
+            syslog: args.syslog.clone(),
+            syslog_facility: args.syslog_facility,
+            timeout_ms: args.timeout_ms,
+            top_n: args.top_n,
+            top_talkers: args.top_talkers,
+            track_hourly: args.track_hourly,
+            trusted_proxies: args.trusted_proxies.clone(),
+            upstream_path_prefix: args.upstream_path_prefix.clone(),
+            upstream_tcp_keepalive_ms: args.upstream_tcp_keepalive_ms,
+            via_name: args.via_name.clone(),
+            worker_threads: args.worker_threads,
+        }
+    }
 }
 
 // unit test
@@ -51,6 +703,107 @@ mod tests {
             monitoring: false,
             server: "https://monitoring.narrow.so".to_string(),
             key: "".to_string(),
+            static_dir: None,
+            static_prefix: vec!["/static".to_string()],
+            group_by_header: None,
+            max_endpoints: 1000,
+            upstream_path_prefix: None,
+            strip_prefix: None,
+            error_format: ErrorFormat::Text,
+            blacklist_url: None,
+            blacklist_refresh: 300,
+            no_color: false,
+            highlight_p95_ms: 500.0,
+            highlight_error_ratio: 0.05,
+            timeout_ms: 0,
+            listen_fd: None,
+            mirror_to: None,
+            sensitive_headers: vec![],
+            trusted_proxies: vec![],
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
+            #[cfg(feature = "chart")]
+            chart_output: None,
+            normalize_trailing_slash: None,
+            normalize_forwarded_path: false,
+            max_request_header_bytes: Some(16384),
+            show_methods: true,
+            json_strip_fields: vec!["debug".to_string()],
+            json_strip_max_bytes: 1048576,
+            shutdown_grace_ms: 10000,
+            geoip_db: None,
+            block_countries: vec![],
+            block_asn: vec![],
+            max_upstream_requests: 0,
+            max_queued: None,
+            connect_timeout_ms: 0,
+            regression_pct: Some(50.0),
+            serve_favicon: false,
+            robots: Some("User-agent: *\\nDisallow: /".to_string()),
+            follow_redirects: 0,
+            via_name: "narrow".to_string(),
+            no_via: false,
+            endpoint_timeouts: vec![],
+            loglist_file: None,
+            percentiles: vec![50.0, 95.0, 99.0],
+            deny_path: vec![],
+            allow_path: vec![],
+            deny_status: 404,
+            max_response_bytes: None,
+            startup_probe: false,
+            health_path: "/".to_string(),
+            startup_probe_retries: 0,
+            startup_probe_timeout_ms: 5000,
+            log_level: "info".to_string(),
+            worker_threads: None,
+            preserve_raw_uri: false,
+            stats_stream: None,
+            track_hourly: false,
+            upstream_tcp_keepalive_ms: 0,
+            replay: None,
+            replay_speed: 1.0,
+            top_talkers: None,
+            max_tracked_ips: 10000,
+            preserve_header_order: false,
+            fair_queueing: false,
+            anonymize_ips: None,
+            anonymize_salt: String::new(),
+            max_uri_bytes: 16384,
+            listen_backlog: 1024,
+            capture_dir: None,
+            capture_sample_rate: 0.01,
+            capture_max_bytes: 65536,
+            capture_redact_headers: vec![],
+            spill_to_disk: None,
+            spill_threshold_bytes: 1048576,
+            syslog: None,
+            syslog_facility: SyslogFacility::User,
+            header_routes: vec![],
+            recent_buffer: None,
+            allow_upstream_override: vec![],
+            endpoint_min_success: vec![],
+            dns_refresh_ms: None,
+            endpoint_ttl: None,
+            statsd: None,
+            coalesce: false,
+            error_page_5xx: None,
+            admin_allow: vec![],
+            admin_token: None,
+            profile: false,
+            greylist: vec![],
+            proxy_protocol: false,
+            http2_max_concurrent_streams: None,
+            http2_initial_stream_window_size: None,
+            http2_initial_connection_window_size: None,
+            alert_webhook: None,
+            admin_addr: Some("127.0.0.1:9000".to_string()),
+            ewma_alpha: Some(0.3),
+            rewrite_rules: None,
+            record_rewritten_path: false,
+            sort_by: Some(SortBy::P95),
+            top_n: Some(20),
+            mirror_timeout_ms: 5000,
+            serve_stats: false,
         };
 
         assert_eq!(config.proxy, 8001);
@@ -58,8 +811,167 @@ mod tests {
         assert_eq!(config.host, "example.com");
         assert_eq!(config.port, 3001);
         assert_eq!(config.blacklist, vec![] as Vec<IpAddr>);
-        assert_eq!(config.monitoring, false);
+        assert!(!config.monitoring);
         assert_eq!(config.server, "https://monitoring.narrow.so");
         assert_eq!(config.key, "");
+        assert_eq!(config.static_dir, None);
+        assert_eq!(config.static_prefix, vec!["/static"]);
+        assert_eq!(config.group_by_header, None);
+        assert_eq!(config.max_endpoints, 1000);
+        assert_eq!(config.upstream_path_prefix, None);
+        assert_eq!(config.strip_prefix, None);
+        assert_eq!(config.error_format, ErrorFormat::Text);
+        assert_eq!(config.blacklist_url, None);
+        assert_eq!(config.blacklist_refresh, 300);
+        assert!(!config.no_color);
+        assert_eq!(config.highlight_p95_ms, 500.0);
+        assert_eq!(config.highlight_error_ratio, 0.05);
+        assert_eq!(config.timeout_ms, 0);
+        assert_eq!(config.listen_fd, None);
+        assert_eq!(config.mirror_to, None);
+        assert_eq!(config.sensitive_headers, vec![] as Vec<String>);
+        assert_eq!(config.trusted_proxies, vec![] as Vec<IpNet>);
+        #[cfg(feature = "otlp")]
+        assert_eq!(config.otlp_endpoint, None);
+        #[cfg(feature = "chart")]
+        assert_eq!(config.chart_output, None);
+        assert_eq!(config.normalize_trailing_slash, None);
+        assert!(!config.normalize_forwarded_path);
+        assert_eq!(config.max_request_header_bytes, Some(16384));
+        assert!(config.show_methods);
+        assert_eq!(config.json_strip_fields, vec!["debug"]);
+        assert_eq!(config.json_strip_max_bytes, 1048576);
+        assert_eq!(config.shutdown_grace_ms, 10000);
+        assert_eq!(config.geoip_db, None);
+        assert_eq!(config.block_countries, vec![] as Vec<String>);
+        assert_eq!(config.block_asn, vec![] as Vec<u32>);
+        assert_eq!(config.max_upstream_requests, 0);
+        assert_eq!(config.max_queued, None);
+        assert_eq!(config.connect_timeout_ms, 0);
+        assert_eq!(config.regression_pct, Some(50.0));
+        assert!(!config.serve_favicon);
+        assert_eq!(config.robots.as_deref(), Some("User-agent: *\\nDisallow: /"));
+        assert_eq!(config.follow_redirects, 0);
+        assert_eq!(config.via_name, "narrow");
+        assert!(!config.no_via);
+        assert_eq!(config.endpoint_timeouts, vec![]);
+        assert_eq!(config.loglist_file, None);
+        assert_eq!(config.percentiles, vec![50.0, 95.0, 99.0]);
+        assert!(config.deny_path.is_empty());
+        assert!(config.allow_path.is_empty());
+        assert_eq!(config.deny_status, 404);
+        assert_eq!(config.max_response_bytes, None);
+        assert!(!config.startup_probe);
+        assert_eq!(config.health_path, "/");
+        assert_eq!(config.startup_probe_retries, 0);
+        assert_eq!(config.startup_probe_timeout_ms, 5000);
+        assert_eq!(config.log_level, "info");
+        assert_eq!(config.worker_threads, None);
+        assert!(!config.preserve_raw_uri);
+        assert_eq!(config.stats_stream, None);
+        assert!(!config.track_hourly);
+        assert_eq!(config.upstream_tcp_keepalive_ms, 0);
+        assert_eq!(config.replay, None);
+        assert_eq!(config.replay_speed, 1.0);
+        assert_eq!(config.top_talkers, None);
+        assert_eq!(config.max_tracked_ips, 10000);
+        assert!(!config.preserve_header_order);
+        assert!(!config.fair_queueing);
+        assert_eq!(config.anonymize_ips, None);
+        assert_eq!(config.max_uri_bytes, 16384);
+        assert_eq!(config.listen_backlog, 1024);
+        assert_eq!(config.capture_dir, None);
+        assert_eq!(config.capture_sample_rate, 0.01);
+        assert_eq!(config.capture_max_bytes, 65536);
+        assert_eq!(config.spill_to_disk, None);
+        assert_eq!(config.spill_threshold_bytes, 1048576);
+        assert_eq!(config.syslog, None);
+        assert_eq!(config.syslog_facility, SyslogFacility::User);
+        assert_eq!(config.header_routes, vec![]);
+        assert_eq!(config.recent_buffer, None);
+        assert_eq!(config.allow_upstream_override, vec![] as Vec<IpNet>);
+        assert_eq!(config.endpoint_min_success, vec![]);
+        assert_eq!(config.dns_refresh_ms, None);
+        assert_eq!(config.endpoint_ttl, None);
+        assert_eq!(config.statsd, None);
+        assert!(!config.coalesce);
+        assert_eq!(config.error_page_5xx, None);
+        assert_eq!(config.admin_allow, vec![] as Vec<IpNet>);
+        assert_eq!(config.admin_token, None);
+        assert!(!config.profile);
+        assert_eq!(config.greylist, vec![]);
+        assert!(!config.proxy_protocol);
+        assert_eq!(config.http2_max_concurrent_streams, None);
+        assert_eq!(config.http2_initial_stream_window_size, None);
+        assert_eq!(config.http2_initial_connection_window_size, None);
+        assert_eq!(config.alert_webhook, None);
+        assert_eq!(config.mirror_timeout_ms, 5000);
+        assert!(!config.serve_stats);
+    }
+
+    #[test]
+    fn test_requester_label() {
+        use clap::Parser;
+
+        let config = |argv: &[&str]| Config::from(&Args::parse_from([&["test"], argv].concat()));
+        let v4: IpAddr = "203.0.113.77".parse().unwrap();
+        let v6: IpAddr = "2001:db8:aa:bb::1".parse().unwrap();
+
+        assert_eq!(config(&[]).requester_label(&v4), "203.0.113.77");
+
+        let truncate = config(&["--anonymize-ips", "truncate"]);
+        assert_eq!(truncate.requester_label(&v4), "203.0.113.0/24");
+        assert_eq!(truncate.requester_label(&v6), "2001:db8:aa::/48");
+
+        let salted = config(&["--anonymize-ips", "hash", "--anonymize-salt", "pepper"]);
+        let label = salted.requester_label(&v4);
+        assert_eq!(label.len(), 16);
+        assert!(!label.contains("203"));
+        assert_eq!(label, salted.requester_label(&v4));
+        // The same in every build
+        assert_eq!(label, "b0886605656a7f8f");
+        assert_ne!(label, salted.requester_label(&v6));
+
+        let random = config(&["--anonymize-ips", "hash"]);
+        assert_ne!(random.anonymize_salt, config(&["--anonymize-ips", "hash"]).anonymize_salt);
+        assert_ne!(random.requester_label(&v4), label);
+    }
+
+    #[test]
+    fn test_is_allowed_path() {
+        use clap::Parser;
+
+        let config = |argv: &[&str]| Config::from(&Args::parse_from([&["test"], argv].concat()));
+
+        let open = config(&[]);
+        assert!(open.is_allowed_path("/admin"));
+
+        let deny = config(&["--deny-path", "^/admin(/|$)", "--deny-path", r"\.php$"]);
+        assert!(!deny.is_allowed_path("/admin"));
+        assert!(!deny.is_allowed_path("/admin/users"));
+        assert!(!deny.is_allowed_path("/index.php"));
+        assert!(deny.is_allowed_path("/administrators"));
+
+        let allow = config(&["--allow-path", "^/api/", "--deny-path", "^/api/internal/"]);
+        assert!(allow.is_allowed_path("/api/items"));
+        assert!(!allow.is_allowed_path("/api/internal/jobs"));
+        assert!(!allow.is_allowed_path("/"));
+    }
+
+    #[test]
+    fn test_greylist_delay() {
+        use clap::Parser;
+
+        let config = Config::from(&Args::parse_from([
+            "test",
+            "--greylist",
+            "10.0.0.1=500",
+            "--greylist",
+            "10.0.0.0/8=100",
+        ]));
+        let delay = |ip: &str| config.greylist_delay(&ip.parse().unwrap());
+        assert_eq!(delay("10.0.0.1"), Some(Duration::from_millis(500)));
+        assert_eq!(delay("10.2.3.4"), Some(Duration::from_millis(100)));
+        assert_eq!(delay("192.168.1.1"), None);
     }
 }
@@ -0,0 +1,131 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+use maxminddb::{geoip2, Reader};
+
+/// Lookups cached before the cache is cleared, so a flood of distinct IPs can't grow it forever
+const CACHE_CAPACITY: usize = 10_000;
+
+/// Where a client address is registered
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+/// A source of `GeoInfo`, a MaxMind database outside of tests
+pub trait GeoLookup: Send + Sync {
+    fn lookup(&self, ip: IpAddr) -> GeoInfo;
+}
+
+impl GeoLookup for Reader<Vec<u8>> {
+    fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        // Country and ASN data ship as separate databases, so either lookup may come up empty
+        let country = Reader::lookup::<geoip2::Country>(self, ip)
+            .ok()
+            .and_then(|c| c.country)
+            .and_then(|c| c.iso_code)
+            .map(str::to_string);
+        let asn =
+            Reader::lookup::<geoip2::Asn>(self, ip).ok().and_then(|a| a.autonomous_system_number);
+
+        GeoInfo { country, asn }
+    }
+}
+
+/// Blocks clients by the country or autonomous system their address is registered to
+pub struct GeoBlocker {
+    lookup: Box<dyn GeoLookup>,
+    countries: HashSet<String>,
+    asns: HashSet<u32>,
+    cache: Mutex<HashMap<IpAddr, bool>>,
+}
+
+impl GeoBlocker {
+    pub fn new(lookup: Box<dyn GeoLookup>, countries: &[String], asns: &[u32]) -> Self {
+        GeoBlocker {
+            lookup,
+            countries: countries.iter().map(|c| c.to_ascii_uppercase()).collect(),
+            asns: asns.iter().copied().collect(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Open the MaxMind database at `path`
+    pub fn open(path: &Path, countries: &[String], asns: &[u32]) -> Result<Self, String> {
+        let reader = Reader::open_readfile(path)
+            .map_err(|e| format!("could not open GeoIP database {}: {}", path.display(), e))?;
+        Ok(GeoBlocker::new(Box::new(reader), countries, asns))
+    }
+
+    pub fn blocks(&self, ip: &IpAddr) -> bool {
+        if let Some(blocked) = self.cache.lock().unwrap().get(ip) {
+            return *blocked;
+        }
+
+        let info = self.lookup.lookup(*ip);
+        let blocked = info.country.is_some_and(|c| self.countries.contains(&c))
+            || info.asn.is_some_and(|asn| self.asns.contains(&asn));
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(*ip, blocked);
+
+        blocked
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct FakeLookup {
+        entries: HashMap<IpAddr, GeoInfo>,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl GeoLookup for FakeLookup {
+        fn lookup(&self, ip: IpAddr) -> GeoInfo {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            self.entries.get(&ip).cloned().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_geo_blocker() {
+        let info =
+            |country: &str, asn| GeoInfo { country: Some(country.to_string()), asn: Some(asn) };
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let lookup = FakeLookup {
+            entries: HashMap::from([
+                ("1.1.1.1".parse().unwrap(), info("NL", 1)),
+                ("2.2.2.2".parse().unwrap(), info("US", 64500)),
+                ("3.3.3.3".parse().unwrap(), info("US", 2)),
+            ]),
+            lookups: Arc::clone(&lookups),
+        };
+        let blocker = GeoBlocker::new(Box::new(lookup), &["nl".to_string()], &[64500]);
+
+        assert!(blocker.blocks(&"1.1.1.1".parse().unwrap()));
+        assert!(blocker.blocks(&"2.2.2.2".parse().unwrap()));
+        assert!(!blocker.blocks(&"3.3.3.3".parse().unwrap()));
+        assert!(!blocker.blocks(&"4.4.4.4".parse().unwrap()));
+
+        assert!(blocker.blocks(&"1.1.1.1".parse().unwrap()));
+        assert_eq!(lookups.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_geo_blocker_open_missing_database() {
+        assert!(GeoBlocker::open(Path::new("/nonexistent/GeoLite2.mmdb"), &[], &[]).is_err());
+    }
+}
@@ -1,5 +1,11 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
 use chrono::{DateTime, Utc};
 use hyper::Method;
+use serde_json::{json, Value};
+
+use crate::state::Config;
 
 #[derive(Debug, Default, Clone)]
 pub struct Log {
@@ -15,8 +21,92 @@ pub struct Log {
     #[allow(dead_code)]
     pub requester_ip: String,
 
+    /// The `host:port` of the target server the request was forwarded to
+    #[allow(dead_code)]
+    pub upstream: String,
+
+    /// How many redirects of the target server were followed, see `--follow-redirects`
+    #[allow(dead_code)]
+    pub redirects: usize,
+
     #[allow(dead_code)]
     pub micros: u128,
+
+    /// The endpoint the request is counted under in the histograms
+    pub endpoint: String,
+
+    /// The status of the response to the client
+    pub status: u16,
+}
+
+impl Log {
+    /// The entry as written to `--loglist-file`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp.to_rfc3339(),
+            "method": self.req_method.as_str(),
+            "uri": self.req_uri,
+            "requester_ip": self.requester_ip,
+            "upstream": self.upstream,
+            "redirects": self.redirects,
+            "micros": self.micros as u64,
+            "endpoint": self.endpoint,
+            "status": self.status,
+        })
+    }
+
+    /// Read back an entry written by `to_json`, `None` if the timestamp, method or URI is missing
+    /// or invalid. Fields added later default when reading older files.
+    pub fn from_json(value: &Value) -> Option<Log> {
+        let str_field = |name: &str| value.get(name).and_then(Value::as_str);
+
+        Some(Log {
+            timestamp: DateTime::parse_from_rfc3339(str_field("timestamp")?).ok()?.into(),
+            req_method: Method::from_bytes(str_field("method")?.as_bytes()).ok()?,
+            req_uri: str_field("uri")?.to_string(),
+            requester_ip: str_field("requester_ip").unwrap_or_default().to_string(),
+            upstream: str_field("upstream").unwrap_or_default().to_string(),
+            redirects: value.get("redirects").and_then(Value::as_u64).unwrap_or_default() as usize,
+            micros: value.get("micros").and_then(Value::as_u64).unwrap_or_default() as u128,
+            endpoint: str_field("endpoint").unwrap_or_default().to_string(),
+            status: value.get("status").and_then(Value::as_u64).unwrap_or_default() as u16,
+        })
+    }
+}
+
+/// The last forwarded requests for `GET /__narrow/recent`. Unlike the log list this is never
+/// cleared, the oldest request is dropped once `capacity` are kept.
+#[derive(Debug)]
+pub struct RecentRequests {
+    capacity: usize,
+    logs: Mutex<VecDeque<Log>>,
+}
+
+impl RecentRequests {
+    pub fn new(capacity: usize) -> Self {
+        RecentRequests { capacity, logs: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    /// The buffer for `--recent-buffer`, if it is enabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.recent_buffer.map(RecentRequests::new)
+    }
+
+    pub fn push(&self, log: Log) {
+        let mut logs = self.logs.lock().unwrap();
+        if logs.len() >= self.capacity {
+            logs.pop_front();
+        }
+        if self.capacity > 0 {
+            logs.push_back(log);
+        }
+    }
+
+    /// The kept requests, newest first, as `--loglist-file` entries
+    pub fn to_json(&self) -> Value {
+        let logs = self.logs.lock().unwrap().iter().rev().map(Log::to_json).collect::<Vec<_>>();
+        json!({ "requests": logs })
+    }
 }
 
 // unit test
@@ -25,6 +115,26 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_recent_requests() {
+        let recent = RecentRequests::new(2);
+        for uri in ["/first", "/second", "/third"] {
+            recent.push(Log { req_uri: uri.to_string(), ..Default::default() });
+        }
+
+        let uris = recent.to_json()["requests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|log| log["uri"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(uris, ["/third", "/second"]);
+
+        let disabled = RecentRequests::new(0);
+        disabled.push(Log::default());
+        assert_eq!(disabled.to_json(), json!({ "requests": [] }));
+    }
+
     #[test]
     fn test_log() {
         let log = Log {
@@ -32,12 +142,74 @@ mod tests {
             req_method: Method::GET,
             req_uri: "/".to_string(),
             requester_ip: "1.1.1.1".to_owned(),
+            upstream: "127.0.0.1:8000".to_string(),
+            redirects: 1,
             micros: 100,
+            endpoint: "/".to_string(),
+            status: 200,
         };
 
         assert_eq!(log.req_method, Method::GET);
         assert_eq!(log.req_uri, "/");
         assert_eq!(log.requester_ip, "1.1.1.1");
+        assert_eq!(log.upstream, "127.0.0.1:8000");
+        assert_eq!(log.redirects, 1);
         assert_eq!(log.micros, 100);
+        assert_eq!(log.endpoint, "/");
+        assert_eq!(log.status, 200);
+    }
+
+    #[test]
+    fn test_log_to_json() {
+        let log = Log {
+            timestamp: DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().into(),
+            req_method: Method::POST,
+            req_uri: "/items?id=1".to_string(),
+            requester_ip: "1.1.1.1".to_owned(),
+            upstream: "127.0.0.1:8000".to_string(),
+            redirects: 0,
+            micros: 1500,
+            endpoint: "/items".to_string(),
+            status: 201,
+        };
+
+        assert_eq!(
+            log.to_json(),
+            json!({
+                "timestamp": "2024-05-01T12:00:00+00:00",
+                "method": "POST",
+                "uri": "/items?id=1",
+                "requester_ip": "1.1.1.1",
+                "upstream": "127.0.0.1:8000",
+                "redirects": 0,
+                "micros": 1500,
+                "endpoint": "/items",
+                "status": 201,
+            })
+        );
+    }
+
+    #[test]
+    fn test_log_from_json() {
+        let log = Log {
+            timestamp: DateTime::parse_from_rfc3339("2024-05-01T12:00:00.250Z").unwrap().into(),
+            req_method: Method::PUT,
+            req_uri: "/items/1".to_string(),
+            requester_ip: "1.1.1.1".to_owned(),
+            upstream: "127.0.0.1:8000".to_string(),
+            redirects: 2,
+            micros: 1500,
+            endpoint: "/items/1".to_string(),
+            status: 204,
+        };
+
+        let read = Log::from_json(&log.to_json()).unwrap();
+        assert_eq!(read.to_json(), log.to_json());
+
+        let old = json!({"timestamp": "2024-05-01T12:00:00Z", "method": "GET", "uri": "/"});
+        assert_eq!(Log::from_json(&old).unwrap().redirects, 0);
+        assert!(Log::from_json(&json!({"method": "GET", "uri": "/"})).is_none());
+        assert!(Log::from_json(&json!({"timestamp": "yesterday", "method": "GET", "uri": "/"}))
+            .is_none());
     }
 }
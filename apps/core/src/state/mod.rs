@@ -1,15 +1,34 @@
+mod blacklist;
 mod config;
+mod geoip;
 mod log;
+mod siphash;
 
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
+pub use blacklist::*;
 pub use config::*;
+pub use geoip::*;
 use hyper::Client;
 pub use log::*;
 
-use crate::statistics::Histogram;
+use crate::net::client::CountingConnector;
+use crate::net::coalesce::Coalescer;
+use crate::net::queue::UpstreamLimiter;
+use crate::statistics::{
+    Counters, Histogram, RequesterCounts, UpstreamConnections, UpstreamCounts
+};
 
-pub type HttpClient = Client<hyper::client::HttpConnector>;
+pub type HttpClient = Client<CountingConnector>;
 pub type HistogramMap = Arc<Mutex<HashMap<String, Histogram>>>;
 pub type LogList = Arc<Mutex<Vec<Log>>>;
+pub type SharedCounters = Arc<Counters>;
+pub type SharedConnections = Arc<UpstreamConnections>;
+pub type SharedUpstreams = Arc<UpstreamCounts>;
+pub type SharedBlacklist = Arc<RwLock<Blacklist>>;
+pub type SharedGeoBlocker = Option<Arc<GeoBlocker>>;
+pub type SharedLimiter = Option<Arc<UpstreamLimiter>>;
+pub type SharedTalkers = Option<Arc<RequesterCounts>>;
+pub type SharedRecent = Option<Arc<RecentRequests>>;
+pub type SharedCoalescer = Option<Arc<Coalescer>>;
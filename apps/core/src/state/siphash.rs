@@ -0,0 +1,63 @@
+/// SipHash-2-4 of `data` under the key `(k0, k1)`. Unlike std's `DefaultHasher`, whose algorithm
+/// may change between Rust releases, the output stays the same across builds, so
+/// `--anonymize-salt` gives stable pseudonyms.
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v[3] ^= m;
+        rounds(&mut v, 2);
+        v[0] ^= m;
+    }
+    // The last word holds the remaining bytes and the length in its top byte
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    let m = u64::from_le_bytes(last) | ((data.len() as u64) << 56);
+    v[3] ^= m;
+    rounds(&mut v, 2);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    rounds(&mut v, 4);
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn rounds(v: &mut [u64; 4], n: usize) {
+    for _ in 0..n {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_siphash24() {
+        // The test vectors of the SipHash paper: key 00..0f, messages 00, 00 01, ...
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let message = (0..15).collect::<Vec<u8>>();
+        assert_eq!(siphash24(k0, k1, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(k0, k1, &message), 0xa129ca6149be45e5);
+        assert_eq!(siphash24(k0, k1, &message[..8]), 0x93f5f5799a932462);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request};
+use serde_json::{json, Value};
+
+use crate::state::HttpClient;
+use crate::statistics::{Regression, SuccessAlert};
+
+/// A threshold an endpoint crossed in the last interval, as posted to `--alert-webhook`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub endpoint: String,
+    pub metric: &'static str,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl Alert {
+    /// A `--regression-pct` breach, which compares the p95 with the previous interval's
+    pub fn regression(regression: &Regression, pct: f64) -> Self {
+        Alert {
+            endpoint: regression.endpoint.clone(),
+            metric: "p95_ms",
+            value: regression.after,
+            threshold: regression.before * (1.0 + pct / 100.0),
+        }
+    }
+
+    /// An `--endpoint-min-success` breach
+    pub fn success_rate(alert: &SuccessAlert) -> Self {
+        Alert {
+            endpoint: alert.endpoint.clone(),
+            metric: "success_ratio",
+            value: alert.success_ratio,
+            threshold: alert.threshold,
+        }
+    }
+
+    /// The JSON body, a p95 in the open-ended top bucket is `null`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "endpoint": self.endpoint,
+            "metric": self.metric,
+            "value": self.value,
+            "threshold": self.threshold,
+        })
+    }
+}
+
+/// Keeps an alert from being sent again every interval while its threshold stays crossed. It is
+/// sent again once it cleared for at least one interval.
+#[derive(Debug, Default)]
+pub struct AlertDebouncer {
+    active: Mutex<HashSet<(String, &'static str)>>,
+}
+
+impl AlertDebouncer {
+    /// The alerts of this interval that weren't already raised in the last one
+    pub fn fresh(&self, alerts: Vec<Alert>) -> Vec<Alert> {
+        let mut active = self.active.lock().unwrap();
+        let previous = std::mem::take(&mut *active);
+        active.extend(alerts.iter().map(|alert| (alert.endpoint.clone(), alert.metric)));
+
+        alerts
+            .into_iter()
+            .filter(|alert| !previous.contains(&(alert.endpoint.clone(), alert.metric)))
+            .collect()
+    }
+}
+
+/// POST every alert to `url` as a JSON object of its own
+pub async fn post_alerts(client: &HttpClient, url: &str, alerts: &[Alert]) -> Result<(), String> {
+    for alert in alerts {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(alert.to_json().to_string()))
+            .map_err(|e| format!("invalid alert webhook {}: {}", url, e))?;
+
+        let resp =
+            client.request(req).await.map_err(|e| format!("could not reach {}: {}", url, e))?;
+        if !resp.status().is_success() {
+            return Err(format!("{} returned {}", url, resp.status()));
+        }
+    }
+
+    Ok(())
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use hyper::StatusCode;
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::client::build_client;
+    use crate::net::mock::MockUpstream;
+    use crate::state::Config;
+
+    fn alert(endpoint: &str, metric: &'static str) -> Alert {
+        Alert { endpoint: endpoint.to_string(), metric, value: 0.9, threshold: 0.99 }
+    }
+
+    #[test]
+    fn test_alert_debouncer() {
+        let debouncer = AlertDebouncer::default();
+        let checkout = alert("/checkout", "success_ratio");
+        let slow = alert("/checkout", "p95_ms");
+
+        assert_eq!(debouncer.fresh(vec![checkout.clone()]), vec![checkout.clone()]);
+        // Still crossed, only the new one is sent
+        assert_eq!(debouncer.fresh(vec![checkout.clone(), slow.clone()]), vec![slow.clone()]);
+        assert_eq!(debouncer.fresh(vec![]), vec![]);
+        assert_eq!(debouncer.fresh(vec![checkout.clone()]), vec![checkout]);
+    }
+
+    #[test]
+    fn test_alert_from_regression() {
+        let regression = Regression { endpoint: "/api".to_string(), before: 100.0, after: 250.0 };
+        let alert = Alert::regression(&regression, 50.0);
+        assert_eq!(
+            alert.to_json(),
+            json!({"endpoint": "/api", "metric": "p95_ms", "value": 250.0, "threshold": 150.0})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_alerts() {
+        let webhook = MockUpstream::start(StatusCode::NO_CONTENT, "", Duration::ZERO).await;
+        let client = build_client(&Config::from(&Args::parse_from(["test"])), Arc::default());
+        let url = format!("http://{}/hooks/narrow", webhook.addr);
+
+        let alerts = [alert("/checkout", "success_ratio"), alert("/search", "success_ratio")];
+        post_alerts(&client, &url, &alerts).await.unwrap();
+        let requests = webhook.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, Method::POST);
+        assert_eq!(requests[0].uri, "/hooks/narrow");
+        let body = serde_json::from_slice::<Value>(&requests[1].body).unwrap();
+        assert_eq!(body["endpoint"], "/search");
+        assert_eq!(body["threshold"], 0.99);
+
+        let failing = MockUpstream::start(StatusCode::BAD_GATEWAY, "", Duration::ZERO).await;
+        let url = format!("http://{}/", failing.addr);
+        assert!(post_alerts(&client, &url, &alerts).await.is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::statistics::{Histogram, BUCKET_BOUNDS_MS};
+
+/// Fill colors of the latency buckets, from fast to slow
+const BUCKET_COLORS: [&str; 6] = ["#2e7d32", "#7cb342", "#fdd835", "#fb8c00", "#e53935", "#8e24aa"];
+
+const BAR_WIDTH: usize = 14;
+const GROUP_GAP: usize = 28;
+const PLOT_HEIGHT: usize = 240;
+const MARGIN: usize = 40;
+
+/// A bar chart of the latency buckets of every endpoint that received requests as an SVG
+/// document, "Overall" first and the rest sorted, each group of bars scaled to the busiest bucket
+pub fn render_chart(histograms: &HashMap<String, Histogram>) -> String {
+    let mut endpoints = histograms.iter().filter(|(_, h)| h.total_requests > 0).collect::<Vec<_>>();
+    endpoints.sort_by_key(|(endpoint, _)| (endpoint.as_str() != "Overall", endpoint.as_str()));
+
+    let labels = bucket_labels();
+    let group_width = BAR_WIDTH * labels.len() + GROUP_GAP;
+    let width = (MARGIN * 2 + group_width * endpoints.len()).max(MARGIN * 2 + 90 * labels.len());
+    let plot_top = MARGIN + 40;
+    let plot_bottom = plot_top + PLOT_HEIGHT;
+    let height = plot_bottom + MARGIN + 20;
+    let max = endpoints.iter().flat_map(|(_, h)| h.buckets()).max().unwrap_or(0).max(1);
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+        width, height
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="24" font-size="16">Requests per latency bucket</text>"#,
+        MARGIN
+    );
+
+    for (i, (label, color)) in labels.iter().zip(BUCKET_COLORS).enumerate() {
+        let x = MARGIN + i * 90;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="38" width="10" height="10" fill="{}"/><text x="{}" y="47">{}</text>"#,
+            x,
+            color,
+            x + 14,
+            label
+        );
+    }
+
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+        MARGIN,
+        plot_bottom,
+        width - MARGIN,
+        plot_bottom
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" fill="gray">max {}</text>"#,
+        MARGIN,
+        plot_top - 4,
+        max
+    );
+
+    for (group, (endpoint, hist)) in endpoints.iter().enumerate() {
+        let endpoint = escape(endpoint);
+        let left = MARGIN + GROUP_GAP / 2 + group * group_width;
+        for (i, ((count, label), color)) in
+            hist.buckets().into_iter().zip(&labels).zip(BUCKET_COLORS).enumerate()
+        {
+            let bar = (count as usize * PLOT_HEIGHT).div_ceil(max as usize);
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"><title>{} {}: {}</title></rect>"#,
+                left + i * BAR_WIDTH,
+                plot_bottom - bar,
+                BAR_WIDTH,
+                bar,
+                color,
+                endpoint,
+                label,
+                count
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+            left + BAR_WIDTH * labels.len() / 2,
+            plot_bottom + 18,
+            endpoint
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The bucket names as the table titles them, e.g. `11-100ms`
+fn bucket_labels() -> Vec<String> {
+    let mut lower = 0;
+    let mut labels = BUCKET_BOUNDS_MS
+        .iter()
+        .map(|bound| {
+            let label = format!("{}-{}ms", lower, bound);
+            lower = bound + 1;
+            label
+        })
+        .collect::<Vec<_>>();
+    labels.push(format!("{}ms+", BUCKET_BOUNDS_MS[BUCKET_BOUNDS_MS.len() - 1]));
+
+    labels
+}
+
+/// Endpoints are request paths, and may contain anything
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket_labels() {
+        assert_eq!(
+            bucket_labels(),
+            ["0-10ms", "11-100ms", "101-250ms", "251-500ms", "501-1000ms", "1000ms+"]
+        );
+    }
+
+    #[test]
+    fn test_render_chart() {
+        let mut hist = Histogram::default();
+        hist.add(Duration::from_millis(5), Utc::now());
+        hist.add(Duration::from_millis(5), Utc::now());
+        hist.add(Duration::from_millis(50), Utc::now());
+        let histograms = HashMap::from([
+            ("Overall".to_string(), hist.clone()),
+            ("/search?q=<b>".to_string(), hist),
+            ("/idle".to_string(), Histogram::default()),
+        ]);
+
+        let svg = render_chart(&histograms);
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(r##"height="240" fill="#2e7d32"><title>Overall 0-10ms: 2</title>"##));
+        assert!(svg.contains(r##"height="120" fill="#7cb342"><title>Overall 11-100ms: 1</title>"##));
+        assert!(svg.contains(">/search?q=&lt;b&gt;</text>"));
+        assert!(!svg.contains("/idle"));
+        assert!(svg.find(">Overall</text>") < svg.find(">/search"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use prettytable::{format, Cell, Row, Table};
+
+use crate::statistics::{format_percentile, Histogram, MIRROR_PREFIX};
+
+/// Every endpoint that saw requests both on the target server and on the `--mirror-to` target,
+/// with the histograms of each, "Overall" first and the rest sorted
+pub fn mirror_pairs(
+    histograms: &HashMap<String, Histogram>,
+) -> Vec<(&str, &Histogram, &Histogram)> {
+    let mut pairs = histograms
+        .iter()
+        .filter_map(|(endpoint, target)| {
+            let mirror = histograms.get(&format!("{}{}", MIRROR_PREFIX, endpoint))?;
+            (target.total_requests > 0 && mirror.total_requests > 0).then_some((
+                endpoint.as_str(),
+                target,
+                mirror,
+            ))
+        })
+        .collect::<Vec<_>>();
+    pairs.sort_by_key(|(endpoint, _, _)| (*endpoint != "Overall", *endpoint));
+
+    pairs
+}
+
+pub fn add_comparison_row(
+    table: &mut Table,
+    endpoint: &str,
+    target: &Histogram,
+    mirror: &Histogram,
+    percentiles: &[f64],
+) {
+    let pair = |target: String, mirror: String| Cell::new(&format!("{} / {}", target, mirror));
+
+    let mut cells = vec![
+        Cell::new(endpoint),
+        pair(target.total_requests.to_string(), mirror.total_requests.to_string()),
+        pair(target.count_5xx.to_string(), mirror.count_5xx.to_string()),
+    ];
+    cells.extend(percentiles.iter().map(|p| {
+        pair(format_percentile(target.percentile(*p)), format_percentile(mirror.percentile(*p)))
+    }));
+
+    table.add_row(Row::new(cells));
+}
+
+/// Print the target server and the mirror side by side for every endpoint both of them served,
+/// nothing if there are none
+pub fn print_mirror_comparison(
+    histograms: &HashMap<String, Histogram>,
+    percentiles: &[f64],
+) -> String {
+    let pairs = mirror_pairs(histograms);
+    if pairs.is_empty() {
+        return String::new();
+    }
+
+    println!("Mirror Comparison (target / mirror):");
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    let mut titles = vec![Cell::new("Endpoint"), Cell::new("Requests"), Cell::new("5xx")];
+    titles.extend(percentiles.iter().map(|p| Cell::new(&format!("p{}", p))));
+    table.set_titles(Row::new(titles));
+
+    for (endpoint, target, mirror) in pairs {
+        add_comparison_row(&mut table, endpoint, target, mirror, percentiles);
+    }
+
+    table.printstd();
+    println!();
+
+    table.to_string()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    fn histogram(millis: &[u64]) -> Histogram {
+        let mut hist = Histogram::default();
+        for ms in millis {
+            hist.add(Duration::from_millis(*ms), Utc::now());
+        }
+        hist
+    }
+
+    #[test]
+    fn test_mirror_pairs() {
+        let histograms = HashMap::from([
+            ("Overall".to_string(), histogram(&[5, 50])),
+            ("mirror Overall".to_string(), histogram(&[200])),
+            ("/b".to_string(), histogram(&[50])),
+            ("mirror /b".to_string(), histogram(&[200])),
+            ("/a".to_string(), histogram(&[5])),
+            ("mirror /a".to_string(), Histogram::default()),
+            ("/unmirrored".to_string(), histogram(&[5])),
+        ]);
+
+        let pairs = mirror_pairs(&histograms);
+        assert_eq!(
+            pairs.iter().map(|(endpoint, _, _)| *endpoint).collect::<Vec<_>>(),
+            ["Overall", "/b"]
+        );
+
+        let mut table = Table::new();
+        let (endpoint, target, mirror) = pairs[1];
+        add_comparison_row(&mut table, endpoint, target, mirror, &[50.0]);
+        assert_eq!(
+            table.get_row(0).unwrap().into_iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec!["/b", "1 / 1", "0 / 0", "100ms / 250ms"]
+        );
+
+        assert_eq!(print_mirror_comparison(&HashMap::new(), &[50.0]), "");
+    }
+}
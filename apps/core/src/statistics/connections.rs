@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// The connections held to one upstream `host:port`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HostConnections {
+    /// Connections open right now, idle in the pool or serving a request
+    pub open: u64,
+
+    /// The most connections open at once this interval
+    pub peak: u64,
+
+    /// Connections opened this interval, a high number hints at a pool that is too small
+    pub opened: u64,
+}
+
+/// Connections to every upstream the proxy talks to, kept up to date by the client's connector
+#[derive(Debug, Default)]
+pub struct UpstreamConnections {
+    hosts: Mutex<BTreeMap<String, HostConnections>>,
+}
+
+impl UpstreamConnections {
+    pub fn opened(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let conns = hosts.entry(host.to_string()).or_default();
+        conns.open += 1;
+        conns.opened += 1;
+        conns.peak = conns.peak.max(conns.open);
+    }
+
+    pub fn closed(&self, host: &str) {
+        if let Some(conns) = self.hosts.lock().unwrap().get_mut(host) {
+            conns.open = conns.open.saturating_sub(1);
+        }
+    }
+
+    /// The connections of every host this interval, starting the next interval from those still
+    /// open. Hosts without an open connection are forgotten once reported.
+    pub fn take(&self) -> Vec<(String, HostConnections)> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let taken = hosts.iter().map(|(host, conns)| (host.clone(), *conns)).collect();
+
+        hosts.retain(|_, conns| conns.open > 0);
+        for conns in hosts.values_mut() {
+            conns.peak = conns.open;
+            conns.opened = 0;
+        }
+
+        taken
+    }
+}
+
+pub fn print_connections(connections: &[(String, HostConnections)]) -> String {
+    if connections.is_empty() {
+        return String::new();
+    }
+
+    let line = connections
+        .iter()
+        .map(|(host, conns)| {
+            format!("{}: {} open (peak {}, {} opened)", host, conns.open, conns.peak, conns.opened)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!("Upstream connections - {}\n", line);
+
+    line
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_upstream_connections() {
+        let connections = UpstreamConnections::default();
+        connections.opened("127.0.0.1:3000");
+        connections.opened("127.0.0.1:3000");
+        connections.opened("127.0.0.1:3000");
+        connections.closed("127.0.0.1:3000");
+        connections.opened("10.0.0.1:80");
+        connections.closed("10.0.0.1:80");
+
+        let taken = connections.take();
+        assert_eq!(
+            taken,
+            vec![
+                ("10.0.0.1:80".to_string(), HostConnections { open: 0, peak: 1, opened: 1 }),
+                ("127.0.0.1:3000".to_string(), HostConnections { open: 2, peak: 3, opened: 3 }),
+            ]
+        );
+        assert_eq!(
+            print_connections(&taken),
+            "10.0.0.1:80: 0 open (peak 1, 1 opened), 127.0.0.1:3000: 2 open (peak 3, 3 opened)"
+        );
+
+        assert_eq!(
+            connections.take(),
+            vec![("127.0.0.1:3000".to_string(), HostConnections { open: 2, peak: 2, opened: 0 })]
+        );
+    }
+}
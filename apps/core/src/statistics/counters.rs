@@ -0,0 +1,214 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+/// Proxy-wide event counters, reset every interval alongside the histograms
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// Requests that failed because the target host could not be resolved
+    pub dns_errors: AtomicU64,
+
+    /// Requests that failed because no connection could be made to the target server
+    pub connect_errors: AtomicU64,
+
+    /// Requests that were not answered by the target server before their timeout
+    pub timeouts: AtomicU64,
+
+    /// Mirrored requests that could not be delivered to the mirror target
+    pub mirror_errors: AtomicU64,
+
+    /// Requests answered with a 503 because the queue for the target server was full
+    pub queue_rejections: AtomicU64,
+
+    /// The most requests waiting for the target server at once
+    pub peak_queue_depth: AtomicU64,
+
+    /// Responses refused or cut off for exceeding `--max-response-bytes`
+    pub oversized_responses: AtomicU64,
+
+    /// Requests answered with the response fetched for an identical one, see `--coalesce`
+    pub coalesced: AtomicU64,
+
+    /// Requests from `--greylist` clients that were held back before being handled
+    pub greylisted: AtomicU64,
+
+    /// Requests answered with a 500 because handling them panicked
+    pub panics: AtomicU64,
+
+    /// Times the histograms were locked by `lock_stats`
+    pub stats_locks: AtomicU64,
+
+    /// Microseconds spent waiting for those locks in total
+    pub stats_lock_wait_micros: AtomicU64,
+
+    /// The longest wait for one of them
+    pub peak_stats_lock_wait_micros: AtomicU64,
+
+    /// Requests being handled right now. This is a gauge, `take` leaves it alone.
+    pub in_flight: AtomicU64,
+
+    /// Requests waiting for the target server right now, a gauge as well
+    pub queue_depth: AtomicU64,
+}
+
+impl Counters {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Lock the stats `mutex`, recording how long that took so contention shows up in the counters.
+    /// A handler that panicked while holding it leaves the stats usable, so the poison is ignored
+    pub fn lock_stats<'a, T>(&self, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        let start = Instant::now();
+        let guard = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        let waited = start.elapsed().as_micros() as u64;
+
+        Counters::incr(&self.stats_locks);
+        self.stats_lock_wait_micros.fetch_add(waited, Ordering::Relaxed);
+        self.peak_stats_lock_wait_micros.fetch_max(waited, Ordering::Relaxed);
+
+        guard
+    }
+
+    /// Read and zero every counter, returning `(name, value)` pairs
+    pub fn take(&self) -> Vec<(&'static str, u64)> {
+        let locks = self.stats_locks.swap(0, Ordering::Relaxed);
+        let lock_wait_micros = self.stats_lock_wait_micros.swap(0, Ordering::Relaxed);
+
+        vec![
+            ("DNS errors", self.dns_errors.swap(0, Ordering::Relaxed)),
+            ("Connect errors", self.connect_errors.swap(0, Ordering::Relaxed)),
+            ("Timeouts", self.timeouts.swap(0, Ordering::Relaxed)),
+            ("Mirror errors", self.mirror_errors.swap(0, Ordering::Relaxed)),
+            ("Queue rejections", self.queue_rejections.swap(0, Ordering::Relaxed)),
+            ("Queue depth", self.queue_depth.load(Ordering::Relaxed)),
+            ("Peak queue depth", self.peak_queue_depth.swap(0, Ordering::Relaxed)),
+            ("Oversized responses", self.oversized_responses.swap(0, Ordering::Relaxed)),
+            ("Coalesced requests", self.coalesced.swap(0, Ordering::Relaxed)),
+            ("Greylisted requests", self.greylisted.swap(0, Ordering::Relaxed)),
+            ("Handler panics", self.panics.swap(0, Ordering::Relaxed)),
+            ("Stats lock wait avg (us)", lock_wait_micros.checked_div(locks).unwrap_or(0)),
+            (
+                "Stats lock wait max (us)",
+                self.peak_stats_lock_wait_micros.swap(0, Ordering::Relaxed),
+            ),
+        ]
+    }
+}
+
+/// Counts a request as in flight until it is dropped, even when the client goes away and its
+/// handler is cancelled
+pub struct InFlight(Arc<Counters>);
+
+impl InFlight {
+    pub fn start(counters: Arc<Counters>) -> Self {
+        Counters::incr(&counters.in_flight);
+        InFlight(counters)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn print_counters(counters: &[(&'static str, u64)]) -> String {
+    let line = counters
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!("{}\n", line);
+
+    line
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let counters = Counters::default();
+        Counters::incr(&counters.dns_errors);
+        Counters::incr(&counters.dns_errors);
+        Counters::incr(&counters.connect_errors);
+
+        let taken = counters.take();
+        assert_eq!(
+            taken,
+            vec![
+                ("DNS errors", 2),
+                ("Connect errors", 1),
+                ("Timeouts", 0),
+                ("Mirror errors", 0),
+                ("Queue rejections", 0),
+                ("Queue depth", 0),
+                ("Peak queue depth", 0),
+                ("Oversized responses", 0),
+                ("Coalesced requests", 0),
+                ("Greylisted requests", 0),
+                ("Handler panics", 0),
+                ("Stats lock wait avg (us)", 0),
+                ("Stats lock wait max (us)", 0)
+            ]
+        );
+        assert_eq!(
+            print_counters(&taken),
+            "DNS errors: 2, Connect errors: 1, Timeouts: 0, Mirror errors: 0, Queue rejections: 0, \
+             Queue depth: 0, Peak queue depth: 0, Oversized responses: 0, Coalesced requests: 0, \
+             Greylisted requests: 0, Handler panics: 0, Stats lock wait avg (us): 0, Stats lock wait max (us): 0"
+        );
+        assert!(counters.take().iter().all(|(_, value)| *value == 0));
+    }
+
+    #[test]
+    fn test_lock_stats() {
+        let counters = Counters::default();
+        let stats = Mutex::new(0);
+        *counters.lock_stats(&stats) += 1;
+        *counters.lock_stats(&stats) += 1;
+        assert_eq!(*stats.lock().unwrap(), 2);
+        assert_eq!(counters.stats_locks.load(Ordering::Relaxed), 2);
+
+        let held = stats.lock().unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| drop(counters.lock_stats(&stats)));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            drop(held);
+        });
+        let taken = counters.take().into_iter().collect::<std::collections::HashMap<_, _>>();
+        assert!(taken["Stats lock wait max (us)"] >= 20_000);
+        assert!(taken["Stats lock wait avg (us)"] >= 20_000 / 3);
+        assert!(counters.take().iter().all(|(_, value)| *value == 0));
+
+        let _ = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _held = stats.lock().unwrap();
+                    panic!("poison the stats");
+                })
+                .join()
+        });
+        assert!(stats.is_poisoned());
+        *counters.lock_stats(&stats) += 1;
+        assert_eq!(*counters.lock_stats(&stats), 3);
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let counters = Arc::new(Counters::default());
+        let first = InFlight::start(Arc::clone(&counters));
+        let second = InFlight::start(Arc::clone(&counters));
+        counters.take();
+        assert_eq!(counters.in_flight.load(Ordering::Relaxed), 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(counters.in_flight.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::statistics::{Histogram, BUCKET_BOUNDS_MS};
+
+/// The p95 latency of every endpoint as an exponentially weighted moving average across
+/// intervals, for `--ewma-alpha`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyEwma {
+    alpha: f64,
+    p95_ms: HashMap<String, f64>,
+}
+
+impl LatencyEwma {
+    pub fn new(alpha: f64) -> Self {
+        LatencyEwma { alpha, p95_ms: HashMap::new() }
+    }
+
+    /// Fold the p95 of this interval's `histograms` into the averages and set them on the
+    /// histograms for the table. Endpoints without requests keep their average, endpoints no
+    /// longer tracked are forgotten.
+    pub fn update(&mut self, histograms: &mut HashMap<String, Histogram>) {
+        self.p95_ms.retain(|endpoint, _| histograms.contains_key(endpoint));
+
+        for (endpoint, hist) in histograms.iter_mut() {
+            if let Some(p95) = hist.percentile(95.0) {
+                let p95 = p95.min(BUCKET_BOUNDS_MS[BUCKET_BOUNDS_MS.len() - 1] as f64);
+                self.p95_ms
+                    .entry(endpoint.clone())
+                    .and_modify(|average| *average += self.alpha * (p95 - *average))
+                    .or_insert(p95);
+            }
+            hist.ewma_p95_ms = self.p95_ms.get(endpoint).copied();
+        }
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    fn interval(latencies_ms: &[u64]) -> HashMap<String, Histogram> {
+        let mut hist = Histogram::default();
+        for ms in latencies_ms {
+            hist.add(Duration::from_millis(*ms), Utc::now());
+        }
+        HashMap::from([("/api".to_string(), hist)])
+    }
+
+    #[test]
+    fn test_latency_ewma() {
+        let mut ewma = LatencyEwma::new(0.5);
+
+        let mut histograms = interval(&[5]);
+        ewma.update(&mut histograms);
+        assert_eq!(histograms["/api"].ewma_p95_ms, Some(10.0));
+
+        let mut histograms = interval(&[200]);
+        ewma.update(&mut histograms);
+        assert_eq!(histograms["/api"].ewma_p95_ms, Some(130.0));
+
+        // The open-ended top bucket counts as 1000ms
+        let mut histograms = interval(&[3000]);
+        ewma.update(&mut histograms);
+        assert_eq!(histograms["/api"].ewma_p95_ms, Some(565.0));
+
+        let mut histograms = interval(&[]);
+        ewma.update(&mut histograms);
+        assert_eq!(histograms["/api"].ewma_p95_ms, Some(565.0));
+
+        ewma.update(&mut HashMap::new());
+        let mut histograms = interval(&[]);
+        ewma.update(&mut histograms);
+        assert_eq!(histograms["/api"].ewma_p95_ms, None);
+    }
+}
@@ -1,8 +1,17 @@
 use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::time::Duration;
 
 use chrono::{DateTime, Local, Utc};
-use prettytable::{format, Cell, Row, Table};
+use hyper::Method;
+use prettytable::{color, format, Attr, Cell, Row, Table};
+
+use crate::config::SortBy;
+use crate::state::Config;
+use crate::statistics::{HourlyCounts, PhaseTimes, QueueWaits, RequestSizeHistogram};
+
+/// Upper bounds in milliseconds of every bucket but the open-ended `1000ms+` one
+pub const BUCKET_BOUNDS_MS: [u64; 5] = [10, 100, 250, 500, 1000];
 
 #[derive(Debug, Default, Clone)]
 pub struct Histogram {
@@ -13,24 +22,265 @@ pub struct Histogram {
     pub count_501_1000: u64,
     pub count_1000_plus: u64,
     pub total_requests: u64,
+    pub count_5xx: u64,
     pub last_request_time: Option<DateTime<Utc>>,
+    /// Incremented every time the counts are reset, so readers can tell a reset apart from a
+    /// histogram that simply received no new requests
+    pub generation: u64,
+    pub request_sizes: RequestSizeHistogram,
+    pub methods: MethodCounts,
+    /// Only recorded with `--track-hourly`
+    pub hourly: HourlyCounts,
+    pub queue_waits: QueueWaits,
+    /// Only recorded with `--profile`
+    pub phases: PhaseTimes,
+    /// Only set on the histograms of a report with `--ewma-alpha`, see `LatencyEwma`
+    pub ewma_p95_ms: Option<f64>,
+}
+
+/// Number of requests per HTTP method
+#[derive(Debug, Default, Clone)]
+pub struct MethodCounts {
+    pub get: u64,
+    pub post: u64,
+    pub put: u64,
+    pub delete: u64,
+    pub other: u64,
+}
+
+impl MethodCounts {
+    /// Record a request made with `method`
+    pub fn add(&mut self, method: &Method) {
+        let count = match *method {
+            Method::GET => &mut self.get,
+            Method::POST => &mut self.post,
+            Method::PUT => &mut self.put,
+            Method::DELETE => &mut self.delete,
+            _ => &mut self.other,
+        };
+        *count = count.saturating_add(1);
+    }
+
+    /// Add the counts of `other`
+    pub fn merge(&mut self, other: &MethodCounts) {
+        let counts = [
+            (&mut self.get, other.get),
+            (&mut self.post, other.post),
+            (&mut self.put, other.put),
+            (&mut self.delete, other.delete),
+            (&mut self.other, other.other),
+        ];
+        for (count, other) in counts {
+            *count = count.saturating_add(other);
+        }
+    }
+
+    /// A compact summary like `G:120 P:5`, leaving out methods without requests
+    pub fn summary(&self) -> String {
+        [("G", self.get), ("P", self.post), ("U", self.put), ("D", self.delete), ("O", self.other)]
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(method, count)| format!("{}:{}", method, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
 
 impl Histogram {
+    /// Record a request. Counters saturate at `u64::MAX` instead of wrapping around.
     pub fn add(&mut self, duration: Duration, timestamp: DateTime<Utc>) {
         let ms = duration.as_millis();
-        match ms {
-            0..=10 => self.count_0_10 += 1,
-            11..=100 => self.count_11_100 += 1,
-            101..=250 => self.count_101_250 += 1,
-            251..=500 => self.count_251_500 += 1,
-            501..=1000 => self.count_501_1000 += 1,
-            _ => self.count_1000_plus += 1,
-        }
+        let bucket = match ms {
+            0..=10 => &mut self.count_0_10,
+            11..=100 => &mut self.count_11_100,
+            101..=250 => &mut self.count_101_250,
+            251..=500 => &mut self.count_251_500,
+            501..=1000 => &mut self.count_501_1000,
+            _ => &mut self.count_1000_plus,
+        };
+        *bucket = bucket.saturating_add(1);
 
-        self.total_requests += 1;
+        self.total_requests = self.total_requests.saturating_add(1);
         self.last_request_time = Some(timestamp);
     }
+
+    /// Record the response status of a request
+    pub fn add_status(&mut self, status: u16) {
+        if (500..600).contains(&status) {
+            self.count_5xx = self.count_5xx.saturating_add(1);
+        }
+    }
+
+    /// The bucket counts in the order of `BUCKET_BOUNDS_MS`, followed by the `1000ms+` bucket
+    pub fn buckets(&self) -> [u64; 6] {
+        [
+            self.count_0_10,
+            self.count_11_100,
+            self.count_101_250,
+            self.count_251_500,
+            self.count_501_1000,
+            self.count_1000_plus,
+        ]
+    }
+
+    /// Estimate the `p`th percentile latency in milliseconds as the upper bound of the bucket it
+    /// falls in, or infinity for the open-ended top bucket. `None` when nothing was recorded.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let buckets = self.buckets();
+        let total: u64 = buckets.iter().sum();
+        if total == 0 {
+            return None;
+        }
+
+        // Fractions like 99.9 aren't exact in binary, so allow for rounding before `ceil` bumps an
+        // exact rank such as 999 of 1000 to the next one
+        let rank = (p * total as f64 / 100.0 - 1e-9).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(BUCKET_BOUNDS_MS.get(i).map_or(f64::INFINITY, |b| *b as f64));
+            }
+        }
+
+        Some(f64::INFINITY)
+    }
+
+    /// The share of requests answered with a 5xx status
+    pub fn error_ratio(&self) -> f64 {
+        if self.total_requests == 0 {
+            0.0
+        } else {
+            self.count_5xx as f64 / self.total_requests as f64
+        }
+    }
+
+    /// Add the counts of `other`, e.g. the same endpoint's histogram from another instance. The
+    /// later of the two last request times is kept, the generation is left alone.
+    pub fn merge(&mut self, other: &Histogram) {
+        let counts = [
+            (&mut self.count_0_10, other.count_0_10),
+            (&mut self.count_11_100, other.count_11_100),
+            (&mut self.count_101_250, other.count_101_250),
+            (&mut self.count_251_500, other.count_251_500),
+            (&mut self.count_501_1000, other.count_501_1000),
+            (&mut self.count_1000_plus, other.count_1000_plus),
+            (&mut self.total_requests, other.total_requests),
+            (&mut self.count_5xx, other.count_5xx),
+        ];
+        for (count, other) in counts {
+            *count = count.saturating_add(other);
+        }
+
+        self.last_request_time = self.last_request_time.max(other.last_request_time);
+        self.request_sizes.merge(&other.request_sizes);
+        self.methods.merge(&other.methods);
+        self.hourly.merge(&other.hourly);
+        self.queue_waits.merge(&other.queue_waits);
+        self.phases.merge(&other.phases);
+    }
+
+    /// Clear the counts and start a new generation, keeping the last request time
+    pub fn reset(&mut self) {
+        *self = Histogram {
+            last_request_time: self.last_request_time,
+            generation: self.generation.wrapping_add(1),
+            ..Default::default()
+        };
+    }
+}
+
+/// Merge every histogram of `other` into the one for the same endpoint in `histograms`, adding
+/// endpoints that are not tracked yet
+#[allow(dead_code)]
+pub fn merge_histograms(
+    histograms: &mut HashMap<String, Histogram>,
+    other: &HashMap<String, Histogram>,
+) {
+    for (endpoint, hist) in other {
+        histograms.entry(endpoint.clone()).or_default().merge(hist);
+    }
+}
+
+/// What the keys of the `--mirror-to` target's histograms start with, they share the map with
+/// the target server's but are counted and shown apart
+pub const MIRROR_PREFIX: &str = "mirror ";
+
+/// The key to record `endpoint` under, folding new endpoints into "Other" once `max_endpoints`
+/// distinct endpoints are tracked (0 disables the limit). Mirror endpoints have a budget of their
+/// own and fold into "mirror Other".
+pub fn capped_endpoint(
+    histograms: &HashMap<String, Histogram>,
+    endpoint: String,
+    max_endpoints: usize,
+) -> String {
+    if max_endpoints == 0 || histograms.contains_key(&endpoint) {
+        return endpoint;
+    }
+
+    let mirrored = endpoint.starts_with(MIRROR_PREFIX);
+    let prefix = if mirrored { MIRROR_PREFIX } else { "" };
+    let tracked = histograms
+        .keys()
+        .filter(|k| k.starts_with(MIRROR_PREFIX) == mirrored)
+        .filter(|k| k[prefix.len()..] != *"Overall" && k[prefix.len()..] != *"Other")
+        .count();
+    if tracked < max_endpoints {
+        endpoint
+    } else {
+        format!("{}Other", prefix)
+    }
+}
+
+/// How `print_histograms` renders the table
+#[derive(Debug, Clone, Default)]
+pub struct TableOptions {
+    /// Highlight rows over a threshold in red
+    pub color: bool,
+
+    /// Rows whose estimated p95 latency in milliseconds exceeds this are highlighted
+    pub highlight_p95_ms: f64,
+
+    /// Rows whose share of 5xx responses exceeds this are highlighted
+    pub highlight_error_ratio: f64,
+
+    /// Add a column with the number of requests per method
+    pub show_methods: bool,
+
+    /// Add a column with the estimated latency of each of these percentiles
+    pub percentiles: Vec<f64>,
+
+    /// Add a column with the smoothed p95 latency
+    pub show_ewma: bool,
+
+    /// Order of the endpoint rows, the busiest first when only `top_n` is given
+    pub sort_by: Option<SortBy>,
+
+    /// Show only this many endpoint rows and an "Other" row for the rest
+    pub top_n: Option<usize>,
+}
+
+impl From<&Config> for TableOptions {
+    fn from(config: &Config) -> Self {
+        TableOptions {
+            color: !config.no_color && std::io::stdout().is_terminal(),
+            highlight_p95_ms: config.highlight_p95_ms,
+            highlight_error_ratio: config.highlight_error_ratio,
+            show_methods: config.show_methods,
+            percentiles: config.percentiles.clone(),
+            show_ewma: config.ewma_alpha.is_some(),
+            sort_by: config.sort_by,
+            top_n: config.top_n,
+        }
+    }
+}
+
+impl TableOptions {
+    fn highlights(&self, hist: &Histogram) -> bool {
+        self.color
+            && (hist.percentile(95.0).is_some_and(|p95| p95 > self.highlight_p95_ms)
+                || hist.error_ratio() > self.highlight_error_ratio)
+    }
 }
 
 pub fn add_histogram_row(table: &mut Table, endpoint: &str, hist: &Histogram) {
@@ -52,13 +302,95 @@ pub fn add_histogram_row(table: &mut Table, endpoint: &str, hist: &Histogram) {
     ]));
 }
 
-pub fn print_histograms(histograms: &HashMap<String, Histogram>) -> String {
+fn add_ewma_cell(table: &mut Table, hist: &Histogram) {
+    if let Some(row) = table.get_mut_row(table.len() - 1) {
+        let ewma = hist.ewma_p95_ms.map_or("-".to_string(), |ms| format!("{:.1}ms", ms));
+        row.add_cell(Cell::new(&ewma));
+    }
+}
+
+fn add_methods_cell(table: &mut Table, hist: &Histogram) {
+    if let Some(row) = table.get_mut_row(table.len() - 1) {
+        row.add_cell(Cell::new(&hist.methods.summary()));
+    }
+}
+
+/// A percentile estimate for the table, the open-ended top bucket only gives a lower bound
+pub fn format_percentile(estimate: Option<f64>) -> String {
+    match estimate {
+        None => "-".to_string(),
+        Some(ms) if ms.is_infinite() => {
+            format!("{}ms+", BUCKET_BOUNDS_MS[BUCKET_BOUNDS_MS.len() - 1])
+        }
+        Some(ms) => format!("{}ms", ms),
+    }
+}
+
+fn add_percentile_cells(table: &mut Table, hist: &Histogram, percentiles: &[f64]) {
+    if let Some(row) = table.get_mut_row(table.len() - 1) {
+        for p in percentiles {
+            row.add_cell(Cell::new(&format_percentile(hist.percentile(*p))));
+        }
+    }
+}
+
+fn highlight_last_row(table: &mut Table) {
+    if let Some(row) = table.get_mut_row(table.len() - 1) {
+        for cell in row.iter_mut() {
+            cell.style(Attr::ForegroundColor(color::RED));
+        }
+    }
+}
+
+/// The endpoint rows of the table besides "Overall", in `sort_by` order. With `top_n` only that
+/// many are kept and the rest are summed up for an "Other" row, along with the endpoints already
+/// folded into "Other" by `--max-endpoints`.
+fn endpoint_rows<'a>(
+    histograms: &'a HashMap<String, Histogram>,
+    options: &TableOptions,
+) -> (Vec<(&'a str, &'a Histogram)>, Option<Histogram>) {
+    // Endpoints kept around from a previous interval have nothing to show, and the mirror has its
+    // own comparison table
+    let mut rows = histograms
+        .iter()
+        .filter(|(endpoint, hist)| *endpoint != "Overall" && hist.total_requests > 0)
+        .filter(|(endpoint, _)| !endpoint.starts_with(MIRROR_PREFIX))
+        .map(|(endpoint, hist)| (endpoint.as_str(), hist))
+        .collect::<Vec<_>>();
+
+    let busiest = |a: &(&str, &Histogram), b: &(&str, &Histogram)| {
+        b.1.total_requests.cmp(&a.1.total_requests).then(a.0.cmp(b.0))
+    };
+    match options.sort_by.or(options.top_n.map(|_| SortBy::Requests)) {
+        Some(SortBy::Requests) => rows.sort_by(busiest),
+        Some(SortBy::P95) => rows.sort_by(|a, b| {
+            let p95 = |hist: &Histogram| hist.percentile(95.0).unwrap_or_default();
+            p95(b.1).total_cmp(&p95(a.1)).then(busiest(a, b))
+        }),
+        None => {}
+    }
+
+    let Some(top_n) = options.top_n else {
+        return (rows, None);
+    };
+    let (folded, mut rows): (Vec<_>, Vec<_>) =
+        rows.into_iter().partition(|(endpoint, _)| *endpoint == "Other");
+    let rest = rows.split_off(top_n.min(rows.len()));
+    let mut other = None;
+    for (_, hist) in folded.into_iter().chain(rest) {
+        other.get_or_insert_with(Histogram::default).merge(hist);
+    }
+
+    (rows, other)
+}
+
+pub fn print_histograms(histograms: &HashMap<String, Histogram>, options: &TableOptions) -> String {
     // Print a newline before the histogram
     println!("\nResponse Time Histogram:");
 
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-    table.set_titles(Row::new(vec![
+    let mut titles = Row::new(vec![
         Cell::new("Endpoint"),
         Cell::new("0-10ms"),
         Cell::new("11-100ms"),
@@ -68,19 +400,45 @@ pub fn print_histograms(histograms: &HashMap<String, Histogram>) -> String {
         Cell::new("1000ms+"),
         Cell::new("Total"),
         Cell::new("Last Request"),
-    ]));
+    ]);
+    for p in &options.percentiles {
+        titles.add_cell(Cell::new(&format!("p{}", p)));
+    }
+    if options.show_ewma {
+        titles.add_cell(Cell::new("EWMA p95"));
+    }
+    if options.show_methods {
+        titles.add_cell(Cell::new("Methods"));
+    }
+    table.set_titles(titles);
+
+    let add_row = |table: &mut Table, endpoint: &str, hist: &Histogram| {
+        add_histogram_row(table, endpoint, hist);
+        add_percentile_cells(table, hist, &options.percentiles);
+        if options.show_ewma {
+            add_ewma_cell(table, hist);
+        }
+        if options.show_methods {
+            add_methods_cell(table, hist);
+        }
+        if options.highlights(hist) {
+            highlight_last_row(table);
+        }
+    };
 
     if histograms.is_empty() || (histograms.len() == 1 && histograms.contains_key("Overall")) {
-        add_histogram_row(&mut table, "Overall", &Histogram::default());
+        add_row(&mut table, "Overall", &Histogram::default());
     } else {
         if let Some(overall_hist) = histograms.get("Overall") {
-            add_histogram_row(&mut table, "Overall", overall_hist);
+            add_row(&mut table, "Overall", overall_hist);
         }
 
-        for (endpoint, hist) in histograms.iter() {
-            if endpoint != "Overall" {
-                add_histogram_row(&mut table, endpoint, hist);
-            }
+        let (rows, other) = endpoint_rows(histograms, options);
+        for (endpoint, hist) in rows {
+            add_row(&mut table, endpoint, hist);
+        }
+        if let Some(other) = &other {
+            add_row(&mut table, "Other", other);
         }
     }
 
@@ -120,6 +478,195 @@ mod tests {
         assert_eq!(hist.last_request_time, Some(timestamp));
     }
 
+    #[test]
+    fn test_histogram_percentile() {
+        let timestamp = Utc::now();
+        let mut hist = Histogram::default();
+        assert_eq!(hist.percentile(95.0), None);
+
+        for _ in 0..90 {
+            hist.add(Duration::from_millis(5), timestamp);
+        }
+        for _ in 0..9 {
+            hist.add(Duration::from_millis(200), timestamp);
+        }
+        hist.add(Duration::from_millis(2000), timestamp);
+
+        assert_eq!(hist.percentile(0.0), Some(10.0));
+        assert_eq!(hist.percentile(50.0), Some(10.0));
+        assert_eq!(hist.percentile(95.0), Some(250.0));
+        assert_eq!(hist.percentile(99.0), Some(250.0));
+        assert_eq!(hist.percentile(100.0), Some(f64::INFINITY));
+
+        // Fractional percentiles reach further into the tail
+        assert_eq!(hist.percentile(99.0), Some(250.0));
+        assert_eq!(hist.percentile(99.9), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_format_percentile() {
+        assert_eq!(format_percentile(None), "-");
+        assert_eq!(format_percentile(Some(250.0)), "250ms");
+        assert_eq!(format_percentile(Some(f64::INFINITY)), "1000ms+");
+    }
+
+    #[test]
+    fn test_histogram_error_ratio() {
+        let mut hist = Histogram::default();
+        assert_eq!(hist.error_ratio(), 0.0);
+
+        for status in [200, 404, 500, 503] {
+            hist.add(Duration::ZERO, Utc::now());
+            hist.add_status(status);
+        }
+        assert_eq!(hist.count_5xx, 2);
+        assert_eq!(hist.error_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_method_counts() {
+        let mut methods = MethodCounts::default();
+        assert_eq!(methods.summary(), "");
+
+        for method in [Method::GET, Method::GET, Method::POST, Method::DELETE, Method::PATCH] {
+            methods.add(&method);
+        }
+        assert_eq!(methods.summary(), "G:2 P:1 D:1 O:1");
+    }
+
+    #[test]
+    fn test_table_options_highlights() {
+        let options = TableOptions {
+            color: true,
+            highlight_p95_ms: 500.0,
+            highlight_error_ratio: 0.1,
+            ..Default::default()
+        };
+
+        let mut hist = Histogram::default();
+        hist.add(Duration::from_millis(50), Utc::now());
+        hist.add_status(200);
+        assert!(!options.highlights(&hist));
+
+        hist.add(Duration::from_millis(50), Utc::now());
+        hist.add_status(502);
+        assert!(options.highlights(&hist));
+
+        let mut slow = Histogram::default();
+        slow.add(Duration::from_millis(800), Utc::now());
+        assert!(options.highlights(&slow));
+        assert!(!TableOptions { color: false, ..options }.highlights(&slow));
+    }
+
+    #[test]
+    fn test_histogram_saturates() {
+        let timestamp = Utc::now();
+        let mut hist = Histogram {
+            count_0_10: u64::MAX - 1,
+            total_requests: u64::MAX - 1,
+            ..Default::default()
+        };
+
+        hist.add(Duration::from_millis(5), timestamp);
+        assert_eq!(hist.count_0_10, u64::MAX);
+        assert_eq!(hist.total_requests, u64::MAX);
+
+        hist.add(Duration::from_millis(5), timestamp);
+        assert_eq!(hist.count_0_10, u64::MAX);
+        assert_eq!(hist.total_requests, u64::MAX);
+    }
+
+    #[test]
+    fn test_histogram_reset() {
+        let timestamp = Utc::now();
+        let mut hist = Histogram::default();
+        hist.add(Duration::from_millis(50), timestamp);
+
+        hist.reset();
+        assert_eq!(hist.count_11_100, 0);
+        assert_eq!(hist.total_requests, 0);
+        assert_eq!(hist.last_request_time, Some(timestamp));
+        assert_eq!(hist.generation, 1);
+
+        hist.generation = u64::MAX;
+        hist.reset();
+        assert_eq!(hist.generation, 0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let earlier = Utc::now() - chrono::Duration::seconds(30);
+        let later = Utc::now();
+
+        let mut hist = Histogram::default();
+        hist.add(Duration::from_millis(5), later);
+        hist.add_status(503);
+        hist.request_sizes.add(100);
+
+        let mut other = Histogram::default();
+        other.add(Duration::from_millis(5), earlier);
+        other.add(Duration::from_millis(2000), earlier);
+        other.request_sizes.add(2048);
+        other.generation = 7;
+
+        hist.merge(&other);
+        assert_eq!(hist.buckets(), [2, 0, 0, 0, 0, 1]);
+        assert_eq!(hist.total_requests, 3);
+        assert_eq!(hist.count_5xx, 1);
+        assert_eq!(hist.last_request_time, Some(later));
+        assert_eq!(hist.generation, 0);
+        assert_eq!(hist.request_sizes.buckets(), [1, 1, 0, 0, 0]);
+        assert_eq!(hist.request_sizes.total_bytes, 2148);
+
+        let mut empty = Histogram::default();
+        empty.merge(&hist);
+        assert_eq!(empty.last_request_time, Some(later));
+    }
+
+    #[test]
+    fn test_merge_histograms() {
+        let timestamp = Utc::now();
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        histograms.entry("Overall".to_string()).or_default().add(Duration::ZERO, timestamp);
+        histograms.entry("/a".to_string()).or_default().add(Duration::ZERO, timestamp);
+
+        let mut other: HashMap<String, Histogram> = HashMap::new();
+        other.entry("Overall".to_string()).or_default().add(Duration::ZERO, timestamp);
+        other.entry("/b".to_string()).or_default().add(Duration::ZERO, timestamp);
+
+        merge_histograms(&mut histograms, &other);
+        assert_eq!(histograms.len(), 3);
+        assert_eq!(histograms["Overall"].total_requests, 2);
+        assert_eq!(histograms["/a"].total_requests, 1);
+        assert_eq!(histograms["/b"].total_requests, 1);
+    }
+
+    #[test]
+    fn test_capped_endpoint() {
+        let mut histograms = HashMap::new();
+        histograms.insert("Overall".to_string(), Histogram::default());
+        histograms.insert("/a".to_string(), Histogram::default());
+
+        assert_eq!(capped_endpoint(&histograms, "/b".to_string(), 2), "/b");
+        histograms.insert("/b".to_string(), Histogram::default());
+
+        assert_eq!(capped_endpoint(&histograms, "/c".to_string(), 2), "Other");
+        histograms.insert("Other".to_string(), Histogram::default());
+
+        assert_eq!(capped_endpoint(&histograms, "/a".to_string(), 2), "/a");
+        assert_eq!(capped_endpoint(&histograms, "/d".to_string(), 2), "Other");
+        assert_eq!(capped_endpoint(&histograms, "/d".to_string(), 0), "/d");
+
+        // The mirror's endpoints don't take from the target server's budget, nor it from theirs
+        histograms.insert("mirror Overall".to_string(), Histogram::default());
+        assert_eq!(capped_endpoint(&histograms, "mirror /a".to_string(), 2), "mirror /a");
+        histograms.insert("mirror /a".to_string(), Histogram::default());
+        histograms.insert("mirror /b".to_string(), Histogram::default());
+        assert_eq!(capped_endpoint(&histograms, "mirror /c".to_string(), 2), "mirror Other");
+        histograms.remove("/b");
+        assert_eq!(capped_endpoint(&histograms, "/c".to_string(), 2), "/c");
+    }
+
     #[test]
     fn test_add_histogram_row() {
         let mut table = Table::new();
@@ -144,7 +691,15 @@ mod tests {
             count_501_1000: 5,
             count_1000_plus: 6,
             total_requests: 21,
+            count_5xx: 0,
             last_request_time: Some(Utc::now()),
+            generation: 0,
+            request_sizes: RequestSizeHistogram::default(),
+            methods: MethodCounts::default(),
+            hourly: HourlyCounts::default(),
+            queue_waits: QueueWaits::default(),
+            phases: PhaseTimes::default(),
+            ewma_p95_ms: None,
         };
 
         add_histogram_row(&mut table, "test", &hist);
@@ -174,13 +729,21 @@ mod tests {
                 count_501_1000: 5,
                 count_1000_plus: 6,
                 total_requests: 21,
+                count_5xx: 0,
                 last_request_time: None,
+                generation: 0,
+                request_sizes: RequestSizeHistogram::default(),
+                methods: MethodCounts::default(),
+                hourly: HourlyCounts::default(),
+                queue_waits: QueueWaits::default(),
+                phases: PhaseTimes::default(),
+                ewma_p95_ms: None,
             },
         );
 
-        let table = print_histograms(&histograms);
+        let table = print_histograms(&histograms, &TableOptions::default());
 
-        let expected = vec![
+        let expected = [
             vec![
                 "Endpoint",
                 "0-10ms",
@@ -206,7 +769,7 @@ mod tests {
 
             let cells = row
                 .split_whitespace()
-                .filter(|c| c.to_string() != "|")
+                .filter(|c| *c != "|")
                 .map(|c| c.to_string())
                 .collect::<Vec<_>>();
 
@@ -214,4 +777,103 @@ mod tests {
             i += 1;
         }
     }
+
+    #[test]
+    fn test_print_histograms_shows_methods() {
+        let mut hist = Histogram::default();
+        hist.add(Duration::ZERO, Utc::now());
+        hist.methods.add(&Method::GET);
+        hist.methods.add(&Method::PUT);
+        let histograms = HashMap::from([("/api".to_string(), hist)]);
+
+        let options = TableOptions { show_methods: true, ..Default::default() };
+        let table = print_histograms(&histograms, &options);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert!(lines[0].trim_end().ends_with("Methods"));
+        assert!(lines[2].trim_end().ends_with("G:1 U:1"));
+
+        assert!(!print_histograms(&histograms, &TableOptions::default()).contains("Methods"));
+    }
+
+    #[test]
+    fn test_print_histograms_shows_ewma() {
+        let mut hist = Histogram::default();
+        hist.add(Duration::ZERO, Utc::now());
+        hist.ewma_p95_ms = Some(42.25);
+        let mut idle = Histogram::default();
+        idle.add(Duration::ZERO, Utc::now());
+        let histograms = HashMap::from([("/api".to_string(), hist), ("/new".to_string(), idle)]);
+
+        let options = TableOptions { show_ewma: true, ..Default::default() };
+        let table = print_histograms(&histograms, &options);
+        assert!(table.lines().next().unwrap().trim_end().ends_with("EWMA p95"));
+        let row = |endpoint: &str| {
+            let line = table.lines().find(|line| line.trim_start().starts_with(endpoint)).unwrap();
+            line.split_whitespace().last().unwrap().to_string()
+        };
+        assert_eq!(row("/api"), "42.2ms");
+        assert_eq!(row("/new"), "-");
+    }
+
+    #[test]
+    fn test_print_histograms_top_n() {
+        let mut histograms = HashMap::new();
+        for (endpoint, latencies_ms) in [
+            ("/busy", &[5, 5, 5, 5][..]),
+            ("/slow", &[2000]),
+            ("/medium", &[200, 200]),
+            ("/rare", &[50]),
+            ("Other", &[5]),
+            ("mirror /busy", &[5, 5, 5, 5, 5]),
+        ] {
+            let hist = histograms.entry(endpoint.to_string()).or_insert_with(Histogram::default);
+            for ms in latencies_ms {
+                hist.add(Duration::from_millis(*ms), Utc::now());
+            }
+        }
+        let rows = |options: &TableOptions| {
+            let table = print_histograms(&histograms, options);
+            table
+                .lines()
+                .skip(2)
+                .map(|line| {
+                    let cells = line.split_whitespace().filter(|c| *c != "|").collect::<Vec<_>>();
+                    format!("{} {}", cells[0], cells[7])
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let options = TableOptions { top_n: Some(2), ..Default::default() };
+        assert_eq!(rows(&options), ["/busy 4", "/medium 2", "Other 3"]);
+        let options = TableOptions { top_n: Some(1), sort_by: Some(SortBy::P95), ..options };
+        assert_eq!(rows(&options), ["/slow 1", "Other 8"]);
+        let options = TableOptions { top_n: None, ..options };
+        assert_eq!(rows(&options), ["/slow 1", "/medium 2", "/rare 1", "/busy 4", "Other 1"]);
+        let options = TableOptions { top_n: Some(10), sort_by: None, ..options };
+        assert_eq!(rows(&options), ["/busy 4", "/medium 2", "/rare 1", "/slow 1", "Other 1"]);
+    }
+
+    #[test]
+    fn test_print_histograms_shows_percentiles() {
+        let mut hist = Histogram::default();
+        for _ in 0..999 {
+            hist.add(Duration::from_millis(5), Utc::now());
+        }
+        hist.add(Duration::from_millis(2000), Utc::now());
+        let histograms = HashMap::from([("/api".to_string(), hist)]);
+
+        let options = TableOptions { percentiles: vec![50.0, 99.9, 100.0], ..Default::default() };
+        let table = print_histograms(&histograms, &options);
+        let lines = table.lines().collect::<Vec<_>>();
+        let cells = |line: &str| {
+            line.split_whitespace()
+                .rev()
+                .filter(|c| *c != "|")
+                .take(3)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(cells(lines[0]), ["p100", "p99.9", "p50"]);
+        assert_eq!(cells(lines[2]), ["1000ms+", "10ms", "10ms"]);
+    }
 }
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, Timelike, Utc};
+use prettytable::{format, Cell, Row, Table};
+
+use crate::statistics::Histogram;
+
+/// Number of requests per local hour of the day, only allocated once `--track-hourly` records
+/// the first request
+#[derive(Debug, Default, Clone)]
+pub struct HourlyCounts {
+    counts: Option<Box<[u64; 24]>>,
+}
+
+impl HourlyCounts {
+    /// Record a request received at `timestamp`, in the hour of the proxy's local time zone
+    pub fn add(&mut self, timestamp: DateTime<Utc>) {
+        let hour = timestamp.with_timezone(&Local).hour() as usize;
+        let counts = self.counts.get_or_insert_with(|| Box::new([0; 24]));
+        counts[hour] = counts[hour].saturating_add(1);
+    }
+
+    /// Add the counts of `other`
+    pub fn merge(&mut self, other: &HourlyCounts) {
+        if let Some(other) = &other.counts {
+            let counts = self.counts.get_or_insert_with(|| Box::new([0; 24]));
+            for (count, other) in counts.iter_mut().zip(other.iter()) {
+                *count = count.saturating_add(*other);
+            }
+        }
+    }
+
+    /// The counts from midnight on, `None` if nothing was recorded
+    pub fn counts(&self) -> Option<&[u64; 24]> {
+        self.counts.as_deref()
+    }
+}
+
+pub fn add_hourly_row(table: &mut Table, endpoint: &str, hourly: &HourlyCounts) {
+    let mut cells = vec![Cell::new(endpoint)];
+    let counts = hourly.counts().copied().unwrap_or_default();
+    cells.extend(counts.iter().map(|count| Cell::new(&count.to_string())));
+
+    table.add_row(Row::new(cells));
+}
+
+pub fn print_hourly(histograms: &HashMap<String, Histogram>) -> String {
+    println!("Requests per Hour:");
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    let mut titles = vec![Cell::new("Endpoint")];
+    titles.extend((0..24).map(|hour| Cell::new(&format!("{:02}", hour))));
+    table.set_titles(Row::new(titles));
+
+    let overall = histograms.get("Overall").map(|hist| hist.hourly.clone());
+    add_hourly_row(&mut table, "Overall", &overall.unwrap_or_default());
+
+    for (endpoint, hist) in histograms.iter() {
+        if endpoint != "Overall" && hist.total_requests > 0 {
+            add_hourly_row(&mut table, endpoint, &hist.hourly);
+        }
+    }
+
+    table.printstd();
+    println!();
+
+    table.to_string()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_hourly_counts() {
+        let mut hourly = HourlyCounts::default();
+        assert_eq!(hourly.counts(), None);
+
+        let morning = Local.with_ymd_and_hms(2024, 3, 1, 9, 15, 0).unwrap().with_timezone(&Utc);
+        let evening = Local.with_ymd_and_hms(2024, 3, 1, 21, 59, 59).unwrap().with_timezone(&Utc);
+        hourly.add(morning);
+        hourly.add(morning);
+        hourly.add(evening);
+
+        let mut other = HourlyCounts::default();
+        other.add(evening);
+        hourly.merge(&other);
+        hourly.merge(&HourlyCounts::default());
+
+        let counts = hourly.counts().unwrap();
+        assert_eq!(counts[9], 2);
+        assert_eq!(counts[21], 2);
+        assert_eq!(counts.iter().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn test_add_hourly_row() {
+        let mut table = Table::new();
+        add_hourly_row(&mut table, "/idle", &HourlyCounts::default());
+
+        let row = table.get_row(0).unwrap().into_iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(row.len(), 25);
+        assert_eq!(row[0], "/idle");
+        assert!(row[1..].iter().all(|count| count == "0"));
+    }
+}
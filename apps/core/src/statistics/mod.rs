@@ -1,3 +1,41 @@
+mod alerts;
+#[cfg(feature = "chart")]
+mod chart;
+mod compare;
+mod connections;
+mod counters;
+mod ewma;
 mod histogram;
+mod hourly;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod phases;
+mod report;
+mod size;
+mod snapshot;
+mod statsd;
+mod syslog;
+mod talkers;
+mod upstreams;
+mod waits;
 
+pub use alerts::*;
+#[cfg(feature = "chart")]
+pub use chart::*;
+pub use compare::*;
+pub use connections::*;
+pub use counters::*;
+pub use ewma::*;
 pub use histogram::*;
+pub use hourly::*;
+#[cfg(feature = "otlp")]
+pub use otlp::*;
+pub use phases::*;
+pub use report::*;
+pub use size::*;
+pub use snapshot::*;
+pub use statsd::*;
+pub use syslog::*;
+pub use talkers::*;
+pub use upstreams::*;
+pub use waits::*;
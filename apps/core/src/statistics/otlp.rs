@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request};
+use serde_json::{json, Value};
+
+use crate::state::HttpClient;
+use crate::statistics::{Histogram, BUCKET_BOUNDS_MS, SIZE_BUCKET_BOUNDS};
+
+/// `AGGREGATION_TEMPORALITY_DELTA`, every export only covers the interval since the last one
+const AGGREGATION_TEMPORALITY_DELTA: u8 = 1;
+
+/// Build an OTLP `ExportMetricsServiceRequest` in the protobuf JSON encoding, with one histogram
+/// data point per endpoint that received requests between `start` and `end`
+pub fn otlp_metrics(
+    histograms: &HashMap<String, Histogram>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Value {
+    let nanos = |t: DateTime<Utc>| t.timestamp_nanos_opt().unwrap_or_default().to_string();
+
+    let mut endpoints = histograms.iter().filter(|(_, h)| h.total_requests > 0).collect::<Vec<_>>();
+    endpoints.sort_by(|a, b| a.0.cmp(b.0));
+
+    let data_point = |endpoint: &str, count: u64, buckets: &[u64], bounds: &[u64]| {
+        json!({
+            "attributes": [{"key": "endpoint", "value": {"stringValue": endpoint}}],
+            "startTimeUnixNano": nanos(start),
+            "timeUnixNano": nanos(end),
+            "count": count.to_string(),
+            "bucketCounts": buckets.iter().map(u64::to_string).collect::<Vec<_>>(),
+            "explicitBounds": bounds,
+        })
+    };
+
+    let durations = endpoints
+        .iter()
+        .map(|(endpoint, hist)| {
+            data_point(endpoint, hist.total_requests, &hist.buckets(), &BUCKET_BOUNDS_MS)
+        })
+        .collect::<Vec<_>>();
+    let sizes = endpoints
+        .iter()
+        .map(|(endpoint, hist)| {
+            let mut point = data_point(
+                endpoint,
+                hist.total_requests,
+                &hist.request_sizes.buckets(),
+                &SIZE_BUCKET_BOUNDS,
+            );
+            point["sum"] = json!(hist.request_sizes.total_bytes);
+            point
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": "narrow"}}],
+            },
+            "scopeMetrics": [{
+                "scope": {"name": "narrow", "version": env!("CARGO_PKG_VERSION")},
+                "metrics": [{
+                    "name": "narrow.request.duration",
+                    "description": "Response time of requests forwarded to the target server",
+                    "unit": "ms",
+                    "histogram": {
+                        "aggregationTemporality": AGGREGATION_TEMPORALITY_DELTA,
+                        "dataPoints": durations,
+                    },
+                }, {
+                    "name": "narrow.request.size",
+                    "description": "Body size of requests forwarded to the target server",
+                    "unit": "By",
+                    "histogram": {
+                        "aggregationTemporality": AGGREGATION_TEMPORALITY_DELTA,
+                        "dataPoints": sizes,
+                    },
+                }],
+            }],
+        }],
+    })
+}
+
+/// POST `metrics` to an OTLP/HTTP collector endpoint such as `http://collector:4318/v1/metrics`
+pub async fn push_otlp(client: &HttpClient, endpoint: &str, metrics: &Value) -> Result<(), String> {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(metrics.to_string()))
+        .map_err(|e| format!("invalid OTLP endpoint {}: {}", endpoint, e))?;
+
+    let resp =
+        client.request(req).await.map_err(|e| format!("could not reach {}: {}", endpoint, e))?;
+    if !resp.status().is_success() {
+        return Err(format!("{} returned {}", endpoint, resp.status()));
+    }
+
+    Ok(())
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use hyper::{Client, StatusCode};
+
+    use super::*;
+    use crate::net::client::CountingConnector;
+    use crate::net::mock::MockUpstream;
+
+    #[test]
+    fn test_otlp_metrics() {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let end = DateTime::from_timestamp(1_700_000_060, 0).unwrap();
+
+        let mut histograms = HashMap::new();
+        let api = histograms.entry("/api".to_string()).or_insert_with(Histogram::default);
+        api.add(Duration::from_millis(120), end);
+        api.request_sizes.add(2048);
+        histograms.insert("/idle".to_string(), Histogram::default());
+
+        let metrics = otlp_metrics(&histograms, start, end);
+        let metric = &metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0];
+        assert_eq!(metric["name"], "narrow.request.duration");
+
+        let points = metric["histogram"]["dataPoints"].as_array().unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0]["attributes"][0]["value"]["stringValue"], "/api");
+        assert_eq!(points[0]["startTimeUnixNano"], "1700000000000000000");
+        assert_eq!(points[0]["timeUnixNano"], "1700000060000000000");
+        assert_eq!(points[0]["count"], "1");
+        assert_eq!(points[0]["bucketCounts"], json!(["0", "0", "1", "0", "0", "0"]));
+        assert_eq!(points[0]["explicitBounds"], json!([10, 100, 250, 500, 1000]));
+
+        let sizes = &metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][1];
+        assert_eq!(sizes["name"], "narrow.request.size");
+        let points = sizes["histogram"]["dataPoints"].as_array().unwrap();
+        assert_eq!(points[0]["bucketCounts"], json!(["0", "1", "0", "0", "0"]));
+        assert_eq!(points[0]["explicitBounds"], json!([1024, 10240, 102400, 1048576]));
+        assert_eq!(points[0]["sum"], 2048);
+    }
+
+    #[tokio::test]
+    async fn test_push_otlp() {
+        let collector = MockUpstream::start(StatusCode::OK, "", Duration::ZERO).await;
+        let endpoint = format!("http://{}/v1/metrics", collector.addr);
+        let metrics = otlp_metrics(&HashMap::new(), Utc::now(), Utc::now());
+
+        push_otlp(&Client::builder().build(CountingConnector::default()), &endpoint, &metrics)
+            .await
+            .unwrap();
+
+        let received = collector.requests();
+        assert_eq!(received[0].uri, "/v1/metrics");
+        assert_eq!(received[0].headers["content-type"], "application/json");
+        assert_eq!(serde_json::from_slice::<Value>(&received[0].body).unwrap(), metrics);
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::statistics::Histogram;
+
+/// Where the time of requests went with `--profile`: preparing the request inside the proxy,
+/// connecting to the target server, waiting for its response head and streaming the body to the
+/// client. Queue waits are left out, see `QueueWaits`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PhaseTimes {
+    pub requests: u64,
+    pub prepare_micros: u64,
+    pub connect_micros: u64,
+    pub upstream_micros: u64,
+
+    /// Bodies are counted on their own, they finish streaming after their request was recorded
+    pub bodies: u64,
+    pub body_micros: u64,
+}
+
+impl PhaseTimes {
+    /// Record the phases of a request up to its response head. `connect` is zero for requests
+    /// that reused a connection.
+    pub fn add(&mut self, prepare: Duration, connect: Duration, upstream: Duration) {
+        self.requests = self.requests.saturating_add(1);
+        self.prepare_micros = self.prepare_micros.saturating_add(prepare.as_micros() as u64);
+        self.connect_micros = self.connect_micros.saturating_add(connect.as_micros() as u64);
+        self.upstream_micros = self.upstream_micros.saturating_add(upstream.as_micros() as u64);
+    }
+
+    /// Record a response body that took `streamed` from the response head to its end
+    pub fn add_body(&mut self, streamed: Duration) {
+        self.bodies = self.bodies.saturating_add(1);
+        self.body_micros = self.body_micros.saturating_add(streamed.as_micros() as u64);
+    }
+
+    /// Add the phases of `other`
+    pub fn merge(&mut self, other: &PhaseTimes) {
+        self.requests = self.requests.saturating_add(other.requests);
+        self.prepare_micros = self.prepare_micros.saturating_add(other.prepare_micros);
+        self.connect_micros = self.connect_micros.saturating_add(other.connect_micros);
+        self.upstream_micros = self.upstream_micros.saturating_add(other.upstream_micros);
+        self.bodies = self.bodies.saturating_add(other.bodies);
+        self.body_micros = self.body_micros.saturating_add(other.body_micros);
+    }
+
+    /// The average `(prepare, connect, upstream, body)` times
+    pub fn averages(&self) -> (Duration, Duration, Duration, Duration) {
+        let average = |micros: u64, count: u64| {
+            Duration::from_micros(micros.checked_div(count).unwrap_or_default())
+        };
+
+        (
+            average(self.prepare_micros, self.requests),
+            average(self.connect_micros, self.requests),
+            average(self.upstream_micros, self.requests),
+            average(self.body_micros, self.bodies),
+        )
+    }
+}
+
+pub fn print_phase_times(histograms: &HashMap<String, Histogram>) -> String {
+    let mut phases = histograms
+        .iter()
+        .filter(|(_, hist)| hist.phases.requests > 0 || hist.phases.bodies > 0)
+        .map(|(endpoint, hist)| (endpoint, &hist.phases))
+        .collect::<Vec<_>>();
+    if phases.is_empty() {
+        return String::new();
+    }
+    // Overall first, then the busiest endpoints
+    phases.sort_by_key(|(endpoint, phases)| {
+        (endpoint.as_str() != "Overall", std::cmp::Reverse(phases.requests), endpoint.as_str())
+    });
+
+    let line = phases
+        .iter()
+        .map(|(endpoint, phases)| {
+            let (prepare, connect, upstream, body) = phases.averages();
+            format!(
+                "{}: {} requests, prepare {:?}, connect {:?}, upstream {:?}, body {:?} average",
+                endpoint, phases.requests, prepare, connect, upstream, body
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!("Phases - {}\n", line);
+
+    line
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_phase_times() {
+        let ms = Duration::from_millis;
+        let mut phases = PhaseTimes::default();
+        assert_eq!(phases.averages(), (ms(0), ms(0), ms(0), ms(0)));
+        phases.add(ms(1), ms(4), ms(10));
+        phases.add(ms(3), ms(0), ms(20));
+        phases.add_body(ms(5));
+        assert_eq!(phases.averages(), (ms(2), ms(2), ms(15), ms(5)));
+
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        assert_eq!(print_phase_times(&histograms), "");
+
+        histograms.entry("/items".to_string()).or_default().phases = phases.clone();
+        let overall = &mut histograms.entry("Overall".to_string()).or_default().phases;
+        overall.merge(&phases);
+        overall.add(ms(2), ms(0), ms(3));
+        histograms.insert("/idle".to_string(), Histogram::default());
+
+        assert_eq!(
+            print_phase_times(&histograms),
+            "Overall: 3 requests, prepare 2ms, connect 1.333ms, upstream 11ms, body 5ms average, \
+             /items: 2 requests, prepare 2ms, connect 2ms, upstream 15ms, body 5ms average"
+        );
+    }
+}
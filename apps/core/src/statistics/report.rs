@@ -0,0 +1,493 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+#[cfg(feature = "otlp")]
+use chrono::DateTime;
+use chrono::Utc;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::state::{
+    Config, HistogramMap, HttpClient, LogList, SharedConnections, SharedCounters, SharedTalkers, SharedUpstreams
+};
+use crate::statistics::{
+    post_alerts, print_connections, print_counters, print_histograms, print_hourly, print_mirror_comparison, print_phase_times, print_queue_waits, print_request_sizes, print_top_talkers, print_upstreams, send_statsd, send_syslog, statsd_lines, syslog_hostname, syslog_message, Alert, AlertDebouncer, LatencyEwma, StatsSnapshot, TableOptions
+};
+
+/// Reports the statistics of every interval to the configured sinks, and of the partial interval
+/// left over when shutting down
+pub struct Reporter {
+    histograms: HistogramMap,
+    loglist: LogList,
+    counters: SharedCounters,
+    connections: SharedConnections,
+    upstreams: SharedUpstreams,
+    talkers: SharedTalkers,
+    table_options: TableOptions,
+    config: Arc<Config>,
+    client: HttpClient,
+    /// The last interval, kept for `--regression-pct`
+    previous: Mutex<Option<StatsSnapshot>>,
+    alerts: AlertDebouncer,
+    /// Only with `--ewma-alpha`
+    ewma: Option<Mutex<LatencyEwma>>,
+    #[cfg(feature = "otlp")]
+    interval_start: Mutex<DateTime<Utc>>,
+}
+
+impl Reporter {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        histograms: HistogramMap,
+        loglist: LogList,
+        counters: SharedCounters,
+        connections: SharedConnections,
+        upstreams: SharedUpstreams,
+        talkers: SharedTalkers,
+        config: Arc<Config>,
+        client: HttpClient,
+    ) -> Self {
+        Reporter {
+            histograms,
+            loglist,
+            counters,
+            connections,
+            upstreams,
+            talkers,
+            table_options: TableOptions::from(config.as_ref()),
+            ewma: config.ewma_alpha.map(|alpha| Mutex::new(LatencyEwma::new(alpha))),
+            config,
+            client,
+            previous: Mutex::new(None),
+            alerts: AlertDebouncer::default(),
+            #[cfg(feature = "otlp")]
+            interval_start: Mutex::new(Utc::now()),
+        }
+    }
+
+    /// Report and reset everything recorded since the last report. Exports to remote sinks run in
+    /// the background, the returned handle finishes once they are done.
+    pub fn report(&self) -> (StatsSnapshot, JoinHandle<()>) {
+        let ttl = self.config.endpoint_ttl.map(Duration::from_secs);
+        let mut snapshot = StatsSnapshot::take(
+            &mut self.histograms.lock().unwrap_or_else(PoisonError::into_inner),
+            ttl,
+        );
+        if let Some(ewma) = &self.ewma {
+            ewma.lock().unwrap().update(&mut snapshot.histograms);
+        }
+        print_histograms(&snapshot.histograms, &self.table_options);
+        print_request_sizes(&snapshot.histograms);
+        print_mirror_comparison(&snapshot.histograms, &self.config.percentiles);
+        if self.config.track_hourly {
+            print_hourly(&snapshot.histograms);
+        }
+        print_queue_waits(&snapshot.histograms);
+        if self.config.profile {
+            print_phase_times(&snapshot.histograms);
+        }
+        print_counters(&self.counters.take());
+        print_connections(&self.connections.take());
+        print_upstreams(&self.upstreams.take());
+        let talkers = self.talkers.as_ref().map(|talkers| talkers.take());
+        if let Some(talkers) = &talkers {
+            print_top_talkers(talkers);
+        }
+        let mut alerts = self.warn_regressions(&snapshot);
+        alerts.extend(self.warn_success_rates(&snapshot));
+        let alerts = self
+            .config
+            .alert_webhook
+            .clone()
+            .map(|url| (self.client.clone(), url, self.alerts.fresh(alerts)));
+
+        let stats_stream = self.config.stats_stream.clone().and_then(|path| {
+            let mut record = snapshot.to_json(Utc::now(), &self.config.percentiles);
+            if let Some(talkers) = &talkers {
+                record["top_talkers"] = talkers.to_json();
+            }
+            let line = record.to_string() + "\n";
+            if path.as_os_str() == "-" {
+                print!("{}", line);
+                None
+            } else {
+                Some((path, line))
+            }
+        });
+
+        #[cfg(feature = "otlp")]
+        let otlp = self.config.otlp_endpoint.clone().map(|endpoint| {
+            let now = Utc::now();
+            let start = std::mem::replace(&mut *self.interval_start.lock().unwrap(), now);
+            (self.client.clone(), endpoint, super::otlp_metrics(&snapshot.histograms, start, now))
+        });
+        #[cfg(feature = "chart")]
+        let chart = self
+            .config
+            .chart_output
+            .clone()
+            .map(|path| (path, super::render_chart(&snapshot.histograms)));
+        let logs =
+            std::mem::take(&mut *self.loglist.lock().unwrap_or_else(PoisonError::into_inner));
+        let syslog = self.config.syslog.clone().map(|target| {
+            let hostname = syslog_hostname();
+            let messages = logs
+                .iter()
+                .map(|log| syslog_message(log, self.config.syslog_facility, &hostname))
+                .collect::<Vec<_>>();
+            (target, messages)
+        });
+        let statsd = self.config.statsd.clone().map(|target| (target, statsd_lines(&logs)));
+        let loglist_file = self.config.loglist_file.clone();
+        let exports = tokio::spawn(async move {
+            if let Some(path) = loglist_file {
+                let lines: String =
+                    logs.iter().map(|log| log.to_json().to_string() + "\n").collect();
+                if let Err(e) = append_lines(&path, &lines).await {
+                    eprintln!("warning: could not write to {}, {}", path.display(), e);
+                }
+            }
+            if let Some((target, messages)) = syslog {
+                if let Err(e) = send_syslog(&target, &messages).await {
+                    eprintln!("warning: could not send to syslog {}, {}", target, e);
+                }
+            }
+            if let Some((target, lines)) = statsd {
+                if let Err(e) = send_statsd(&target, &lines).await {
+                    eprintln!("warning: could not send to StatsD {}, {}", target, e);
+                }
+            }
+            if let Some((path, line)) = stats_stream {
+                if let Err(e) = append_lines(&path, &line).await {
+                    eprintln!("warning: could not write to {}, {}", path.display(), e);
+                }
+            }
+            // Replaced every interval, so whatever displays it always shows the latest one
+            #[cfg(feature = "chart")]
+            if let Some((path, svg)) = chart {
+                if let Err(e) = tokio::fs::write(&path, svg).await {
+                    eprintln!("warning: could not write to {}, {}", path.display(), e);
+                }
+            }
+            if let Some((client, url, alerts)) = alerts {
+                if let Err(e) = post_alerts(&client, &url, &alerts).await {
+                    eprintln!("warning: could not post alerts, {}", e);
+                }
+            }
+            #[cfg(feature = "otlp")]
+            if let Some((client, endpoint, metrics)) = otlp {
+                if let Err(e) = super::push_otlp(&client, &endpoint, &metrics).await {
+                    eprintln!("warning: could not push OTLP metrics, {}", e);
+                }
+            }
+        });
+
+        // TODO: send the histograms and loglist to a monitoring service
+
+        (snapshot, exports)
+    }
+
+    fn warn_regressions(&self, snapshot: &StatsSnapshot) -> Vec<Alert> {
+        let Some(pct) = self.config.regression_pct else {
+            return Vec::new();
+        };
+
+        let mut alerts = Vec::new();
+        let mut previous = self.previous.lock().unwrap();
+        if let Some(previous) = previous.as_ref() {
+            for regression in snapshot.p95_regressions(previous, pct) {
+                eprintln!("warning: {}", regression);
+                alerts.push(Alert::regression(&regression, pct));
+            }
+        }
+        *previous = Some(snapshot.clone());
+
+        alerts
+    }
+
+    fn warn_success_rates(&self, snapshot: &StatsSnapshot) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for alert in snapshot.success_alerts(&self.config.endpoint_min_success) {
+            eprintln!("warning: {}", alert);
+            warn!(
+                target: "narrow::alert",
+                endpoint = %alert.endpoint,
+                requests = alert.requests,
+                success_ratio = alert.success_ratio,
+                threshold = alert.threshold,
+                "success rate below threshold"
+            );
+            alerts.push(Alert::success_rate(&alert));
+        }
+
+        alerts
+    }
+}
+
+/// Append `lines` to the file at `path`, creating it if needed
+async fn append_lines(path: &Path, lines: &str) -> std::io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(lines.as_bytes()).await?;
+    // Tokio finishes file writes in the background, flushing waits until they are on disk
+    file.flush().await
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Args;
+    use crate::net::client::build_client;
+    use crate::state::Log;
+    use crate::statistics::{Counters, RequesterCounts};
+
+    fn reporter(extra_args: &[&str]) -> Reporter {
+        let mut argv = vec!["test"];
+        argv.extend_from_slice(extra_args);
+
+        let config = Arc::new(Config::from(&Args::parse_from(argv)));
+        let connections = SharedConnections::default();
+        Reporter::new(
+            Arc::new(std::sync::Mutex::new(HashMap::new())),
+            Arc::new(std::sync::Mutex::new(Vec::new())),
+            Arc::new(Counters::default()),
+            Arc::clone(&connections),
+            SharedUpstreams::default(),
+            RequesterCounts::from_config(&config).map(Arc::new),
+            Arc::clone(&config),
+            build_client(&config, connections),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_report_partial_interval() {
+        let reporter = reporter(&[]);
+        reporter
+            .histograms
+            .lock()
+            .unwrap()
+            .entry("/api".to_string())
+            .or_default()
+            .add(Duration::from_millis(5), chrono::Utc::now());
+        reporter.loglist.lock().unwrap().push(Log::default());
+        Counters::incr(&reporter.counters.timeouts);
+
+        let (snapshot, exports) = reporter.report();
+        exports.await.unwrap();
+
+        assert_eq!(snapshot.histograms["/api"].total_requests, 1);
+        assert_eq!(reporter.histograms.lock().unwrap()["/api"].total_requests, 0);
+        assert!(reporter.loglist.lock().unwrap().is_empty());
+        assert!(reporter.counters.take().iter().all(|(_, value)| *value == 0));
+    }
+
+    #[tokio::test]
+    async fn test_report_appends_loglist_file() {
+        let path =
+            std::env::temp_dir().join(format!("narrow-loglist-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let reporter = reporter(&["--loglist-file", path.to_str().unwrap()]);
+
+        for uri in ["/a", "/b"] {
+            let log = Log { req_uri: uri.to_string(), ..Log::default() };
+            reporter.loglist.lock().unwrap().push(log);
+            reporter.report().1.await.unwrap();
+        }
+        reporter.report().1.await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let uris: Vec<_> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["uri"].clone())
+            .collect();
+        assert_eq!(uris, ["/a", "/b"]);
+        assert!(reporter.loglist.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_writes_stats_stream() {
+        let path = std::env::temp_dir().join(format!("narrow-stats-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let reporter = reporter(&["--stats-stream", path.to_str().unwrap()]);
+
+        reporter
+            .histograms
+            .lock()
+            .unwrap()
+            .entry("/api".to_string())
+            .or_default()
+            .add(Duration::from_millis(5), chrono::Utc::now());
+        reporter.report().1.await.unwrap();
+        reporter.report().1.await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> =
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["endpoints"]["/api"]["requests"], 1);
+        assert_eq!(records[0]["endpoints"]["/api"]["percentiles_ms"]["p95"], 10.0);
+        assert!(records[1]["endpoints"].get("/api").is_none());
+        assert!(records[0].get("top_talkers").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_report_top_talkers() {
+        let path =
+            std::env::temp_dir().join(format!("narrow-talkers-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let reporter = reporter(&["--top-talkers", "1", "--stats-stream", path.to_str().unwrap()]);
+
+        let talkers = reporter.talkers.as_ref().unwrap();
+        talkers.add("10.0.0.1");
+        talkers.add("10.0.0.2");
+        talkers.add("10.0.0.2");
+        reporter.report().1.await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let record: serde_json::Value = serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(
+            record["top_talkers"],
+            serde_json::json!({"top": [{"ip": "10.0.0.2", "requests": 2}], "untracked": 0})
+        );
+        assert!(talkers.take().top.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_keeps_previous_interval_for_regressions() {
+        let reporter = reporter(&["--regression-pct", "50"]);
+        reporter
+            .histograms
+            .lock()
+            .unwrap()
+            .entry("/api".to_string())
+            .or_default()
+            .add(Duration::from_millis(5), chrono::Utc::now());
+
+        reporter.report();
+        let previous = reporter.previous.lock().unwrap().clone().unwrap();
+        assert_eq!(previous.histograms["/api"].total_requests, 1);
+
+        reporter.report();
+        let previous = reporter.previous.lock().unwrap().clone().unwrap();
+        assert!(!previous.histograms.contains_key("/api"));
+    }
+
+    #[tokio::test]
+    async fn test_report_keeps_no_previous_interval_by_default() {
+        let reporter = reporter(&[]);
+        reporter.report();
+        assert!(reporter.previous.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_report_smooths_p95_across_intervals() {
+        assert!(reporter(&[]).ewma.is_none());
+        let reporter = reporter(&["--ewma-alpha", "0.5"]);
+        let record = |ms: u64| {
+            reporter
+                .histograms
+                .lock()
+                .unwrap()
+                .entry("/api".to_string())
+                .or_default()
+                .add(Duration::from_millis(ms), chrono::Utc::now());
+        };
+
+        record(5);
+        assert_eq!(reporter.report().0.histograms["/api"].ewma_p95_ms, Some(10.0));
+        record(300);
+        assert_eq!(reporter.report().0.histograms["/api"].ewma_p95_ms, Some(255.0));
+        assert_eq!(reporter.histograms.lock().unwrap()["/api"].ewma_p95_ms, None);
+    }
+
+    #[cfg(feature = "otlp")]
+    #[tokio::test]
+    async fn test_report_waits_for_otlp_push() {
+        use hyper::StatusCode;
+
+        use crate::net::mock::MockUpstream;
+
+        let collector = MockUpstream::start(StatusCode::OK, "", Duration::from_millis(50)).await;
+        let endpoint = format!("http://{}/v1/metrics", collector.addr);
+        let reporter = reporter(&["--otlp-endpoint", &endpoint]);
+        reporter
+            .histograms
+            .lock()
+            .unwrap()
+            .entry("/api".to_string())
+            .or_default()
+            .add(Duration::from_millis(5), chrono::Utc::now());
+
+        let (_, exports) = reporter.report();
+        exports.await.unwrap();
+
+        let received = collector.requests();
+        assert_eq!(received.len(), 1);
+        assert!(String::from_utf8_lossy(&received[0].body).contains("/api"));
+    }
+
+    #[cfg(feature = "chart")]
+    #[tokio::test]
+    async fn test_report_writes_chart() {
+        let path = std::env::temp_dir().join(format!("narrow-chart-{}.svg", std::process::id()));
+        let reporter = reporter(&["--chart-output", path.to_str().unwrap()]);
+        for endpoint in ["/first", "/second"] {
+            reporter
+                .histograms
+                .lock()
+                .unwrap()
+                .entry(endpoint.to_string())
+                .or_default()
+                .add(Duration::from_millis(5), chrono::Utc::now());
+            let (_, exports) = reporter.report();
+            exports.await.unwrap();
+        }
+
+        // Only the latest interval is kept
+        let svg = std::fs::read_to_string(&path).unwrap();
+        assert!(svg.contains(">/second</text>"));
+        assert!(!svg.contains("/first"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_report_posts_alerts_once() {
+        let webhook =
+            crate::net::mock::MockUpstream::start(hyper::StatusCode::OK, "", Duration::ZERO).await;
+        let url = format!("http://{}/alerts", webhook.addr);
+        let reporter =
+            reporter(&["--endpoint-min-success", "/api=0.99", "--alert-webhook", url.as_str()]);
+
+        for _ in 0..2 {
+            {
+                let mut histograms = reporter.histograms.lock().unwrap();
+                let hist = histograms.entry("/api".to_string()).or_default();
+                hist.add(Duration::from_millis(5), chrono::Utc::now());
+                hist.add_status(503);
+            }
+            reporter.report().1.await.unwrap();
+        }
+
+        let requests = webhook.requests();
+        assert_eq!(requests.len(), 1);
+        let alert = serde_json::from_slice::<serde_json::Value>(&requests[0].body).unwrap();
+        assert_eq!(alert["endpoint"], "/api");
+        assert_eq!(alert["metric"], "success_ratio");
+        assert_eq!(alert["value"], 0.0);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use prettytable::{format, Cell, Row, Table};
+
+use crate::statistics::Histogram;
+
+/// Upper bounds in bytes of every bucket but the open-ended `1MB+` one
+pub const SIZE_BUCKET_BOUNDS: [u64; 4] = [1024, 10 * 1024, 100 * 1024, 1024 * 1024];
+
+/// Distribution of request body sizes
+#[derive(Debug, Default, Clone)]
+pub struct RequestSizeHistogram {
+    pub count_0_1k: u64,
+    pub count_1k_10k: u64,
+    pub count_10k_100k: u64,
+    pub count_100k_1m: u64,
+    pub count_1m_plus: u64,
+    pub total_bytes: u64,
+}
+
+impl RequestSizeHistogram {
+    /// Record a request of `bytes`. Counters saturate at `u64::MAX` instead of wrapping around.
+    pub fn add(&mut self, bytes: u64) {
+        let [to_1k, to_10k, to_100k, to_1m] = SIZE_BUCKET_BOUNDS;
+        let bucket = match bytes {
+            b if b <= to_1k => &mut self.count_0_1k,
+            b if b <= to_10k => &mut self.count_1k_10k,
+            b if b <= to_100k => &mut self.count_10k_100k,
+            b if b <= to_1m => &mut self.count_100k_1m,
+            _ => &mut self.count_1m_plus,
+        };
+        *bucket = bucket.saturating_add(1);
+
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+    }
+
+    /// Add the counts of `other`
+    pub fn merge(&mut self, other: &RequestSizeHistogram) {
+        let counts = [
+            (&mut self.count_0_1k, other.count_0_1k),
+            (&mut self.count_1k_10k, other.count_1k_10k),
+            (&mut self.count_10k_100k, other.count_10k_100k),
+            (&mut self.count_100k_1m, other.count_100k_1m),
+            (&mut self.count_1m_plus, other.count_1m_plus),
+            (&mut self.total_bytes, other.total_bytes),
+        ];
+        for (count, other) in counts {
+            *count = count.saturating_add(other);
+        }
+    }
+
+    /// The bucket counts in the order of `SIZE_BUCKET_BOUNDS`, followed by the `1MB+` bucket
+    pub fn buckets(&self) -> [u64; 5] {
+        [
+            self.count_0_1k,
+            self.count_1k_10k,
+            self.count_10k_100k,
+            self.count_100k_1m,
+            self.count_1m_plus,
+        ]
+    }
+}
+
+pub fn add_request_size_row(table: &mut Table, endpoint: &str, sizes: &RequestSizeHistogram) {
+    let mut cells = vec![Cell::new(endpoint)];
+    cells.extend(sizes.buckets().iter().map(|count| Cell::new(&count.to_string())));
+    cells.push(Cell::new(&sizes.total_bytes.to_string()));
+
+    table.add_row(Row::new(cells));
+}
+
+pub fn print_request_sizes(histograms: &HashMap<String, Histogram>) -> String {
+    println!("Request Size Histogram:");
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(Row::new(vec![
+        Cell::new("Endpoint"),
+        Cell::new("0-1KB"),
+        Cell::new("1-10KB"),
+        Cell::new("10-100KB"),
+        Cell::new("100KB-1MB"),
+        Cell::new("1MB+"),
+        Cell::new("Total Bytes"),
+    ]));
+
+    let overall = histograms.get("Overall").map(|hist| hist.request_sizes.clone());
+    add_request_size_row(&mut table, "Overall", &overall.unwrap_or_default());
+
+    for (endpoint, hist) in histograms.iter() {
+        if endpoint != "Overall" && hist.total_requests > 0 {
+            add_request_size_row(&mut table, endpoint, &hist.request_sizes);
+        }
+    }
+
+    table.printstd();
+    println!();
+
+    table.to_string()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_request_size_histogram() {
+        let mut sizes = RequestSizeHistogram::default();
+        for bytes in [0, 1024, 1025, 50_000, 200_000, 5_000_000] {
+            sizes.add(bytes);
+        }
+
+        assert_eq!(sizes.buckets(), [2, 1, 1, 1, 1]);
+        assert_eq!(sizes.total_bytes, 5_252_049);
+
+        sizes.total_bytes = u64::MAX;
+        sizes.add(1);
+        assert_eq!(sizes.total_bytes, u64::MAX);
+    }
+
+    #[test]
+    fn test_print_request_sizes() {
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        histograms.insert("Overall".to_string(), Histogram::default());
+        histograms.insert("/idle".to_string(), Histogram::default());
+
+        let table = print_request_sizes(&histograms);
+
+        let rows = table.lines().filter(|row| !row.contains("-----")).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].contains("Overall"));
+    }
+
+    #[test]
+    fn test_add_request_size_row() {
+        let mut table = Table::new();
+        let mut sizes = RequestSizeHistogram::default();
+        sizes.add(2048);
+
+        add_request_size_row(&mut table, "/upload", &sizes);
+
+        assert_eq!(
+            table.get_row(0).unwrap().into_iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec!["/upload", "0", "1", "0", "0", "0", "2048"]
+        );
+    }
+}
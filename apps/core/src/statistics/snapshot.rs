@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::statistics::{mirror_pairs, Histogram, BUCKET_BOUNDS_MS};
+
+/// The histograms of one interval, taken out of the live map in a single pass under its lock.
+/// Rendering and exporting a snapshot never holds up requests, and no request can slip in
+/// between copying the counts and resetting them.
+#[derive(Debug, Default, Clone)]
+pub struct StatsSnapshot {
+    pub histograms: HashMap<String, Histogram>,
+}
+
+impl StatsSnapshot {
+    /// Take the counts recorded since the last snapshot and reset every histogram for the next
+    /// interval. Endpoints that were already idle for a whole interval are dropped, or with `ttl`
+    /// those whose last request is older than that. "Overall" is always kept.
+    pub fn take(histograms: &mut HashMap<String, Histogram>, ttl: Option<Duration>) -> Self {
+        let cutoff =
+            ttl.map(|ttl| Utc::now() - chrono::Duration::from_std(ttl).unwrap_or_default());
+        let recent = |hist: &Histogram| {
+            cutoff.is_some_and(|cutoff| hist.last_request_time.is_some_and(|t| t >= cutoff))
+        };
+        histograms.retain(|endpoint, hist| {
+            endpoint == "Overall" || hist.total_requests > 0 || recent(hist)
+        });
+
+        // Cloning the whole map copies its table as is, which is much cheaper than collecting the
+        // entries into a new one
+        let taken = histograms.clone();
+        histograms.values_mut().for_each(Histogram::reset);
+        // Their counts are in the snapshot, so expired endpoints can go right away
+        if cutoff.is_some() {
+            histograms.retain(|endpoint, hist| endpoint == "Overall" || recent(hist));
+        }
+
+        StatsSnapshot { histograms: taken }
+    }
+
+    /// The snapshot as one `--stats-stream` record taken at `at`, with the bucket counts, totals
+    /// and the estimates of `percentiles` for every endpoint. An estimate in the open-ended top
+    /// bucket is `null`, as the histogram only knows it is above the last bound. Endpoints tracked
+    /// with `--track-hourly` also get their requests per local hour. With `--mirror-to`, every
+    /// endpoint served by both targets is paired up under `mirror_comparison`.
+    pub fn to_json(&self, at: DateTime<Utc>, percentiles: &[f64]) -> Value {
+        let endpoints = self
+            .histograms
+            .iter()
+            .map(|(endpoint, hist)| (endpoint.clone(), endpoint_json(hist, percentiles)))
+            .collect::<Map<_, _>>();
+
+        let mut record = json!({
+            "timestamp": at.to_rfc3339(),
+            "bucket_bounds_ms": BUCKET_BOUNDS_MS,
+            "endpoints": endpoints,
+        });
+
+        let pairs = mirror_pairs(&self.histograms);
+        if !pairs.is_empty() {
+            record["mirror_comparison"] = pairs
+                .into_iter()
+                .map(|(endpoint, target, mirror)| {
+                    let pair = json!({
+                        "target": endpoint_json(target, percentiles),
+                        "mirror": endpoint_json(mirror, percentiles),
+                    });
+                    (endpoint.to_string(), pair)
+                })
+                .collect::<Map<_, _>>()
+                .into();
+        }
+
+        record
+    }
+
+    /// Read the histograms back from a `to_json` record, as served by the admin API. Only the
+    /// bucket counts, totals and an endpoint's `last_request` come back, the estimates are
+    /// derived from the counts again.
+    pub fn from_json(record: &Value) -> Result<Self, String> {
+        let endpoints = record["endpoints"]
+            .as_object()
+            .ok_or_else(|| "no endpoints in the stats".to_string())?;
+
+        let histograms = endpoints
+            .iter()
+            .map(|(endpoint, entry)| {
+                let count = |value: &Value| {
+                    value.as_u64().ok_or_else(|| format!("invalid counts for {}", endpoint))
+                };
+                let buckets = entry["buckets"]
+                    .as_array()
+                    .filter(|buckets| buckets.len() == 6)
+                    .ok_or_else(|| format!("invalid buckets for {}", endpoint))?
+                    .iter()
+                    .map(count)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let last_request_time = entry["last_request"]
+                    .as_str()
+                    .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                    .map(Into::into);
+
+                let hist = Histogram {
+                    count_0_10: buckets[0],
+                    count_11_100: buckets[1],
+                    count_101_250: buckets[2],
+                    count_251_500: buckets[3],
+                    count_501_1000: buckets[4],
+                    count_1000_plus: buckets[5],
+                    total_requests: count(&entry["requests"])?,
+                    count_5xx: count(&entry["errors_5xx"])?,
+                    last_request_time,
+                    generation: entry["generation"].as_u64().unwrap_or_default(),
+                    ..Default::default()
+                };
+                Ok((endpoint.clone(), hist))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(StatsSnapshot { histograms })
+    }
+
+    /// Endpoints whose estimated p95 latency grew by more than `pct` percent since `previous`.
+    /// Endpoints already in the open-ended top bucket before can't get any worse.
+    pub fn p95_regressions(&self, previous: &StatsSnapshot, pct: f64) -> Vec<Regression> {
+        let mut regressions =
+            self.histograms
+                .iter()
+                .filter_map(|(endpoint, hist)| {
+                    let before = previous.histograms.get(endpoint)?.percentile(95.0)?;
+                    let after = hist.percentile(95.0)?;
+                    (before.is_finite() && after > before * (1.0 + pct / 100.0))
+                        .then(|| Regression { endpoint: endpoint.clone(), before, after })
+                })
+                .collect::<Vec<_>>();
+        regressions.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+
+        regressions
+    }
+
+    /// Endpoints whose share of requests answered without a 5xx fell below their
+    /// `--endpoint-min-success` ratio. Endpoints without requests in the interval are left out.
+    pub fn success_alerts(&self, thresholds: &[(String, f64)]) -> Vec<SuccessAlert> {
+        thresholds
+            .iter()
+            .filter_map(|(endpoint, threshold)| {
+                let hist = self.histograms.get(endpoint).filter(|h| h.total_requests > 0)?;
+                let success_ratio = 1.0 - hist.error_ratio();
+                (success_ratio < *threshold).then(|| SuccessAlert {
+                    endpoint: endpoint.clone(),
+                    requests: hist.total_requests,
+                    success_ratio,
+                    threshold: *threshold,
+                })
+            })
+            .collect()
+    }
+}
+
+fn endpoint_json(hist: &Histogram, percentiles: &[f64]) -> Value {
+    let estimates = percentiles
+        .iter()
+        .map(|p| (format!("p{}", p), json!(hist.percentile(*p))))
+        .collect::<Map<_, _>>();
+    let mut entry = json!({
+        "requests": hist.total_requests,
+        "errors_5xx": hist.count_5xx,
+        "buckets": hist.buckets(),
+        "percentiles_ms": estimates,
+    });
+    if let Some(hourly) = hist.hourly.counts() {
+        entry["hourly"] = json!(hourly);
+    }
+
+    entry
+}
+
+/// An endpoint whose estimated p95 latency in milliseconds got worse between two intervals
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub endpoint: String,
+    pub before: f64,
+    pub after: f64,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms =
+            |p95: f64| if p95.is_finite() { format!("{}ms", p95) } else { "1000ms+".to_string() };
+        write!(
+            f,
+            "p95 latency of {} regressed from {} to {}",
+            self.endpoint,
+            ms(self.before),
+            ms(self.after)
+        )
+    }
+}
+
+/// An endpoint that answered too few of its requests without a 5xx in an interval
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuccessAlert {
+    pub endpoint: String,
+    pub requests: u64,
+    pub success_ratio: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for SuccessAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "success rate of {} dropped to {:.2}% of {} requests, below {}%",
+            self.endpoint,
+            self.success_ratio * 100.0,
+            self.requests,
+            self.threshold * 100.0
+        )
+    }
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let timestamp = Utc::now();
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        histograms.entry("Overall".to_string()).or_default().add(Duration::ZERO, timestamp);
+        histograms.entry("/active".to_string()).or_default().add(Duration::ZERO, timestamp);
+        histograms.insert("/idle".to_string(), Histogram::default());
+
+        let snapshot = StatsSnapshot::take(&mut histograms, None);
+        assert_eq!(snapshot.histograms.len(), 2);
+        assert_eq!(snapshot.histograms["/active"].total_requests, 1);
+        assert_eq!(snapshot.histograms["/active"].generation, 0);
+        assert!(!histograms.contains_key("/idle"));
+        assert_eq!(histograms["/active"].total_requests, 0);
+        assert_eq!(histograms["/active"].last_request_time, Some(timestamp));
+        assert_eq!(histograms["/active"].generation, 1);
+        assert_eq!(histograms["Overall"].generation, 1);
+
+        let snapshot = StatsSnapshot::take(&mut histograms, None);
+        assert!(!snapshot.histograms.contains_key("/active"));
+        assert!(!histograms.contains_key("/active"));
+        assert_eq!(histograms["Overall"].generation, 2);
+    }
+
+    #[test]
+    fn test_take_with_ttl() {
+        let ttl = Some(Duration::from_secs(60));
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        histograms.insert("Overall".to_string(), Histogram::default());
+        let recent = Utc::now() - chrono::Duration::seconds(10);
+        histograms.entry("/recent".to_string()).or_default().add(Duration::ZERO, recent);
+        let expired = Utc::now() - chrono::Duration::seconds(120);
+        histograms.entry("/expired".to_string()).or_default().add(Duration::ZERO, expired);
+
+        // Counted once more before going, even though the last request is past the TTL
+        let snapshot = StatsSnapshot::take(&mut histograms, ttl);
+        assert_eq!(snapshot.histograms["/expired"].total_requests, 1);
+        assert!(!histograms.contains_key("/expired"));
+
+        // Idle for a whole interval, but still within the TTL
+        let snapshot = StatsSnapshot::take(&mut histograms, ttl);
+        assert_eq!(snapshot.histograms["/recent"].total_requests, 0);
+        assert_eq!(histograms["/recent"].generation, 2);
+        assert!(histograms.contains_key("Overall"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut hist = Histogram::default();
+        hist.add(Duration::from_millis(5), Utc::now());
+        hist.add(Duration::from_millis(2000), Utc::now());
+        hist.add_status(502);
+        let snapshot = StatsSnapshot { histograms: HashMap::from([("/api".to_string(), hist)]) };
+
+        let at = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().into();
+        assert_eq!(
+            snapshot.to_json(at, &[50.0, 99.9]),
+            json!({
+                "timestamp": "2024-05-01T12:00:00+00:00",
+                "bucket_bounds_ms": [10, 100, 250, 500, 1000],
+                "endpoints": {
+                    "/api": {
+                        "requests": 2,
+                        "errors_5xx": 1,
+                        "buckets": [1, 0, 0, 0, 0, 1],
+                        "percentiles_ms": {"p50": 10.0, "p99.9": null},
+                    },
+                },
+            })
+        );
+
+        let back = StatsSnapshot::from_json(&snapshot.to_json(at, &[])).unwrap();
+        let api = &back.histograms["/api"];
+        assert_eq!((api.buckets(), api.total_requests, api.count_5xx), ([1, 0, 0, 0, 0, 1], 2, 1));
+        assert_eq!(api.last_request_time, None);
+        assert!(StatsSnapshot::from_json(&json!({"endpoints": {"/api": {}}})).is_err());
+        assert!(StatsSnapshot::from_json(&json!([])).is_err());
+
+        let mut hist = Histogram::default();
+        hist.hourly.add(Utc::now());
+        let snapshot = StatsSnapshot { histograms: HashMap::from([("/api".to_string(), hist)]) };
+        let hourly = &snapshot.to_json(at, &[])["endpoints"]["/api"]["hourly"];
+        assert_eq!(hourly.as_array().unwrap().len(), 24);
+        assert!(snapshot.to_json(at, &[]).get("mirror_comparison").is_none());
+
+        let mut target = Histogram::default();
+        target.add(Duration::from_millis(5), Utc::now());
+        let mut mirror = Histogram::default();
+        mirror.add(Duration::from_millis(300), Utc::now());
+        let snapshot = StatsSnapshot {
+            histograms: HashMap::from([
+                ("/api".to_string(), target),
+                ("mirror /api".to_string(), mirror),
+            ]),
+        };
+        let comparison = &snapshot.to_json(at, &[95.0])["mirror_comparison"]["/api"];
+        assert_eq!(comparison["target"]["percentiles_ms"]["p95"], 10.0);
+        assert_eq!(comparison["mirror"]["percentiles_ms"]["p95"], 500.0);
+    }
+
+    #[test]
+    fn test_p95_regressions() {
+        let snapshot = |latencies: &[(&str, u64)]| {
+            let mut histograms: HashMap<String, Histogram> = HashMap::new();
+            for (endpoint, ms) in latencies {
+                let hist = histograms.entry(endpoint.to_string()).or_default();
+                hist.add(Duration::from_millis(*ms), Utc::now());
+            }
+            StatsSnapshot { histograms }
+        };
+
+        let previous = snapshot(&[("/a", 50), ("/b", 50), ("/c", 200), ("/d", 2000)]);
+        let current = snapshot(&[("/a", 200), ("/b", 90), ("/c", 1200), ("/d", 2000), ("/e", 900)]);
+
+        let regressions = current.p95_regressions(&previous, 50.0);
+        assert_eq!(
+            regressions,
+            vec![
+                Regression { endpoint: "/a".to_string(), before: 100.0, after: 250.0 },
+                Regression { endpoint: "/c".to_string(), before: 250.0, after: f64::INFINITY },
+            ]
+        );
+        assert_eq!(regressions[1].to_string(), "p95 latency of /c regressed from 250ms to 1000ms+");
+        assert!(current.p95_regressions(&previous, 500.0).iter().all(|r| r.endpoint == "/c"));
+    }
+
+    #[test]
+    fn test_success_alerts() {
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        for (endpoint, status) in [("/checkout", 200), ("/checkout", 502), ("/search", 500)] {
+            let hist = histograms.entry(endpoint.to_string()).or_default();
+            hist.add(Duration::from_millis(5), Utc::now());
+            hist.add_status(status);
+        }
+        histograms.entry("/stock".to_string()).or_default();
+        let snapshot = StatsSnapshot { histograms };
+
+        let thresholds = [
+            ("/checkout".to_string(), 0.99),
+            ("/search".to_string(), 0.0),
+            ("/stock".to_string(), 0.99),
+            ("/unknown".to_string(), 0.99),
+        ];
+        let alerts = snapshot.success_alerts(&thresholds);
+        assert_eq!(
+            alerts,
+            vec![SuccessAlert {
+                endpoint: "/checkout".to_string(),
+                requests: 2,
+                success_ratio: 0.5,
+                threshold: 0.99,
+            }]
+        );
+        assert_eq!(
+            alerts[0].to_string(),
+            "success rate of /checkout dropped to 50.00% of 2 requests, below 99%"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use tokio::net::UdpSocket;
+
+use crate::state::Log;
+
+/// Datagrams are kept below the usual Ethernet MTU so they aren't fragmented on the way
+const MAX_DATAGRAM_BYTES: usize = 1432;
+
+/// StatsD lines for the requests of an interval, a `narrow.request.duration` timing for every
+/// request and a `narrow.request.count` counter per endpoint and status, tagged the DogStatsD way
+pub fn statsd_lines(logs: &[Log]) -> Vec<String> {
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut lines = logs
+        .iter()
+        .map(|log| {
+            let tags = format!("endpoint:{},status:{}", tag_value(&log.endpoint), log.status);
+            let line =
+                format!("narrow.request.duration:{:.3}|ms|#{}", log.micros as f64 / 1000.0, tags);
+            *counts.entry(tags).or_default() += 1;
+            line
+        })
+        .collect::<Vec<_>>();
+    lines.extend(
+        counts
+            .into_iter()
+            .map(|(tags, count)| format!("narrow.request.count:{}|c|#{}", count, tags)),
+    );
+
+    lines
+}
+
+/// Endpoints are request paths, and may contain the characters that separate tags and fields
+fn tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '|' | ',' | '#' | ':' | '\n' | '\r' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Send `lines` to the StatsD server at `target`, as many lines per datagram as fit
+pub async fn send_statsd(target: &str, lines: &[String]) -> io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let addr = tokio::net::lookup_host(target)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+    let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(addr).await?;
+    for datagram in batch(lines) {
+        socket.send(datagram.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Join `lines` with newlines into datagrams of up to `MAX_DATAGRAM_BYTES`, a longer line is
+/// sent on its own
+fn batch(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = Vec::new();
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM_BYTES => {
+                datagram.push('\n');
+                datagram.push_str(line);
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+
+    datagrams
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn log(endpoint: &str, status: u16, micros: u128) -> Log {
+        Log { endpoint: endpoint.to_string(), status, micros, ..Log::default() }
+    }
+
+    #[test]
+    fn test_statsd_lines() {
+        let logs = [log("/checkout", 200, 1500), log("/a|b,c", 502, 20), log("/checkout", 200, 3)];
+        assert_eq!(
+            statsd_lines(&logs),
+            vec![
+                "narrow.request.duration:1.500|ms|#endpoint:/checkout,status:200",
+                "narrow.request.duration:0.020|ms|#endpoint:/a_b_c,status:502",
+                "narrow.request.duration:0.003|ms|#endpoint:/checkout,status:200",
+                "narrow.request.count:1|c|#endpoint:/a_b_c,status:502",
+                "narrow.request.count:2|c|#endpoint:/checkout,status:200",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch() {
+        let lines = vec!["a".repeat(700), "b".repeat(700), "c".repeat(2000), "d".to_string()];
+        let datagrams = batch(&lines);
+        assert_eq!(datagrams.len(), 3);
+        assert_eq!(datagrams[0], format!("{}\n{}", lines[0], lines[1]));
+        assert_eq!(datagrams[1], lines[2]);
+        assert_eq!(datagrams[2], "d");
+    }
+
+    #[tokio::test]
+    async fn test_send_statsd() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = server.local_addr().unwrap().to_string();
+        let lines = statsd_lines(&[log("/", 200, 1000)]);
+        send_statsd(&target, &lines).await.unwrap();
+
+        let mut buf = vec![0; 2048];
+        let len = server.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            "narrow.request.duration:1.000|ms|#endpoint:/,status:200\n\
+             narrow.request.count:1|c|#endpoint:/,status:200"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+
+use chrono::SecondsFormat;
+use tokio::net::UdpSocket;
+
+use crate::config::SyslogFacility;
+use crate::state::Log;
+
+/// The `informational` severity every access log is sent with
+const SEVERITY_INFO: u8 = 6;
+
+/// The socket of the local syslog daemon
+#[cfg(unix)]
+const LOCAL_SOCKET: &str = "/dev/log";
+
+/// An access log as an RFC 5424 message, with the same JSON as `--loglist-file` as its message
+pub fn syslog_message(log: &Log, facility: SyslogFacility, hostname: &str) -> String {
+    format!(
+        "<{}>1 {} {} narrow {} access - {}",
+        facility as u8 * 8 + SEVERITY_INFO,
+        log.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+        hostname,
+        std::process::id(),
+        log.to_json()
+    )
+}
+
+/// This machine's name for the HOSTNAME field, or the nil value `-` if it can't be found out
+pub fn syslog_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && name.is_ascii() && !name.contains(' '))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Send every message as its own datagram to `target`, the local syslog daemon for `local` or a
+/// remote one listening on UDP at `host:port`
+pub async fn send_syslog(target: &str, messages: &[String]) -> io::Result<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    if target == "local" {
+        return send_local(messages).await;
+    }
+
+    let addr = tokio::net::lookup_host(target)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+    let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(addr).await?;
+    for message in messages {
+        socket.send(message.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn send_local(messages: &[String]) -> io::Result<()> {
+    let socket = tokio::net::UnixDatagram::unbound()?;
+    for message in messages {
+        socket.send_to(message.as_bytes(), LOCAL_SOCKET).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn send_local(_messages: &[String]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "local syslog is only supported on unix"))
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use chrono::{TimeZone, Utc};
+    use hyper::Method;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_syslog() {
+        let log = Log {
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            req_method: Method::GET,
+            req_uri: "/items".to_string(),
+            requester_ip: "10.0.0.1".to_string(),
+            upstream: "localhost:3000".to_string(),
+            redirects: 0,
+            micros: 1500,
+            endpoint: "/items".to_string(),
+            status: 200,
+        };
+        let message = syslog_message(&log, SyslogFacility::Local0, "web-1");
+        assert_eq!(
+            message,
+            format!(
+                "<134>1 2024-05-01T12:00:00.000000Z web-1 narrow {} access - {}",
+                std::process::id(),
+                log.to_json()
+            )
+        );
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = server.local_addr().unwrap().to_string();
+        send_syslog(&target, &[message.clone(), "second".to_string()]).await.unwrap();
+
+        let mut buf = vec![0; 2048];
+        let len = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], message.as_bytes());
+        let len = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"second");
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::state::Config;
+
+/// Requests per requester this interval, by their IP or its `--anonymize-ips` label. At most
+/// `capacity` requesters are tracked, requests from any further requester are only counted as
+/// untracked until the next interval starts.
+#[derive(Debug)]
+pub struct RequesterCounts {
+    capacity: usize,
+    shown: usize,
+    counts: Mutex<(HashMap<String, u64>, u64)>,
+}
+
+/// The addresses that sent the most requests in an interval
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TopTalkers {
+    pub top: Vec<(String, u64)>,
+
+    /// Requests from addresses that didn't fit in `--max-tracked-ips`
+    pub untracked: u64,
+}
+
+impl RequesterCounts {
+    /// Track up to `capacity` addresses and report the `shown` busiest of them
+    pub fn new(capacity: usize, shown: usize) -> Self {
+        RequesterCounts { capacity, shown, counts: Mutex::new((HashMap::new(), 0)) }
+    }
+
+    /// The counts for `--top-talkers`, if it is enabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.top_talkers.map(|shown| RequesterCounts::new(config.max_tracked_ips, shown))
+    }
+
+    pub fn add(&self, requester: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        let (tracked, untracked) = &mut *counts;
+        let full = tracked.len() >= self.capacity;
+        match tracked.get_mut(requester) {
+            Some(count) => *count = count.saturating_add(1),
+            None if !full => {
+                tracked.insert(requester.to_string(), 1);
+            }
+            None => *untracked = untracked.saturating_add(1),
+        }
+    }
+
+    /// The busiest addresses since the last call, busiest first, starting over for the next
+    /// interval
+    pub fn take(&self) -> TopTalkers {
+        let (tracked, untracked) = std::mem::take(&mut *self.counts.lock().unwrap());
+
+        let mut top = tracked.into_iter().collect::<Vec<_>>();
+        top.sort_by(|(a_requester, a), (b_requester, b)| {
+            b.cmp(a).then(a_requester.cmp(b_requester))
+        });
+        top.truncate(self.shown);
+
+        TopTalkers { top, untracked }
+    }
+}
+
+impl TopTalkers {
+    /// The interval's top talkers as they appear in a `--stats-stream` record
+    pub fn to_json(&self) -> Value {
+        let top = self
+            .top
+            .iter()
+            .map(|(requester, requests)| json!({"ip": requester, "requests": requests}))
+            .collect::<Vec<_>>();
+
+        json!({"top": top, "untracked": self.untracked})
+    }
+}
+
+pub fn print_top_talkers(talkers: &TopTalkers) -> String {
+    if talkers.top.is_empty() {
+        return String::new();
+    }
+
+    let mut line = talkers
+        .top
+        .iter()
+        .map(|(requester, requests)| format!("{}: {}", requester, requests))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if talkers.untracked > 0 {
+        line.push_str(&format!(" ({} requests from untracked addresses)", talkers.untracked));
+    }
+
+    println!("Top talkers - {}\n", line);
+
+    line
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_requester_counts() {
+        let counts = RequesterCounts::new(2, 1);
+        for requester in ["10.0.0.1", "10.0.0.2", "10.0.0.2", "10.0.0.3", "10.0.0.3", "10.0.0.3"] {
+            counts.add(requester);
+        }
+
+        let talkers = counts.take();
+        assert_eq!(talkers, TopTalkers { top: vec![("10.0.0.2".to_string(), 2)], untracked: 3 });
+        assert_eq!(
+            print_top_talkers(&talkers),
+            "10.0.0.2: 2 (3 requests from untracked addresses)"
+        );
+        assert_eq!(
+            talkers.to_json(),
+            json!({"top": [{"ip": "10.0.0.2", "requests": 2}], "untracked": 3})
+        );
+
+        // The next interval has room for new addresses again
+        counts.add("10.0.0.3");
+        assert_eq!(
+            counts.take(),
+            TopTalkers { top: vec![("10.0.0.3".to_string(), 1)], untracked: 0 }
+        );
+        assert_eq!(print_top_talkers(&counts.take()), "");
+    }
+}
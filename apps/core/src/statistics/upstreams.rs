@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use prettytable::{format, Cell, Row, Table};
+use serde_json::{json, Map, Value};
+
+/// What happened to the requests forwarded to one upstream `host:port`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct UpstreamStats {
+    pub requests: u64,
+
+    /// Requests that failed or were answered with a 5xx, timeouts included
+    pub errors: u64,
+
+    /// Requests the upstream did not answer before their timeout
+    pub timeouts: u64,
+}
+
+/// Requests and errors per upstream, so a reconfigured backend can be told apart from the old one
+#[derive(Debug, Default)]
+pub struct UpstreamCounts {
+    hosts: Mutex<BTreeMap<String, UpstreamStats>>,
+}
+
+impl UpstreamCounts {
+    /// Record a request forwarded to `upstream` that ended with `status`, the proxy's own 502 or
+    /// 504 when it failed
+    pub fn add(&self, upstream: &str, status: u16, timed_out: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let stats = hosts.entry(upstream.to_string()).or_default();
+        stats.requests = stats.requests.saturating_add(1);
+        if (500..600).contains(&status) || timed_out {
+            stats.errors = stats.errors.saturating_add(1);
+        }
+        if timed_out {
+            stats.timeouts = stats.timeouts.saturating_add(1);
+        }
+    }
+
+    /// The stats of every upstream so far this interval
+    pub fn current(&self) -> Vec<(String, UpstreamStats)> {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.iter().map(|(host, stats)| (host.clone(), *stats)).collect()
+    }
+
+    /// The stats of every upstream this interval, starting the next one from scratch
+    pub fn take(&self) -> Vec<(String, UpstreamStats)> {
+        let hosts = std::mem::take(&mut *self.hosts.lock().unwrap());
+        hosts.into_iter().collect()
+    }
+}
+
+/// The stats as an object keyed by upstream, for the admin API
+pub fn upstreams_json(upstreams: &[(String, UpstreamStats)]) -> Value {
+    upstreams
+        .iter()
+        .map(|(host, stats)| {
+            let entry = json!({
+                "requests": stats.requests,
+                "errors": stats.errors,
+                "timeouts": stats.timeouts,
+            });
+            (host.clone(), entry)
+        })
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+pub fn print_upstreams(upstreams: &[(String, UpstreamStats)]) -> String {
+    if upstreams.is_empty() {
+        return String::new();
+    }
+
+    println!("Requests per Upstream:");
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(Row::new(vec![
+        Cell::new("Upstream"),
+        Cell::new("Requests"),
+        Cell::new("Errors"),
+        Cell::new("Timeouts"),
+    ]));
+    for (host, stats) in upstreams {
+        table.add_row(Row::new(vec![
+            Cell::new(host),
+            Cell::new(&stats.requests.to_string()),
+            Cell::new(&stats.errors.to_string()),
+            Cell::new(&stats.timeouts.to_string()),
+        ]));
+    }
+
+    table.printstd();
+    println!();
+
+    table.to_string()
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_upstream_counts() {
+        let upstreams = UpstreamCounts::default();
+        upstreams.add("127.0.0.1:3000", 200, false);
+        upstreams.add("127.0.0.1:3000", 503, false);
+        upstreams.add("127.0.0.1:3000", 504, true);
+        upstreams.add("10.0.0.1:80", 404, false);
+
+        let stats = |requests, errors, timeouts| UpstreamStats { requests, errors, timeouts };
+        let expected = vec![
+            ("10.0.0.1:80".to_string(), stats(1, 0, 0)),
+            ("127.0.0.1:3000".to_string(), stats(3, 2, 1)),
+        ];
+        assert_eq!(upstreams.current(), expected);
+        assert_eq!(upstreams_json(&expected)["127.0.0.1:3000"]["errors"], 2);
+
+        let taken = upstreams.take();
+        assert_eq!(taken, expected);
+        assert!(upstreams.take().is_empty());
+
+        let table = print_upstreams(&taken);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0].split_whitespace().collect::<Vec<_>>(),
+            ["Upstream", "|", "Requests", "|", "Errors", "|", "Timeouts"]
+        );
+        assert_eq!(
+            lines[3].split_whitespace().filter(|c| *c != "|").collect::<Vec<_>>(),
+            ["127.0.0.1:3000", "3", "2", "1"]
+        );
+        assert_eq!(print_upstreams(&[]), "");
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::statistics::Histogram;
+
+/// Time requests spent waiting for a `--max-upstream-requests` slot
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueueWaits {
+    /// Requests that had to wait, those that got a slot straight away aren't counted
+    pub queued: u64,
+    pub total_micros: u64,
+}
+
+impl QueueWaits {
+    /// Record a request that waited `waited` for a slot
+    pub fn add(&mut self, waited: Duration) {
+        self.queued = self.queued.saturating_add(1);
+        self.total_micros = self.total_micros.saturating_add(waited.as_micros() as u64);
+    }
+
+    /// Add the waits of `other`
+    pub fn merge(&mut self, other: &QueueWaits) {
+        self.queued = self.queued.saturating_add(other.queued);
+        self.total_micros = self.total_micros.saturating_add(other.total_micros);
+    }
+
+    /// The average wait of the requests that were queued
+    pub fn average(&self) -> Option<Duration> {
+        (self.queued > 0).then(|| Duration::from_micros(self.total_micros / self.queued))
+    }
+}
+
+pub fn print_queue_waits(histograms: &HashMap<String, Histogram>) -> String {
+    let mut waits = histograms
+        .iter()
+        .filter_map(|(endpoint, hist)| {
+            Some((endpoint, hist.queue_waits.queued, hist.queue_waits.average()?))
+        })
+        .collect::<Vec<_>>();
+    if waits.is_empty() {
+        return String::new();
+    }
+    // Overall first, then the endpoints that waited longest
+    waits.sort_by_key(|(endpoint, _, average)| {
+        (endpoint.as_str() != "Overall", std::cmp::Reverse(*average))
+    });
+
+    let line = waits
+        .iter()
+        .map(|(endpoint, queued, average)| {
+            format!("{}: {} queued, {:?} average", endpoint, queued, average)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!("Queue waits - {}\n", line);
+
+    line
+}
+
+// unit test
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_queue_waits() {
+        let mut waits = QueueWaits::default();
+        assert_eq!(waits.average(), None);
+        waits.add(Duration::from_millis(10));
+        waits.add(Duration::from_millis(30));
+        assert_eq!(waits.average(), Some(Duration::from_millis(20)));
+
+        let mut histograms: HashMap<String, Histogram> = HashMap::new();
+        assert_eq!(print_queue_waits(&histograms), "");
+
+        histograms
+            .entry("/fast".to_string())
+            .or_default()
+            .queue_waits
+            .add(Duration::from_millis(1));
+        histograms.entry("/slow".to_string()).or_default().queue_waits = waits.clone();
+        let overall = &mut histograms.entry("Overall".to_string()).or_default().queue_waits;
+        overall.merge(&waits);
+        overall.add(Duration::from_millis(1));
+        histograms.insert("/idle".to_string(), Histogram::default());
+
+        assert_eq!(
+            print_queue_waits(&histograms),
+            "Overall: 3 queued, 13.666ms average, /slow: 2 queued, 20ms average, \
+             /fast: 1 queued, 1ms average"
+        );
+    }
+}